@@ -1,9 +1,93 @@
+// src/agent.rs
+
+//! Typed QEMU guest agent RPCs layered on [`virsh::virsh_qemu_agent`]:
+//! OS identification, capabilities (`guest-info`), logged-in users,
+//! passwords, network interfaces, the guest clock and filesystem freezing.
+
+use std::collections::HashMap;
+
 use serde_json::Value;
-use std::io;
+use crate::error::{Error, Result};
 use crate::virsh;
 
+/// A user logged into the guest, from guest-get-users.
+#[derive(Debug, Clone)]
+pub struct GuestUser {
+    pub user: String,
+    /// Windows domain, when the guest reports one.
+    pub domain: Option<String>,
+    /// Login time in seconds since the Unix epoch.
+    pub login_time: f64,
+}
+
+/// A network interface as the guest sees it, from guest-network-get-interfaces.
+#[derive(Debug, Clone)]
+pub struct GuestInterface {
+    pub name: String,
+    /// Lower-case `aa:bb:cc:dd:ee:ff`; absent for some virtual interfaces.
+    pub mac: Option<String>,
+    /// Addresses as `ip/prefix`.
+    pub ipv4: Vec<String>,
+    pub ipv6: Vec<String>,
+    /// Byte counters; older agents do not report statistics.
+    pub rx_bytes: Option<u64>,
+    pub tx_bytes: Option<u64>,
+}
+
+/// Agent commands used to read a guest file.
+pub const FILE_READ: &[&str] = &["guest-file-open", "guest-file-read", "guest-file-close"];
+/// Agent commands used to write a guest file.
+pub const FILE_WRITE: &[&str] = &["guest-file-open", "guest-file-write", "guest-file-close"];
+/// Agent commands used to run a program in the guest.
+pub const EXEC: &[&str] = &["guest-exec", "guest-exec-status"];
+
+/// Agent command that lists the guest's network interfaces.
+pub const NETWORK: &[&str] = &["guest-network-get-interfaces"];
+
+/// Agent command that reads the guest clock.
+pub const CLOCK_READ: &[&str] = &["guest-get-time"];
+/// Agent command that sets the guest clock.
+pub const CLOCK_SET: &[&str] = &["guest-set-time"];
+
+/// Agent commands used to quiesce guest filesystems for a backup.
+pub const FSFREEZE: &[&str] = &["guest-fsfreeze-freeze", "guest-fsfreeze-thaw", "guest-fsfreeze-status"];
+
+/// What guest-info reports: the agent version and its commands.
+#[derive(Debug, Clone)]
+pub struct AgentInfo {
+    pub version: String,
+    /// Every command the agent knows, mapped to whether its policy allows it.
+    pub commands: HashMap<String, bool>,
+}
+
+impl AgentInfo {
+    /// Ok when every one of `commands` is known to the agent and enabled.
+    pub fn check(&self, vm: &str, commands: &[&str]) -> Result<()> {
+        for &command in commands {
+            match self.commands.get(command) {
+                Some(true) => {}
+                Some(false) => {
+                    return Err(Error::AgentCommandDisabled { vm: vm.to_string(), command: command.to_string() });
+                }
+                None => {
+                    return Err(Error::AgentCommandUnsupported {
+                        vm: vm.to_string(),
+                        command: command.to_string(),
+                        version: self.version.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn supports(&self, command: &str) -> bool {
+        self.commands.get(command).copied().unwrap_or(false)
+    }
+}
+
 /// Try guest-get-osinfo and return a friendly OS string if present.
-pub fn try_guest_get_osinfo(vm: &str, timeout_secs: u64) -> io::Result<Option<String>> {
+pub fn try_guest_get_osinfo(vm: &str, timeout_secs: u64) -> Result<Option<String>> {
     let payload = r#"{"execute":"guest-get-osinfo"}"#;
     let json: Value = virsh::virsh_qemu_agent(vm, payload, timeout_secs)?;
     if let Some(ret) = json.get("return") {
@@ -24,7 +108,7 @@ pub fn try_guest_get_osinfo(vm: &str, timeout_secs: u64) -> io::Result<Option<St
 }
 
 /// Try guest-get-os (older RPC) — similar parsing strategy.
-pub fn try_guest_get_os(vm: &str, timeout_secs: u64) -> io::Result<Option<String>> {
+pub fn try_guest_get_os(vm: &str, timeout_secs: u64) -> Result<Option<String>> {
     let payload = r#"{"execute":"guest-get-os"}"#;
     let json: Value = virsh::virsh_qemu_agent(vm, payload, timeout_secs)?;
     if let Some(ret) = json.get("return") {
@@ -38,3 +122,167 @@ pub fn try_guest_get_os(vm: &str, timeout_secs: u64) -> io::Result<Option<String
     }
     Ok(None)
 }
+
+/// Ask guest-info for the agent version and which commands it supports.
+pub fn guest_info(vm: &str, timeout_secs: u64) -> Result<AgentInfo> {
+    let payload = r#"{"execute":"guest-info"}"#;
+    let json: Value = virsh::virsh_qemu_agent(vm, payload, timeout_secs)?;
+    let ret = json
+        .get("return")
+        .ok_or_else(|| Error::Protocol("No return in guest-info".to_string()))?;
+    let version = ret
+        .get("version")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| Error::Protocol("No version in guest-info".to_string()))?
+        .to_string();
+    let commands = ret
+        .get("supported_commands")
+        .and_then(|v| v.as_array())
+        .map(|cmds| {
+            cmds.iter()
+                .filter_map(|c| {
+                    let name = c.get("name")?.as_str()?.to_string();
+                    Some((name, c.get("enabled").and_then(|v| v.as_bool()).unwrap_or(true)))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(AgentInfo { version, commands })
+}
+
+/// Send `guest-ping`; Ok(()) means the agent is installed and answering.
+pub fn guest_ping(vm: &str, timeout_secs: u64) -> Result<()> {
+    let payload = r#"{"execute":"guest-ping"}"#;
+    virsh::virsh_qemu_agent(vm, payload, timeout_secs)?;
+    Ok(())
+}
+
+/// List logged-in users with guest-get-users.
+pub fn guest_get_users(vm: &str, timeout_secs: u64) -> Result<Vec<GuestUser>> {
+    let payload = r#"{"execute":"guest-get-users"}"#;
+    let json: Value = virsh::virsh_qemu_agent(vm, payload, timeout_secs)?;
+    let users = json
+        .get("return")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| Error::Protocol("No user list in guest-get-users".to_string()))?;
+    Ok(users
+        .iter()
+        .filter_map(|u| {
+            Some(GuestUser {
+                user: u.get("user")?.as_str()?.to_string(),
+                domain: u.get("domain").and_then(|v| v.as_str()).map(str::to_string),
+                login_time: u.get("login-time").and_then(|v| v.as_f64()).unwrap_or(0.0),
+            })
+        })
+        .collect())
+}
+
+/// The guest's network interfaces with guest-network-get-interfaces.
+pub fn guest_network_get_interfaces(vm: &str, timeout_secs: u64) -> Result<Vec<GuestInterface>> {
+    let payload = r#"{"execute":"guest-network-get-interfaces"}"#;
+    let json: Value = virsh::virsh_qemu_agent(vm, payload, timeout_secs)?;
+    let list = json
+        .get("return")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| Error::Protocol("No interface list in guest-network-get-interfaces".to_string()))?;
+    Ok(list
+        .iter()
+        .filter_map(|i| {
+            let mut iface = GuestInterface {
+                name: i.get("name")?.as_str()?.to_string(),
+                mac: i.get("hardware-address").and_then(|v| v.as_str()).map(str::to_lowercase),
+                ipv4: Vec::new(),
+                ipv6: Vec::new(),
+                rx_bytes: i.pointer("/statistics/rx-bytes").and_then(|v| v.as_u64()),
+                tx_bytes: i.pointer("/statistics/tx-bytes").and_then(|v| v.as_u64()),
+            };
+            for a in i.get("ip-addresses").and_then(|v| v.as_array()).into_iter().flatten() {
+                let (Some(ip), Some(prefix)) = (a.get("ip-address").and_then(|v| v.as_str()), a.get("prefix")) else {
+                    continue;
+                };
+                let addr = format!("{}/{}", ip, prefix);
+                match a.get("ip-address-type").and_then(|v| v.as_str()) {
+                    Some("ipv6") => iface.ipv6.push(addr),
+                    _ => iface.ipv4.push(addr),
+                }
+            }
+            Some(iface)
+        })
+        .collect())
+}
+
+/// Set a local account's password with guest-set-user-password. The
+/// password is sent base64-encoded in clear (not pre-hashed) form, so it
+/// is passed to virsh on stdin rather than on its command line, and left
+/// out of the payload printed in dry-run mode.
+pub fn guest_set_user_password(vm: &str, user: &str, password: &str, timeout_secs: u64) -> Result<()> {
+    use base64::Engine;
+    let payload = |password: &str| {
+        serde_json::json!({
+            "execute": "guest-set-user-password",
+            "arguments": {"username": user, "password": password, "crypted": false}
+        })
+        .to_string()
+    };
+    if virsh::agent_dry_run(vm, &payload("<redacted>"), timeout_secs)? {
+        return Ok(());
+    }
+    virsh::virsh_qemu_agent_secret(vm, &payload(&base64::engine::general_purpose::STANDARD.encode(password)), timeout_secs)?;
+    Ok(())
+}
+
+/// Read the guest clock with guest-get-time, in nanoseconds since the epoch.
+pub fn guest_get_time(vm: &str, timeout_secs: u64) -> Result<i64> {
+    let payload = r#"{"execute":"guest-get-time"}"#;
+    let json: Value = virsh::virsh_qemu_agent(vm, payload, timeout_secs)?;
+    json.get("return")
+        .and_then(|v| v.as_i64())
+        .ok_or_else(|| Error::Protocol("No time in guest-get-time".to_string()))
+}
+
+/// Set the guest clock to `time_ns` (nanoseconds since the epoch) with
+/// guest-set-time. The agent also writes it to the guest's RTC.
+pub fn guest_set_time(vm: &str, time_ns: i64, timeout_secs: u64) -> Result<()> {
+    let payload = serde_json::json!({"execute": "guest-set-time", "arguments": {"time": time_ns}}).to_string();
+    if virsh::agent_dry_run(vm, &payload, timeout_secs)? {
+        return Ok(());
+    }
+    virsh::virsh_qemu_agent(vm, &payload, timeout_secs)?;
+    Ok(())
+}
+
+/// Freeze every guest filesystem with guest-fsfreeze-freeze and return how
+/// many were frozen. Must be paired with `guest_fsfreeze_thaw`.
+pub fn guest_fsfreeze_freeze(vm: &str, timeout_secs: u64) -> Result<u64> {
+    let payload = r#"{"execute":"guest-fsfreeze-freeze"}"#;
+    if virsh::agent_dry_run(vm, payload, timeout_secs)? {
+        return Ok(0);
+    }
+    let json: Value = virsh::virsh_qemu_agent(vm, payload, timeout_secs)?;
+    json.get("return")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| Error::Protocol("No count in guest-fsfreeze-freeze".to_string()))
+}
+
+/// Thaw the guest filesystems with guest-fsfreeze-thaw and return how many
+/// were thawed.
+pub fn guest_fsfreeze_thaw(vm: &str, timeout_secs: u64) -> Result<u64> {
+    let payload = r#"{"execute":"guest-fsfreeze-thaw"}"#;
+    if virsh::agent_dry_run(vm, payload, timeout_secs)? {
+        return Ok(0);
+    }
+    let json: Value = virsh::virsh_qemu_agent(vm, payload, timeout_secs)?;
+    json.get("return")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| Error::Protocol("No count in guest-fsfreeze-thaw".to_string()))
+}
+
+/// "thawed" or "frozen", from guest-fsfreeze-status.
+pub fn guest_fsfreeze_status(vm: &str, timeout_secs: u64) -> Result<String> {
+    let payload = r#"{"execute":"guest-fsfreeze-status"}"#;
+    let json: Value = virsh::virsh_qemu_agent(vm, payload, timeout_secs)?;
+    json.get("return")
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| Error::Protocol("No status in guest-fsfreeze-status".to_string()))
+}
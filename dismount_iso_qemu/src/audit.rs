@@ -0,0 +1,138 @@
+// src/audit.rs
+
+//! Append-only JSONL audit log of changes made inside guests.
+//!
+//! Every guest modification goes through `run`, which opens the log before
+//! touching the guest (so an unwritable log blocks the change) and appends
+//! one record with the outcome afterwards.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::dryrun;
+use crate::error;
+use crate::utils::{format_rfc3339, sha256_hex, state_dir, unix_now};
+use crate::virsh;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// RFC 3339 UTC timestamp.
+    pub ts: String,
+    /// Local user running the tool.
+    pub user: String,
+    /// Original user when run through sudo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sudo_user: Option<String>,
+    pub uri: Option<String>,
+    pub vm: String,
+    /// What was done, e.g. "write-file".
+    pub action: String,
+    /// Remote path (or media/command) the action touched.
+    pub path: Option<String>,
+    pub sha256_before: Option<String>,
+    pub sha256_after: Option<String>,
+    /// `"ok"` or `"error: <message>"`.
+    pub outcome: String,
+}
+
+impl AuditRecord {
+    pub fn new(vm: &str, action: &str, path: Option<&str>) -> Self {
+        let env = |k: &str| std::env::var(k).ok().filter(|v| !v.is_empty());
+        Self {
+            ts: format_rfc3339(unix_now()),
+            user: env("USER").or_else(|| env("LOGNAME")).unwrap_or_else(|| "unknown".to_string()),
+            sudo_user: env("SUDO_USER"),
+            uri: virsh::connection_uri(vm).ok(),
+            vm: vm.to_string(),
+            action: action.to_string(),
+            path: path.map(str::to_string),
+            sha256_before: None,
+            sha256_after: None,
+            outcome: String::new(),
+        }
+    }
+}
+
+/// Location of the audit log: `[audit] path` or the state directory.
+pub fn log_path() -> Option<PathBuf> {
+    config::get()
+        .audit
+        .path
+        .clone()
+        .or_else(|| state_dir().map(|d| d.join("audit.jsonl")))
+}
+
+fn open_log() -> Result<(PathBuf, File)> {
+    let path = log_path().ok_or_else(|| anyhow!("cannot locate audit log: HOME is not set"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("opening audit log {}", path.display()))?;
+    Ok((path, file))
+}
+
+/// Run a guest-modifying operation and record it. The log is opened first,
+/// so nothing is changed if the record could not be written. On failure
+/// `sha256_after` is cleared because the guest state is unknown. Dry runs
+/// change nothing and are not recorded.
+pub fn run<T>(mut rec: AuditRecord, op: impl FnOnce() -> error::Result<T>) -> Result<T> {
+    if dryrun::enabled() {
+        return Ok(op()?);
+    }
+    let (path, mut log) = open_log()?;
+    let result = op();
+    match &result {
+        Ok(_) => rec.outcome = "ok".to_string(),
+        Err(e) => {
+            rec.outcome = format!("error: {}", e);
+            rec.sha256_after = None;
+        }
+    }
+    let line = serde_json::to_string(&rec)?;
+    writeln!(log, "{}", line).with_context(|| format!("writing audit log {}", path.display()))?;
+    Ok(result?)
+}
+
+/// Write `content` to `path` in `vm` via the guest agent, audited.
+/// `before` is the file's previous content when the caller has it.
+pub fn write_file(vm: &str, path: &str, before: Option<&[u8]>, content: &[u8]) -> Result<()> {
+    let mut rec = AuditRecord::new(vm, "write-file", Some(path));
+    rec.sha256_before = before.map(sha256_hex);
+    rec.sha256_after = Some(sha256_hex(content));
+    run(rec, || virsh::ga_write_file(vm, path, content))
+}
+
+/// Read every record from the audit log, oldest first. Lines that do not
+/// parse are skipped and counted in the second return value.
+pub fn read_log() -> Result<(Vec<AuditRecord>, usize)> {
+    let Some(path) = log_path() else {
+        return Ok((Vec::new(), 0));
+    };
+    let file = match File::open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(e).with_context(|| format!("opening audit log {}", path.display())),
+    };
+    let mut records = Vec::new();
+    let mut bad = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(rec) => records.push(rec),
+            Err(_) => bad += 1,
+        }
+    }
+    Ok((records, bad))
+}
@@ -0,0 +1,391 @@
+// src/backup.rs
+
+//! Disk backups and restores.
+//!
+//! A running VM is backed up by redirecting its writes into temporary qcow2
+//! overlays (an external disk-only snapshot without metadata), copying the
+//! now-unchanging original images, and committing the overlays back with
+//! `blockcommit --pivot`. When the guest agent allows it the filesystems
+//! are frozen around the snapshot, so the copy is what the guest would
+//! have after a clean sync; otherwise it is crash-consistent. Shut-off VMs
+//! are copied directly.
+//!
+//! Each backup is a directory `<backup dir>/<vm>/<unix-time>/`, or
+//! `<backup dir>/<host>/<vm>/<unix-time>/` for a VM on a `[hosts]` entry,
+//! holding the disk images, the inactive domain XML and `manifest.json`
+//! with SHA-256 checksums. The manifest is written last; directories
+//! without one are unfinished and ignored.
+//!
+//! Disk images are read and written as local files, so only VMs on this
+//! machine can be backed up or restored.
+//!
+//! In dry-run mode the virsh and agent calls are printed by their modules
+//! and the file copies here; nothing is written.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::agent;
+use crate::config;
+use crate::dryrun;
+use crate::utils::{parse_domblklist, sha256_file, sha256_hex, state_dir, unix_now};
+use crate::virsh;
+
+const MANIFEST: &str = "manifest.json";
+const DOMAIN_XML: &str = "domain.xml";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskBackup {
+    pub target: String,
+    /// Image path in the VM definition; restores write back to it.
+    pub source: String,
+    /// File name inside the backup directory.
+    pub file: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub vm: String,
+    /// Seconds since the epoch.
+    pub created: u64,
+    /// Guest filesystems were frozen while the snapshot was taken.
+    pub frozen: bool,
+    pub domain_xml_sha256: String,
+    pub disks: Vec<DiskBackup>,
+}
+
+/// A finished backup on disk.
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub dir: PathBuf,
+    pub manifest: Manifest,
+}
+
+impl Backup {
+    pub fn size(&self) -> u64 {
+        self.manifest.disks.iter().map(|d| d.size).sum()
+    }
+
+    pub fn domain_xml(&self) -> Result<String> {
+        let path = self.dir.join(DOMAIN_XML);
+        std::fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))
+    }
+}
+
+/// Directory holding every VM's backups.
+pub fn root() -> Result<PathBuf> {
+    match &config::get().backup.dir {
+        Some(dir) => Ok(dir.clone()),
+        None => Ok(state_dir()
+            .ok_or_else(|| anyhow!("cannot locate state directory: HOME is not set"))?
+            .join("backups")),
+    }
+}
+
+fn vm_dir(root: &Path, vm: &str) -> PathBuf {
+    match vm.split_once('/') {
+        Some((host, name)) => root.join(host).join(name),
+        None => root.join(vm),
+    }
+}
+
+/// Fail unless `vm` is on this machine: the disk paths of a VM on another
+/// host name files there, not here.
+pub fn require_local(vm: &str) -> Result<()> {
+    if !virsh::is_local(vm)? {
+        bail!("'{}' is on a remote host; its disk images are not local files, so back it up on that host", vm);
+    }
+    Ok(())
+}
+
+/// Finished backups of `vm`, newest first.
+pub fn list(vm: &str) -> Result<Vec<Backup>> {
+    list_in(&vm_dir(&root()?, vm), vm)
+}
+
+/// Finished backups of `vm` in `dir`. Backups of other VMs are skipped:
+/// the directory of `hv1` also holds the per-VM directories of host `hv1`.
+fn list_in(dir: &Path, vm: &str) -> Result<Vec<Backup>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", dir.display())),
+    };
+    let mut backups: Vec<Backup> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let text = std::fs::read_to_string(e.path().join(MANIFEST)).ok()?;
+            let manifest: Manifest = serde_json::from_str(&text).ok()?;
+            (manifest.vm == vm).then(|| Backup { dir: e.path(), manifest })
+        })
+        .collect();
+    backups.sort_by_key(|b| std::cmp::Reverse(b.manifest.created));
+    Ok(backups)
+}
+
+/// Back up every disk of `vm`. With `freeze` the guest filesystems are
+/// frozen for the snapshot and the thaw is checked with
+/// guest-fsfreeze-status; callers only ask for it when the agent allows
+/// the fsfreeze commands.
+pub fn create(vm: &str, freeze: bool) -> Result<Backup> {
+    require_local(vm)?;
+    let live = matches!(virsh::domstate(vm)?.as_str(), "running" | "paused");
+    let xml = virsh::dumpxml(vm, true)?;
+    let devices = parse_domblklist(&virsh::domblklist_raw(vm)?);
+    let disks: Vec<(String, String)> = devices
+        .iter()
+        .filter(|d| d.device == "disk")
+        .map(|d| match (&d.source, d.kind.as_str()) {
+            (Some(source), "file" | "block") => Ok((d.target.clone(), source.clone())),
+            _ => Err(anyhow!("disk {} is a {} disk and cannot be copied", d.target, d.kind)),
+        })
+        .collect::<Result<_>>()?;
+    if disks.is_empty() {
+        bail!("'{}' has no disks to back up", vm);
+    }
+
+    let parent = vm_dir(&root()?, vm);
+    let mut created = unix_now();
+    while parent.join(created.to_string()).exists() {
+        created += 1;
+    }
+    let dir = parent.join(created.to_string());
+    if !dryrun::enabled() {
+        std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+
+    let result = fill(vm, &dir, created, live && freeze, live, &disks, &xml);
+    if result.is_err() && !dryrun::enabled() {
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    result
+}
+
+/// Take the backup of `vm` into the fresh directory `dir`.
+fn fill(vm: &str, dir: &Path, created: u64, freeze: bool, live: bool, disks: &[(String, String)], xml: &str) -> Result<Backup> {
+    let mut frozen = false;
+    let copied = if live {
+        // Overlays sit next to their images, where qemu is allowed to write.
+        let overlays: Vec<(String, String)> = disks
+            .iter()
+            .map(|(target, source)| (target.clone(), format!("{}.backup-{}", source, created)))
+            .collect();
+
+        let timeout = config::get().timeouts.fsfreeze;
+        if freeze {
+            if let Err(e) = agent::guest_fsfreeze_freeze(vm, timeout) {
+                // A freeze that timed out may still have frozen some filesystems.
+                let _ = agent::guest_fsfreeze_thaw(vm, timeout);
+                return Err(anyhow::Error::new(e).context("guest-fsfreeze-freeze failed"));
+            }
+            frozen = true;
+        }
+        let snapshot = virsh::snapshot_overlays(vm, &overlays);
+        // Thaw whatever happened; a guest left frozen hangs on its next write.
+        let thawed = if frozen { thaw(vm, timeout) } else { Ok(()) };
+        if let Err(e) = snapshot {
+            thawed?;
+            return Err(anyhow::Error::new(e).context("disk-only snapshot failed"));
+        }
+
+        let copied = copy_disks(disks, dir);
+        let committed = commit(vm, &overlays);
+        thawed?;
+        committed?;
+        copied
+    } else {
+        copy_disks(disks, dir)
+    };
+    let disk_backups = copied?;
+
+    let manifest = Manifest {
+        vm: vm.to_string(),
+        created,
+        frozen,
+        domain_xml_sha256: sha256_hex(xml.as_bytes()),
+        disks: disk_backups,
+    };
+    if dryrun::enabled() {
+        dryrun::note(&format!("write {} and {} to {}", DOMAIN_XML, MANIFEST, dir.display()));
+    } else {
+        std::fs::write(dir.join(DOMAIN_XML), xml)?;
+        std::fs::write(dir.join(MANIFEST), serde_json::to_string_pretty(&manifest)? + "\n")?;
+    }
+    Ok(Backup { dir: dir.to_path_buf(), manifest })
+}
+
+/// Thaw and confirm the guest reports its filesystems thawed.
+fn thaw(vm: &str, timeout: u64) -> Result<()> {
+    let result = agent::guest_fsfreeze_thaw(vm, timeout)
+        .and_then(|_| agent::guest_fsfreeze_status(vm, timeout));
+    match result {
+        Ok(status) if status == "thawed" => Ok(()),
+        Ok(status) => bail!("guest filesystems in '{}' are still {} after guest-fsfreeze-thaw", vm, status),
+        Err(e) => Err(anyhow::Error::new(e)
+            .context(format!("guest filesystems in '{}' may still be frozen; run guest-fsfreeze-thaw", vm))),
+    }
+}
+
+/// Merge every overlay back and delete it. All disks are tried even if one
+/// fails, since each left behind keeps growing.
+fn commit(vm: &str, overlays: &[(String, String)]) -> Result<()> {
+    let mut failed = Vec::new();
+    for (target, overlay) in overlays {
+        match virsh::blockcommit_pivot(vm, target) {
+            Ok(()) => {
+                let _ = std::fs::remove_file(overlay);
+            }
+            Err(e) => failed.push(format!("{} ({}): {}", target, overlay, e)),
+        }
+    }
+    if !failed.is_empty() {
+        bail!("blockcommit failed; the VM still writes to these overlays: {}", failed.join("; "));
+    }
+    Ok(())
+}
+
+fn copy_disks(disks: &[(String, String)], dir: &Path) -> Result<Vec<DiskBackup>> {
+    disks
+        .iter()
+        .map(|(target, source)| {
+            let name = Path::new(source).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            let file = format!("{}-{}", target, name);
+            let (size, sha256) = copy_hashed(Path::new(source), &dir.join(&file))?;
+            Ok(DiskBackup { target: target.clone(), source: source.clone(), file, size, sha256 })
+        })
+        .collect()
+}
+
+/// Copy `from` to `to` via a `.part` file, returning the size and SHA-256.
+/// A dry run only reports the copy and returns the size with no checksum.
+fn copy_hashed(from: &Path, to: &Path) -> Result<(u64, String)> {
+    if dryrun::enabled() {
+        let size = std::fs::metadata(from).with_context(|| format!("reading {}", from.display()))?.len();
+        dryrun::note(&format!("copy {} to {}", from.display(), to.display()));
+        return Ok((size, String::new()));
+    }
+    let mut input = File::open(from).with_context(|| format!("opening {}", from.display()))?;
+    let part = PathBuf::from(format!("{}.part", to.display()));
+    let mut output = File::create(&part).with_context(|| format!("creating {}", part.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    let mut size = 0u64;
+    loop {
+        let n = input.read(&mut buf).with_context(|| format!("reading {}", from.display()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        output.write_all(&buf[..n]).with_context(|| format!("writing {}", part.display()))?;
+        size += n as u64;
+    }
+    output.sync_all()?;
+    std::fs::rename(&part, to).with_context(|| format!("renaming {}", part.display()))?;
+    Ok((size, hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()))
+}
+
+/// Re-hash every file of `backup` against its manifest.
+pub fn verify(backup: &Backup) -> Result<()> {
+    let mut bad = Vec::new();
+    for disk in &backup.manifest.disks {
+        let path = backup.dir.join(&disk.file);
+        match sha256_file(&path) {
+            Ok(sum) if sum == disk.sha256 => {}
+            Ok(_) => bad.push(format!("{} (checksum mismatch)", disk.file)),
+            Err(e) => bad.push(format!("{} ({})", disk.file, e)),
+        }
+    }
+    if sha256_hex(backup.domain_xml()?.as_bytes()) != backup.manifest.domain_xml_sha256 {
+        bad.push(format!("{} (checksum mismatch)", DOMAIN_XML));
+    }
+    if !bad.is_empty() {
+        bail!("backup {} is damaged: {}", backup.dir.display(), bad.join(", "));
+    }
+    Ok(())
+}
+
+/// Copy the disk images of `backup` back to their original paths. The VM
+/// must not be running and must be on this machine.
+pub fn restore_disks(backup: &Backup) -> Result<()> {
+    require_local(&backup.manifest.vm)?;
+    for disk in &backup.manifest.disks {
+        let (_, sha256) = copy_hashed(&backup.dir.join(&disk.file), Path::new(&disk.source))?;
+        if sha256 != disk.sha256 && !dryrun::enabled() {
+            bail!("{} changed while it was being restored", disk.source);
+        }
+    }
+    Ok(())
+}
+
+/// Delete all but the newest `keep` backups of `vm` and return their
+/// directories. `keep` 0 keeps everything.
+pub fn prune(vm: &str, keep: usize) -> Result<Vec<PathBuf>> {
+    if keep == 0 {
+        return Ok(Vec::new());
+    }
+    // A dry-run backup is not on disk, but a real one would count.
+    let keep = if dryrun::enabled() { keep - 1 } else { keep };
+    let mut removed = Vec::new();
+    for backup in list(vm)?.into_iter().skip(keep) {
+        if dryrun::enabled() {
+            dryrun::note(&format!("remove {}", backup.dir.display()));
+            removed.push(backup.dir);
+            continue;
+        }
+        std::fs::remove_dir_all(&backup.dir).with_context(|| format!("removing {}", backup.dir.display()))?;
+        removed.push(backup.dir);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dirs_per_host() {
+        let root = Path::new("/srv/backups");
+        assert_eq!(vm_dir(root, "web"), root.join("web"));
+        assert_eq!(vm_dir(root, "hv1/web"), root.join("hv1").join("web"));
+        assert_ne!(vm_dir(root, "hv1/web"), vm_dir(root, "hv1_web"));
+    }
+
+    #[test]
+    fn list_skips_other_vms() {
+        let root = std::env::temp_dir().join(format!("dismount_iso_qemu-backup-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let write = |vm: &str, created: u64| {
+            let dir = vm_dir(&root, vm).join(created.to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            let manifest = Manifest {
+                vm: vm.to_string(),
+                created,
+                frozen: false,
+                domain_xml_sha256: String::new(),
+                disks: Vec::new(),
+            };
+            std::fs::write(dir.join(MANIFEST), serde_json::to_string(&manifest).unwrap()).unwrap();
+        };
+        // A local VM named like a host, and a VM on that host.
+        write("hv1", 100);
+        write("hv1", 200);
+        write("hv1/web", 150);
+        // Unfinished: no manifest.
+        std::fs::create_dir_all(vm_dir(&root, "hv1").join("300")).unwrap();
+
+        let created = |vm: &str| -> Vec<u64> {
+            list_in(&vm_dir(&root, vm), vm).unwrap().iter().map(|b| b.manifest.created).collect()
+        };
+        assert_eq!(created("hv1"), [200, 100]);
+        assert_eq!(created("hv1/web"), [150]);
+        assert_eq!(created("hv2/web"), Vec::<u64>::new());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
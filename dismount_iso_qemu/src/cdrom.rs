@@ -0,0 +1,178 @@
+// src/cdrom.rs
+
+//! CD-ROM device definitions: reading them from domain XML and building
+//! the `<disk device='cdrom'>` element for a new drive.
+
+use std::fmt;
+
+/// Bus a new CD-ROM drive is attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    Sata,
+    Ide,
+    Scsi,
+}
+
+impl Bus {
+    pub const ALL: [Bus; 3] = [Bus::Sata, Bus::Ide, Bus::Scsi];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "sata" => Some(Bus::Sata),
+            "ide" => Some(Bus::Ide),
+            "scsi" => Some(Bus::Scsi),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Bus::Sata => "sata",
+            Bus::Ide => "ide",
+            Bus::Scsi => "scsi",
+        }
+    }
+
+    /// Target name prefix and how many targets the bus offers: IDE has
+    /// two channels of two devices (hda-hdd).
+    fn targets(self) -> (&'static str, usize) {
+        match self {
+            Bus::Ide => ("hd", 4),
+            Bus::Sata | Bus::Scsi => ("sd", 26),
+        }
+    }
+}
+
+impl fmt::Display for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A CD-ROM drive found in domain XML.
+#[derive(Debug, Clone)]
+pub struct CdromDevice {
+    pub target: String,
+    pub bus: Option<String>,
+    /// Inserted image; `None` when the tray is empty.
+    pub source: Option<String>,
+    /// The `<disk>` element as written in the domain XML, for detach-device.
+    pub xml: String,
+}
+
+/// Target names (`vda`, `sdb`, ...) of every disk in a domain XML.
+pub fn used_targets(domain_xml: &str) -> Vec<String> {
+    let Ok(doc) = roxmltree::Document::parse(domain_xml) else {
+        return Vec::new();
+    };
+    doc.descendants()
+        .filter(|n| n.has_tag_name("disk"))
+        .filter_map(|d| d.children().find(|c| c.has_tag_name("target")))
+        .filter_map(|t| t.attribute("dev").map(str::to_string))
+        .collect()
+}
+
+/// CD-ROM drives in a domain XML.
+pub fn cdroms(domain_xml: &str) -> Vec<CdromDevice> {
+    let Ok(doc) = roxmltree::Document::parse(domain_xml) else {
+        return Vec::new();
+    };
+    doc.descendants()
+        .filter(|n| n.has_tag_name("disk") && n.attribute("device") == Some("cdrom"))
+        .filter_map(|d| {
+            let target = d.children().find(|c| c.has_tag_name("target"))?;
+            let source = d
+                .children()
+                .find(|c| c.has_tag_name("source"))
+                .and_then(|s| s.attribute("file").or(s.attribute("dev")).or(s.attribute("name")))
+                .map(str::to_string);
+            Some(CdromDevice {
+                target: target.attribute("dev")?.to_string(),
+                bus: target.attribute("bus").map(str::to_string),
+                source,
+                xml: domain_xml[d.range()].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// First target name on `bus` not in `used`, e.g. "sdb".
+pub fn free_target(bus: Bus, used: &[String]) -> Option<String> {
+    let (prefix, count) = bus.targets();
+    (b'a'..b'a' + count as u8)
+        .map(|c| format!("{}{}", prefix, c as char))
+        .find(|t| !used.contains(t))
+}
+
+/// XML for an empty, read-only CD-ROM drive.
+pub fn cdrom_xml(bus: Bus, target: &str) -> String {
+    format!(
+        "<disk type='file' device='cdrom'>\n  <driver name='qemu' type='raw'/>\n  <target dev='{}' bus='{}'/>\n  <readonly/>\n</disk>\n",
+        target, bus
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOMAIN: &str = "<domain type='kvm'>
+  <name>web</name>
+  <devices>
+    <disk type='file' device='disk'>
+      <source file='/var/lib/libvirt/images/web.qcow2'/>
+      <target dev='vda' bus='virtio'/>
+    </disk>
+    <disk type='file' device='cdrom'>
+      <source file='/isos/setup.iso'/>
+      <target dev='sda' bus='sata'/>
+      <readonly/>
+    </disk>
+    <disk type='block' device='cdrom'>
+      <target dev='hdc' bus='ide'/>
+    </disk>
+  </devices>
+</domain>";
+
+    #[test]
+    fn free_targets() {
+        let used = |t: &[&str]| t.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let all_sd: Vec<String> = (b'a'..=b'z').map(|c| format!("sd{}", c as char)).collect();
+        let cases = [
+            (Bus::Sata, used(&[]), Some("sda")),
+            (Bus::Sata, used(&["vda", "sda"]), Some("sdb")),
+            (Bus::Scsi, used(&["sda", "sdc"]), Some("sdb")),
+            (Bus::Ide, used(&["hda", "hdb", "hdc"]), Some("hdd")),
+            (Bus::Ide, used(&["hda", "hdb", "hdc", "hdd"]), None),
+            (Bus::Sata, all_sd, None),
+        ];
+        for (bus, used, want) in cases {
+            assert_eq!(free_target(bus, &used).as_deref(), want, "{} {:?}", bus, used);
+        }
+    }
+
+    #[test]
+    fn drives_in_domain_xml() {
+        assert_eq!(used_targets(DOMAIN), ["vda", "sda", "hdc"]);
+        let drives = cdroms(DOMAIN);
+        let summary: Vec<_> = drives
+            .iter()
+            .map(|d| (d.target.as_str(), d.bus.as_deref(), d.source.as_deref()))
+            .collect();
+        assert_eq!(summary, [("sda", Some("sata"), Some("/isos/setup.iso")), ("hdc", Some("ide"), None)]);
+        assert!(drives[1].xml.starts_with("<disk type='block' device='cdrom'>"));
+        assert!(drives[1].xml.ends_with("</disk>"));
+        assert!(cdroms("<domain").is_empty());
+        assert!(used_targets("").is_empty());
+    }
+
+    #[test]
+    fn new_drive_xml() {
+        let xml = cdrom_xml(Bus::Ide, "hdb");
+        let drives = cdroms(&format!("<domain><devices>{}</devices></domain>", xml));
+        assert_eq!(drives.len(), 1);
+        assert_eq!((drives[0].target.as_str(), drives[0].bus.as_deref(), drives[0].source.as_deref()), ("hdb", Some("ide"), None));
+        assert_eq!(Bus::parse("SATA"), Some(Bus::Sata));
+        assert_eq!(Bus::parse("usb"), None);
+    }
+}
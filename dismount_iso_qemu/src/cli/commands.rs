@@ -0,0 +1,410 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use crate::cli::flows::backup::run_backup;
+use crate::cli::flows::clock;
+use crate::cli::flows::history::print_history;
+use crate::cli::flows::isos::print_iso_report;
+use crate::cli::flows::network;
+use crate::cli::flows::patch::run_patch;
+use crate::cli::flows::power::{self, PowerAction};
+use crate::cli::flows::transfer::{pull, push};
+use crate::cli::flows::trends::{print_trends, run_sampler};
+use crate::cli::prompts::{confirm, confirm_selection};
+use dismount_iso_qemu::agent;
+use dismount_iso_qemu::config::{self, Overrides};
+use dismount_iso_qemu::dryrun;
+use dismount_iso_qemu::guestpath::GuestPath;
+use dismount_iso_qemu::inventory::{self, Format};
+use dismount_iso_qemu::metrics::{self, ServeEvent};
+use dismount_iso_qemu::network::Needle;
+use dismount_iso_qemu::patch::PatchSpec;
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::selector::Selector;
+
+const USAGE: &str = "\
+Usage: dismount_iso_qemu [OPTIONS] [COMMAND]
+
+Without a command the interactive menu is started.
+
+Options (override environment variables and the config file):
+  --config PATH         config file (default: ~/.config/dismount_iso_qemu/config.toml)
+  -c, --connect URI     libvirt connection URI
+  --editor CMD          editor for in-VM file editing
+  --timeout SECS        guest agent probe timeout
+  --cache-ttl SECS      how long probed OS names stay cached
+  --dry-run             print the virsh commands and guest agent calls that
+                        would change anything instead of running them
+
+Commands:
+  power <action> <selector>... [--wait SECS] [--yes]
+      --wait defaults to timeouts.power_wait from the config
+      action: start | shutdown | reboot | force-off | suspend | resume
+  patch <spec.toml> <selector>... [--dry-run] [--yes]
+      Apply a declarative patch spec to the same file in every selected VM
+  backup <selector>... [--yes]
+      Back up the disks of each VM to [backup] dir, freezing guest
+      filesystems when the agent allows it, and prune old backups
+  clock [--fix] [--yes] [<selector>...]
+      Compare guest clocks with the host (all VMs by default);
+      --fix sets those drifting more than [clock] threshold_ms to host time
+  pull <vm> <guest-path> <local-path> [--resume]
+      Download a file from a VM in chunks, retrying with backoff when the
+      agent stalls; --resume continues an interrupted <local-path>.part
+  push <local-path> <vm> <guest-path>
+      Upload a file to a VM the same way, replacing the guest file
+  network <selector>...
+      Show each VM's NICs: MAC, libvirt network or bridge, addresses and
+      traffic counters (from the guest agent, else DHCP leases)
+  network --find <ip|mac> [<selector>...]
+      Find the VMs holding an IP or MAC address (all VMs by default)
+  isos [--rehash]
+      Index ISO images ([isos] dirs and storage pools) and report orphans,
+      broken CD-ROM references and duplicates
+  export [--format csv|json|html] [--output FILE] [<selector>...]
+      Write the VM inventory (all VMs by default); the format defaults to
+      the output file's extension, else JSON on stdout
+  serve-metrics [--listen ADDR]
+      Serve Prometheus metrics on http://ADDR/metrics (default from
+      [metrics] listen in the config, 0.0.0.0:9477)
+  sample [--once] [<selector>...]
+      Record memory use and CPU time of the running VMs (all by default)
+      every [history] interval seconds, or once for cron
+  trends [<selector>...]
+      Show memory and CPU averages and sparklines over 1h, 24h and 7d
+  history [--vm GLOB] [--path TEXT]
+      Show the audit log of guest modifications
+  help
+      Show this message
+
+Selectors (comma-separated groups are OR-ed, '+' joins terms with AND):
+  fs00            exact VM name
+  web-*           glob on the VM name
+  /^db-[0-9]+$/   regex on the VM name
+  all             every VM
+  state:running   libvirt state: running, paused, shutoff
+  os:windows      OS family from the guest agent probe, or OS name substring
+  tag:lab         tag from [vm.<name>] tags in the config file
+
+Bulk selections are listed and confirmed first; --yes skips the prompt.";
+
+/// Split leading global options off `args`, returning them as config
+/// overrides together with the remaining command line.
+pub fn parse_global_args(args: &[String]) -> Result<(Overrides, Vec<String>)> {
+    let mut overrides = Overrides::default();
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        if flag == "--dry-run" {
+            overrides.dry_run = true;
+            i += 1;
+            continue;
+        }
+        if !matches!(flag, "--config" | "-c" | "--connect" | "--editor" | "--timeout" | "--cache-ttl") {
+            break;
+        }
+        let Some(value) = args.get(i + 1) else {
+            bail!("{} expects a value\n\n{}", flag, USAGE);
+        };
+        let secs = || {
+            value
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("{} expects a number of seconds", flag))
+        };
+        match flag {
+            "--config" => overrides.config_path = Some(value.into()),
+            "-c" | "--connect" => overrides.uri = Some(value.clone()),
+            "--editor" => overrides.editor = Some(value.clone()),
+            "--timeout" => overrides.probe_timeout = Some(secs()?),
+            _ => overrides.cache_ttl = Some(secs()?),
+        }
+        i += 2;
+    }
+    Ok((overrides, args[i..].to_vec()))
+}
+
+/// Run a non-interactive subcommand. `args` excludes the program name.
+pub fn run(probe_mgr: &ProbeManager, args: &[String]) -> Result<()> {
+    let Some((cmd, rest)) = args.split_first() else {
+        bail!("{}", USAGE);
+    };
+    match cmd.as_str() {
+        "power" => power_cmd(probe_mgr, rest),
+        "patch" => patch_cmd(probe_mgr, rest),
+        "backup" => backup_cmd(probe_mgr, rest),
+        "clock" => clock_cmd(probe_mgr, rest),
+        "pull" => {
+            let (vm, remote, local, resume) = match rest {
+                [vm, remote, local] => (vm, remote, local, false),
+                [vm, remote, local, flag] if flag == "--resume" => (vm, remote, local, true),
+                _ => bail!("pull: expected <vm> <guest-path> <local-path> [--resume]\n\n{}", USAGE),
+            };
+            let vm = single_vm(probe_mgr, vm)?;
+            probe_mgr.require(&vm, agent::FILE_READ)?;
+            pull(&vm, &GuestPath::for_vm(probe_mgr, &vm, remote)?, Path::new(local), resume)
+        }
+        "push" => {
+            let [local, vm, remote] = rest else {
+                bail!("push: expected <local-path> <vm> <guest-path>\n\n{}", USAGE);
+            };
+            let vm = single_vm(probe_mgr, vm)?;
+            probe_mgr.require(&vm, agent::FILE_WRITE)?;
+            push(&vm, Path::new(local), &GuestPath::for_vm(probe_mgr, &vm, remote)?)
+        }
+        "isos" => match rest {
+            [] => print_iso_report(false),
+            [flag] if flag == "--rehash" => print_iso_report(true),
+            _ => bail!("isos: unexpected arguments\n\n{}", USAGE),
+        },
+        "export" => export_cmd(probe_mgr, rest),
+        "network" => network_cmd(probe_mgr, rest),
+        "serve-metrics" => {
+            let listen = match rest {
+                [] => config::get().metrics.listen.clone(),
+                [flag, addr] if flag == "--listen" => addr.clone(),
+                _ => bail!("serve-metrics: unexpected arguments\n\n{}", USAGE),
+            };
+            metrics::serve(probe_mgr, &listen, |event| match event {
+                ServeEvent::Listening => eprintln!("Serving Prometheus metrics on http://{}/metrics", listen),
+                ServeEvent::SendFailed(e) => eprintln!("Warning: failed to send response: {}", e),
+            })
+        }
+        "sample" => {
+            let once = rest.iter().any(|a| a == "--once");
+            let selectors: Vec<&str> = rest.iter().map(String::as_str).filter(|a| *a != "--once").collect();
+            run_sampler(probe_mgr, &selector_expr(&selectors), once)
+        }
+        "trends" => {
+            print_trends(&Selector::parse(&selector_expr(rest))?.resolve(probe_mgr)?);
+            Ok(())
+        }
+        "history" => history_cmd(rest),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => bail!("unknown command '{}'\n\n{}", other, USAGE),
+    }
+}
+
+fn power_cmd(probe_mgr: &ProbeManager, args: &[String]) -> Result<()> {
+    let Some((action, rest)) = args.split_first() else {
+        bail!("power: missing action\n\n{}", USAGE);
+    };
+    let Some(action) = PowerAction::parse(action) else {
+        bail!("power: unknown action '{}'\n\n{}", action, USAGE);
+    };
+
+    let mut wait = Duration::from_secs(config::get().timeouts.power_wait);
+    let mut yes = false;
+    let mut selectors = Vec::new();
+    let mut it = rest.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--wait" => {
+                let Some(secs) = it.next().and_then(|s| s.parse::<u64>().ok()) else {
+                    bail!("power: --wait expects a number of seconds");
+                };
+                wait = Duration::from_secs(secs);
+            }
+            "--yes" | "-y" => yes = true,
+            _ => selectors.push(arg.as_str()),
+        }
+    }
+    if selectors.is_empty() {
+        bail!("power: no VM given\n\n{}", USAGE);
+    }
+
+    let Some(vms) = resolve_selection(probe_mgr, &selectors.join(","), yes)? else {
+        return Ok(());
+    };
+    power::run_action(probe_mgr, action, &vms, wait)
+}
+
+/// Resolve a selector for a subcommand. Bulk matches are listed and need
+/// confirmation unless `yes` is set or this is a dry run; `None` means the
+/// user declined.
+fn resolve_selection(probe_mgr: &ProbeManager, expr: &str, yes: bool) -> Result<Option<Vec<String>>> {
+    let selector = Selector::parse(expr)?;
+    let vms = selector.resolve(probe_mgr)?;
+    if vms.is_empty() {
+        bail!("No VMs match '{}'.", expr);
+    }
+    if selector.is_bulk() && !yes && !dryrun::enabled() && !confirm_selection(&vms)? {
+        println!("Aborted.");
+        return Ok(None);
+    }
+    Ok(Some(vms))
+}
+
+/// Selector arguments joined into one expression; none means every VM.
+fn selector_expr<S: AsRef<str>>(selectors: &[S]) -> String {
+    if selectors.is_empty() {
+        "all".to_string()
+    } else {
+        selectors.iter().map(AsRef::as_ref).collect::<Vec<_>>().join(",")
+    }
+}
+
+/// Resolve a selector that must name exactly one VM.
+fn single_vm(probe_mgr: &ProbeManager, expr: &str) -> Result<String> {
+    match Selector::parse(expr)?.resolve(probe_mgr)?.as_slice() {
+        [vm] => Ok(vm.clone()),
+        [] => bail!("No VMs match '{}'.", expr),
+        vms => bail!("'{}' matches {} VMs; name one", expr, vms.len()),
+    }
+}
+
+fn patch_cmd(probe_mgr: &ProbeManager, args: &[String]) -> Result<()> {
+    let Some((spec_path, rest)) = args.split_first() else {
+        bail!("patch: missing spec file\n\n{}", USAGE);
+    };
+    let mut dry_run = dryrun::enabled();
+    let mut yes = false;
+    let mut selectors = Vec::new();
+    for arg in rest {
+        match arg.as_str() {
+            "--dry-run" | "-n" => dry_run = true,
+            "--yes" | "-y" => yes = true,
+            _ => selectors.push(arg.as_str()),
+        }
+    }
+    if selectors.is_empty() {
+        bail!("patch: no VM given\n\n{}", USAGE);
+    }
+
+    let spec = PatchSpec::load(Path::new(spec_path))?;
+    let Some(vms) = resolve_selection(probe_mgr, &selectors.join(","), yes || dry_run)? else {
+        return Ok(());
+    };
+    run_patch(probe_mgr, &spec, &vms, dry_run)?;
+    Ok(())
+}
+
+fn backup_cmd(probe_mgr: &ProbeManager, args: &[String]) -> Result<()> {
+    let yes = args.iter().any(|a| a == "--yes" || a == "-y");
+    let selectors: Vec<&str> = args.iter().map(String::as_str).filter(|a| !matches!(*a, "--yes" | "-y")).collect();
+    if selectors.is_empty() {
+        bail!("backup: no VM given\n\n{}", USAGE);
+    }
+    let Some(vms) = resolve_selection(probe_mgr, &selectors.join(","), yes)? else {
+        return Ok(());
+    };
+    run_backup(probe_mgr, &vms)
+}
+
+fn network_cmd(probe_mgr: &ProbeManager, args: &[String]) -> Result<()> {
+    // Read-only, so bulk selections need no confirmation either.
+    match args {
+        [flag, needle, selectors @ ..] if flag == "--find" => {
+            let needle = Needle::parse(needle)?;
+            let expr = selector_expr(selectors);
+            network::find(probe_mgr, &Selector::parse(&expr)?.resolve(probe_mgr)?, &needle)
+        }
+        [flag] if flag == "--find" => bail!("network: --find expects an IP or MAC address\n\n{}", USAGE),
+        [] => bail!("network: missing selector\n\n{}", USAGE),
+        selectors => {
+            let expr = selectors.join(",");
+            let vms = Selector::parse(&expr)?.resolve(probe_mgr)?;
+            if vms.is_empty() {
+                bail!("No VMs match '{}'.", expr);
+            }
+            network::show(probe_mgr, &vms)
+        }
+    }
+}
+
+fn clock_cmd(probe_mgr: &ProbeManager, args: &[String]) -> Result<()> {
+    let mut fix = false;
+    let mut yes = false;
+    let mut selectors = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--fix" => fix = true,
+            "--yes" | "-y" => yes = true,
+            _ => selectors.push(arg.as_str()),
+        }
+    }
+    // Reading clocks changes nothing; only the correction is confirmed.
+    let expr = selector_expr(&selectors);
+    let vms = Selector::parse(&expr)?.resolve(probe_mgr)?;
+    if vms.is_empty() {
+        bail!("No VMs match '{}'.", expr);
+    }
+    let drifted = clock::report(probe_mgr, &vms);
+    if drifted.is_empty() {
+        return Ok(());
+    }
+    if !fix {
+        bail!("clock drift above the threshold; run with --fix to correct it");
+    }
+    if !yes && !dryrun::enabled() && !confirm(&format!("Set the clock of {} VM(s) to host time?", drifted.len()))? {
+        println!("Aborted.");
+        return Ok(());
+    }
+    clock::fix(probe_mgr, &drifted)
+}
+
+fn export_cmd(probe_mgr: &ProbeManager, args: &[String]) -> Result<()> {
+    let mut format = None;
+    let mut output: Option<&str> = None;
+    let mut selectors = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--format" | "--output" | "-o" => {
+                let Some(value) = it.next() else {
+                    bail!("export: {} expects a value", arg);
+                };
+                if arg == "--format" {
+                    format = Some(Format::parse(value)?);
+                } else {
+                    output = Some(value.as_str());
+                }
+            }
+            _ => selectors.push(arg.as_str()),
+        }
+    }
+    let format = match (format, output.and_then(|o| Path::new(o).extension())) {
+        (Some(f), _) => f,
+        (None, Some(ext)) => Format::parse(&ext.to_string_lossy())?,
+        (None, None) => Format::Json,
+    };
+
+    // Exporting changes nothing, so bulk selections need no confirmation.
+    let expr = selector_expr(&selectors);
+    let vms = Selector::parse(&expr)?.resolve(probe_mgr)?;
+    let text = inventory::render(&inventory::collect(probe_mgr, &vms), format)?;
+
+    match output {
+        None | Some("-") => print!("{}", text),
+        Some(path) => {
+            // Write then rename so a reader never sees a half-written export.
+            let tmp = format!("{}.tmp", path);
+            std::fs::write(&tmp, &text)?;
+            std::fs::rename(&tmp, path)?;
+            eprintln!("Wrote {} VM(s) to {}", vms.len(), path);
+        }
+    }
+    Ok(())
+}
+
+fn history_cmd(args: &[String]) -> Result<()> {
+    let mut vm = None;
+    let mut path = None;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let slot = match arg.as_str() {
+            "--vm" => &mut vm,
+            "--path" => &mut path,
+            other => bail!("history: unexpected argument '{}'\n\n{}", other, USAGE),
+        };
+        let Some(value) = it.next() else {
+            bail!("history: {} expects a value", arg);
+        };
+        *slot = Some(value.as_str());
+    }
+    print_history(vm, path)
+}
@@ -0,0 +1,148 @@
+use anyhow::{bail, Result};
+
+use dismount_iso_qemu::agent;
+use dismount_iso_qemu::audit::{self, AuditRecord};
+use dismount_iso_qemu::backup;
+use dismount_iso_qemu::config;
+use dismount_iso_qemu::domxml;
+use dismount_iso_qemu::dryrun;
+use dismount_iso_qemu::error::Error;
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::utils::{format_memory_kib, format_unix_time, sha256_hex};
+use dismount_iso_qemu::virsh;
+
+use crate::cli::hint_for;
+use crate::cli::prompts::{confirm, prompt, select_vm, select_vms};
+
+pub fn backup_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    println!("\n--- BACKUPS ---");
+    println!("1) Back up VMs");
+    println!("2) Restore a VM from a backup");
+    println!("3) Back");
+    match prompt("Select option: ")?.as_str() {
+        "1" => {
+            let vms = select_vms(probe_mgr)?;
+            if vms.is_empty() {
+                return Ok(());
+            }
+            run_backup(probe_mgr, &vms)
+        }
+        "2" => restore(probe_mgr),
+        _ => Ok(()),
+    }
+}
+
+/// Back up each VM in turn, then prune its old backups per `[backup] keep`.
+/// Running VMs are frozen when their agent allows it. Failures are reported
+/// per VM and make the whole run an error after all VMs are tried.
+pub fn run_backup(probe_mgr: &ProbeManager, vms: &[String]) -> Result<()> {
+    let keep = config::get().backup.keep;
+    let mut failed = 0;
+    for vm in vms {
+        let result = (|| -> Result<()> {
+            let state = virsh::domstate(vm)?;
+            let freeze = state == "running" && probe_mgr.require(vm, agent::FSFREEZE).is_ok();
+            match (state.as_str(), freeze) {
+                (_, true) => println!("{}: backing up, filesystems frozen for the snapshot...", vm),
+                ("running" | "paused", false) => {
+                    println!("{}: backing up without freezing (guest agent unavailable); the copy is crash-consistent...", vm)
+                }
+                _ => println!("{}: backing up ({})...", vm, state),
+            }
+
+            let b = backup::create(vm, freeze)?;
+            println!(
+                "{}: {} disk(s), {} {} {}",
+                vm,
+                b.manifest.disks.len(),
+                format_memory_kib(Some(b.size() / 1024)),
+                if dryrun::enabled() { "would be written to" } else { "written to" },
+                b.dir.display()
+            );
+            let pruned = backup::prune(vm, keep)?;
+            if !pruned.is_empty() {
+                println!("{}: pruned {} old backup(s), keeping {}", vm, pruned.len(), keep);
+            }
+            Ok(())
+        })();
+
+        if let Err(e) = result {
+            failed += 1;
+            eprintln!("{}: backup failed: {:#}", vm, e);
+            if let Some(hint) = hint_for(&e) {
+                eprintln!("Hint: {}", hint);
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("backup failed on {} of {} VM(s)", failed, vms.len());
+    }
+    Ok(())
+}
+
+/// Pick a backup, verify it, copy its disks back and define the VM from it.
+fn restore(probe_mgr: &ProbeManager) -> Result<()> {
+    let Some(vm) = select_vm(probe_mgr)? else {
+        return Ok(());
+    };
+    backup::require_local(&vm)?;
+    let backups = backup::list(&vm)?;
+    if backups.is_empty() {
+        println!("No backups of '{}' in {}.", vm, backup::root()?.display());
+        return Ok(());
+    }
+    println!("\nBackups of {} (newest first):", vm);
+    for (i, b) in backups.iter().enumerate() {
+        println!(
+            "{}) {} UTC  {} disk(s)  {:>10}  {}",
+            i + 1,
+            format_unix_time(b.manifest.created),
+            b.manifest.disks.len(),
+            format_memory_kib(Some(b.size() / 1024)),
+            if b.manifest.frozen { "frozen" } else { "not frozen" }
+        );
+    }
+    let Some(chosen) = prompt("Select backup: ")?
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| backups.get(i))
+    else {
+        println!("Invalid selection.");
+        return Ok(());
+    };
+
+    let defined = match virsh::domstate(&vm) {
+        Ok(state) if state == "running" || state == "paused" => {
+            bail!("'{}' is {}; shut it down before restoring", vm, state)
+        }
+        Ok(_) => true,
+        Err(Error::DomainNotFound(_)) => false,
+        Err(e) => return Err(e.into()),
+    };
+
+    println!("Verifying checksums...");
+    backup::verify(chosen)?;
+    println!("These disk images will be overwritten:");
+    for disk in &chosen.manifest.disks {
+        println!("  {} <- {}", disk.source, disk.file);
+    }
+    if !confirm(&format!("Restore '{}' from this backup?", vm))? {
+        println!("Aborted; nothing changed.");
+        return Ok(());
+    }
+
+    backup::restore_disks(chosen)?;
+    let xml = chosen.domain_xml()?;
+    let mut rec = AuditRecord::new(&vm, "restore-domain", Some(&chosen.dir.to_string_lossy()));
+    if defined {
+        // Keep the replaced definition so "Edit domain XML" can revert it.
+        let current = virsh::dumpxml(&vm, true)?;
+        domxml::save_revision(&vm, &current)?;
+        rec.sha256_before = Some(sha256_hex(current.as_bytes()));
+    }
+    rec.sha256_after = Some(sha256_hex(xml.as_bytes()));
+    audit::run(rec, || virsh::define(&vm, &xml))?;
+    println!("'{}' restored from the backup of {} UTC.", vm, format_unix_time(chosen.manifest.created));
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use anyhow::{bail, Result};
+
+use dismount_iso_qemu::audit::{self, AuditRecord};
+use dismount_iso_qemu::cdrom::{self, Bus, CdromDevice};
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::virsh;
+
+use crate::cli::prompts::{confirm, prompt, select_vm};
+
+pub fn cdrom_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    println!("\n--- CD-ROM DEVICES ---");
+    println!("1) Add a CD-ROM drive");
+    println!("2) Remove an unused CD-ROM drive");
+    println!("3) Back");
+    let choice = prompt("Select option: ")?;
+    if !matches!(choice.as_str(), "1" | "2") {
+        return Ok(());
+    }
+    let Some(vm) = select_vm(probe_mgr)? else {
+        return Ok(());
+    };
+    let running = virsh::domstate(&vm)? == "running";
+    if choice == "1" { add(&vm, running) } else { remove(&vm, running) }
+}
+
+/// libvirt cannot hot-plug CD-ROM drives, so changes only ever go to the
+/// persistent config; tell the user when that means waiting for a reboot.
+fn explain_scope(vm: &str, running: bool) {
+    if running {
+        println!(
+            "'{}' is running and CD-ROM drives cannot be hot-plugged: the persistent config is changed, \
+             which takes effect when the VM is next started.",
+            vm
+        );
+    }
+}
+
+fn add(vm: &str, running: bool) -> Result<()> {
+    let names: Vec<&str> = Bus::ALL.iter().map(|b| b.name()).collect();
+    let input = prompt(&format!("Bus ({}) [sata]: ", names.join(", ")))?;
+    let bus = if input.is_empty() {
+        Bus::Sata
+    } else {
+        Bus::parse(&input).ok_or_else(|| anyhow::anyhow!("unknown bus '{}'", input))?
+    };
+
+    let used = cdrom::used_targets(&virsh::dumpxml(vm, true)?);
+    let Some(target) = cdrom::free_target(bus, &used) else {
+        bail!("no free {} target left in '{}'", bus, vm);
+    };
+
+    let xml = cdrom::cdrom_xml(bus, &target);
+    println!("\nDevice XML:\n{}", xml);
+    explain_scope(vm, running);
+    if !confirm(&format!("Attach CD-ROM {} to '{}'?", target, vm))? {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    let rec = AuditRecord::new(vm, "attach-cdrom", Some(&target));
+    audit::run(rec, || virsh::attach_device(vm, &xml, false, true))?;
+    if running {
+        println!("Added CD-ROM {} ({} bus) to '{}'; it appears at the next start.", target, bus, vm);
+    } else {
+        println!("Added CD-ROM {} ({} bus) to '{}'.", target, bus, vm);
+    }
+    Ok(())
+}
+
+fn remove(vm: &str, running: bool) -> Result<()> {
+    let persistent = cdrom::cdroms(&virsh::dumpxml(vm, true)?);
+    let live = if running { cdrom::cdroms(&virsh::dumpxml(vm, false)?) } else { Vec::new() };
+
+    let mut targets: Vec<&str> = persistent.iter().chain(&live).map(|d| d.target.as_str()).collect();
+    targets.sort();
+    targets.dedup();
+    if targets.is_empty() {
+        println!("'{}' has no CD-ROM drives.", vm);
+        return Ok(());
+    }
+
+    let find = |list: &'_ [CdromDevice], t: &str| list.iter().find(|d| d.target == t).cloned();
+    println!("\n{:8} {:6} {:12} Image", "Target", "Bus", "Defined in");
+    println!("{}", "-".repeat(70));
+    for t in &targets {
+        let (p, l) = (find(&persistent, t), find(&live, t));
+        let dev = p.as_ref().or(l.as_ref()).unwrap();
+        let defined = match (p.is_some(), l.is_some()) {
+            (true, true) => "both",
+            (true, false) => "config",
+            _ => "live",
+        };
+        let image = p.as_ref().and_then(|d| d.source.clone()).or_else(|| l.as_ref().and_then(|d| d.source.clone()));
+        println!("{:8} {:6} {:12} {}", t, dev.bus.as_deref().unwrap_or("-"), defined, image.as_deref().unwrap_or("(empty)"));
+    }
+
+    let target = prompt("\nTarget to remove (empty to go back): ")?;
+    if target.is_empty() {
+        return Ok(());
+    }
+    let (p, l) = (find(&persistent, &target), find(&live, &target));
+    let Some(dev) = p.as_ref() else {
+        if l.is_some() {
+            bail!("CD-ROM {} exists only in the running '{}' and goes away when it shuts down", target, vm);
+        }
+        bail!("'{}' has no CD-ROM drive {}", vm, target);
+    };
+    if let Some(image) = dev.source.as_deref() {
+        bail!("CD-ROM {} in '{}' holds {}; eject it first", target, vm, image);
+    }
+
+    println!("\nDevice XML:\n{}", dev.xml);
+    explain_scope(vm, running);
+    if !confirm(&format!("Remove CD-ROM {} from '{}'?", target, vm))? {
+        println!("Aborted.");
+        return Ok(());
+    }
+    let rec = AuditRecord::new(vm, "detach-cdrom", Some(&target));
+    audit::run(rec, || virsh::detach_device(vm, &dev.xml, false, true))?;
+    if running {
+        println!("Removed CD-ROM {} from the config of '{}'; it is gone after the next shutdown.", target, vm);
+    } else {
+        println!("Removed CD-ROM {} from '{}'.", target, vm);
+    }
+    Ok(())
+}
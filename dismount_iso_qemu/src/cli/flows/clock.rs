@@ -0,0 +1,116 @@
+use anyhow::{bail, Result};
+
+use dismount_iso_qemu::clock;
+use dismount_iso_qemu::config;
+use dismount_iso_qemu::dryrun;
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::utils::{format_seconds_dhms, format_unix_time};
+
+use crate::cli::hint_for;
+use crate::cli::prompts::{confirm, prompt, select_vms};
+
+pub fn clock_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    println!("\n--- CLOCK DRIFT ---");
+    println!("1) Check guest clocks");
+    println!("2) Correct drifting clocks");
+    println!("3) Back");
+    let choice = prompt("Select option: ")?;
+    if !matches!(choice.as_str(), "1" | "2") {
+        return Ok(());
+    }
+    let vms = select_vms(probe_mgr)?;
+    if vms.is_empty() {
+        return Ok(());
+    }
+
+    let drifted = report(probe_mgr, &vms);
+    if choice == "2" {
+        if drifted.is_empty() {
+            println!("Nothing to correct.");
+        } else if confirm(&format!("Set the clock of {} VM(s) to host time?", drifted.len()))? {
+            fix(probe_mgr, &drifted)?;
+        }
+    }
+    Ok(())
+}
+
+/// Print each VM's clock and its drift from the host. Returns the VMs that
+/// drift more than `[clock] threshold_ms`.
+pub fn report(probe_mgr: &ProbeManager, vms: &[String]) -> Vec<String> {
+    let threshold = config::get().clock.threshold_ms;
+    let mut drifted = Vec::new();
+    let mut hints = Vec::new();
+
+    println!("\n{:20} {:20} {:>12} {:>7}", "VM", "Guest time (UTC)", "Drift", "RTT");
+    println!("{}", "-".repeat(70));
+    for (vm, result) in clock::check(probe_mgr, vms) {
+        match result {
+            Ok(d) => {
+                let over = d.exceeds(threshold);
+                println!(
+                    "{:20} {:20} {:>12} {:>5}ms{}",
+                    vm,
+                    format_unix_time((d.guest_ns / 1_000_000_000) as u64),
+                    format_offset(d.offset_ms),
+                    d.rtt_ms,
+                    if over { "  DRIFT" } else { "" }
+                );
+                if over {
+                    drifted.push(vm);
+                }
+            }
+            Err(e) => {
+                println!("{:20} {}", vm, e.short());
+                if let Some(hint) = e.hint()
+                    && !hints.contains(&hint)
+                {
+                    hints.push(hint);
+                }
+            }
+        }
+    }
+    for hint in hints {
+        println!("Hint: {}", hint);
+    }
+    if !drifted.is_empty() {
+        println!("{} VM(s) drift more than {}ms from the host.", drifted.len(), threshold);
+    }
+    drifted
+}
+
+/// Set the clocks of `vms` to host time and show the drift that is left.
+pub fn fix(probe_mgr: &ProbeManager, vms: &[String]) -> Result<()> {
+    let mut failed = 0;
+    for vm in vms {
+        if let Err(e) = clock::correct(probe_mgr, vm) {
+            failed += 1;
+            eprintln!("{}: setting the clock failed: {:#}", vm, e);
+            if let Some(hint) = hint_for(&e) {
+                eprintln!("Hint: {}", hint);
+            }
+            continue;
+        }
+        if dryrun::enabled() {
+            continue;
+        }
+        match clock::measure(probe_mgr, vm) {
+            Ok(d) => println!("{}: clock set, drift now {}", vm, format_offset(d.offset_ms)),
+            Err(e) => println!("{}: clock set, but could not be read back: {}", vm, e.short()),
+        }
+    }
+    if failed > 0 {
+        bail!("could not set the clock of {} of {} VM(s)", failed, vms.len());
+    }
+    Ok(())
+}
+
+/// `+1.250s` below a minute, `-2h 5m 3s` above.
+fn format_offset(ms: i64) -> String {
+    let sign = if ms < 0 { '-' } else { '+' };
+    let abs = ms.unsigned_abs();
+    if abs < 60_000 {
+        format!("{}{}.{:03}s", sign, abs / 1000, abs % 1000)
+    } else {
+        format!("{}{}", sign, format_seconds_dhms(abs / 1000))
+    }
+}
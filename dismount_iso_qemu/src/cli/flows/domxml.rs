@@ -0,0 +1,110 @@
+use anyhow::{bail, Result};
+
+use dismount_iso_qemu::audit::{self, AuditRecord};
+use dismount_iso_qemu::domxml;
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::utils::{format_unix_time, open_in_editor, sha256_hex};
+use dismount_iso_qemu::virsh;
+
+use crate::cli::prompts::{confirm, prompt, select_vm};
+
+pub fn domxml_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    println!("\n--- DOMAIN XML ---");
+    println!("1) Edit definition");
+    println!("2) Revert to a previous definition");
+    println!("3) Back");
+    let choice = prompt("Select option: ")?;
+    if !matches!(choice.as_str(), "1" | "2") {
+        return Ok(());
+    }
+    let Some(vm) = select_vm(probe_mgr)? else {
+        return Ok(());
+    };
+    if choice == "1" { edit(&vm) } else { revert(&vm) }
+}
+
+/// dumpxml, edit in `$EDITOR`, diff, validate and redefine.
+fn edit(vm: &str) -> Result<()> {
+    let current = virsh::dumpxml(vm, true)?;
+    let local_path = std::env::temp_dir().join(format!("{}.xml", vm.replace('/', "_")));
+    std::fs::write(&local_path, &current)?;
+    println!("Using local file: {}", local_path.display());
+
+    let updated = loop {
+        println!("Opening editor...");
+        open_in_editor(&local_path)?;
+        let updated = std::fs::read_to_string(&local_path)?;
+        if updated == current {
+            println!("No changes detected. Nothing to define.");
+            return Ok(());
+        }
+        match domxml::validate(&updated, &current) {
+            Ok(()) => break updated,
+            Err(e) => {
+                println!("Invalid domain XML: {:#}", e);
+                if !confirm("Re-open the editor?")? {
+                    println!("Discarded; the domain is unchanged.");
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    apply(vm, &current, &updated, "define-domain")?;
+    let _ = std::fs::remove_file(&local_path);
+    Ok(())
+}
+
+/// Pick a saved definition and make it current again.
+fn revert(vm: &str) -> Result<()> {
+    let revisions = domxml::revisions(vm)?;
+    if revisions.is_empty() {
+        println!("No previous definitions saved for '{}'.", vm);
+        return Ok(());
+    }
+    println!("\nSaved definitions of {} (newest first):", vm);
+    for (i, rev) in revisions.iter().enumerate() {
+        println!("{}) replaced {} UTC", i + 1, format_unix_time(rev.saved_at));
+    }
+    let Some(rev) = prompt("Select definition: ")?
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| revisions.get(i))
+    else {
+        println!("Invalid selection.");
+        return Ok(());
+    };
+
+    let current = virsh::dumpxml(vm, true)?;
+    let saved = std::fs::read_to_string(&rev.path)?;
+    if saved == current {
+        println!("That definition is already current.");
+        return Ok(());
+    }
+    if let Err(e) = domxml::validate(&saved, &current) {
+        bail!("saved definition {} cannot be applied: {:#}", rev.path.display(), e);
+    }
+    apply(vm, &current, &saved, "revert-domain")
+}
+
+/// Show the diff, confirm, save the current definition and define `updated`.
+fn apply(vm: &str, current: &str, updated: &str, action: &str) -> Result<()> {
+    println!("\n{}", diffy::create_patch(current, updated));
+    if !confirm(&format!("Redefine '{}' with these changes?", vm))? {
+        println!("Aborted; the domain is unchanged.");
+        return Ok(());
+    }
+
+    let saved = domxml::save_revision(vm, current)?;
+    let mut rec = AuditRecord::new(vm, action, None);
+    rec.sha256_before = Some(sha256_hex(current.as_bytes()));
+    rec.sha256_after = Some(sha256_hex(updated.as_bytes()));
+    audit::run(rec, || virsh::define(vm, updated))?;
+
+    println!("Domain redefined; previous definition saved to {}.", saved.display());
+    if virsh::domstate(vm).is_ok_and(|s| s == "running") {
+        println!("'{}' is running; the new definition takes effect after it is shut down and started again.", vm);
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use anyhow::Result;
+
+use dismount_iso_qemu::audit::{self, AuditRecord};
+use dismount_iso_qemu::utils::glob_match;
+
+use crate::cli::prompts::prompt;
+
+pub fn history_flow() -> Result<()> {
+    let vm = prompt("Filter by VM (glob, empty for all): ")?;
+    let path = prompt("Filter by path (substring, empty for all): ")?;
+    print_history(Some(vm.as_str()).filter(|v| !v.is_empty()), Some(path.as_str()).filter(|p| !p.is_empty()))
+}
+
+/// Print audit records whose VM matches the `vm` glob and whose path
+/// contains `path` (case-insensitive), oldest first.
+pub fn print_history(vm: Option<&str>, path: Option<&str>) -> Result<()> {
+    let (records, bad) = audit::read_log()?;
+    let path = path.map(str::to_lowercase);
+    let matches: Vec<&AuditRecord> = records
+        .iter()
+        .filter(|r| vm.is_none_or(|g| glob_match(g, &r.vm)))
+        .filter(|r| {
+            path.as_deref()
+                .is_none_or(|p| r.path.as_deref().unwrap_or("").to_lowercase().contains(p))
+        })
+        .collect();
+
+    if bad > 0 {
+        eprintln!("Warning: skipped {} unreadable line(s) in the audit log", bad);
+    }
+    if matches.is_empty() {
+        println!("No audit records match.");
+        return Ok(());
+    }
+
+    println!("\n{:20} {:12} {:20} {:12} {:32} {:17} Outcome", "Time (UTC)", "User", "VM", "Action", "Path", "SHA-256 before>after");
+    println!("{}", "-".repeat(130));
+    for r in matches {
+        let user = match &r.sudo_user {
+            Some(s) => format!("{}({})", s, r.user),
+            None => r.user.clone(),
+        };
+        let short = |h: &Option<String>| h.as_deref().map(|h| h[..h.len().min(8)].to_string()).unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:20} {:12} {:20} {:12} {:32} {:8}>{:8} {}",
+            r.ts.trim_end_matches('Z').replace('T', " "),
+            user,
+            r.vm,
+            r.action,
+            r.path.as_deref().unwrap_or("-"),
+            short(&r.sha256_before),
+            short(&r.sha256_after),
+            r.outcome
+        );
+    }
+    Ok(())
+}
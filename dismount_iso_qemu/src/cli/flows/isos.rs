@@ -0,0 +1,104 @@
+use anyhow::Result;
+
+use dismount_iso_qemu::isolib::{self, CdromRef};
+use dismount_iso_qemu::utils::{format_memory_kib, format_unix_time};
+use dismount_iso_qemu::virsh;
+
+use crate::cli::prompts::confirm;
+
+/// Menu option 2: list the CD-ROM drives of every VM and what is inserted.
+pub fn scan_mounted_flow() -> Result<()> {
+    let vms = virsh::list_vms()?;
+    scan_cdroms(&vms);
+    Ok(())
+}
+
+/// Print each VM's CD-ROM drives and return the drives that could be read.
+fn scan_cdroms(vms: &[String]) -> Vec<CdromRef> {
+    let mut refs = Vec::new();
+    println!("\n{:20} {:8} Image", "VM", "Drive");
+    println!("{}", "-".repeat(80));
+    for (vm, result) in isolib::cdroms(vms) {
+        match result {
+            Ok(drives) if drives.is_empty() => println!("{:20} {:8} (no CD-ROM drive)", vm, "-"),
+            Ok(drives) => {
+                for d in &drives {
+                    println!("{:20} {:8} {}", vm, d.target, d.source.as_deref().unwrap_or("(empty)"));
+                }
+                refs.extend(drives);
+            }
+            Err(e) => println!("{:20} {:8} {}", vm, "-", e.short()),
+        }
+    }
+    refs
+}
+
+pub fn iso_report_flow() -> Result<()> {
+    let rehash = confirm("Re-hash every image (slow; cached hashes are reused otherwise)?")?;
+    print_iso_report(rehash)
+}
+
+/// Index the ISO library, then print it with orphans, broken references
+/// and duplicates.
+pub fn print_iso_report(rehash: bool) -> Result<()> {
+    let vms = virsh::list_vms()?;
+    let refs = scan_cdroms(&vms);
+
+    println!("\nIndexing ISO images...");
+    let index = isolib::build_index(&refs, rehash)?;
+    for w in &index.warnings {
+        eprintln!("Warning: {}", w);
+    }
+    if index.entries.is_empty() {
+        println!("No ISO images found; set [isos] dirs in the config or add an ISO storage pool.");
+    }
+
+    let size = |s: Option<u64>| match s {
+        Some(bytes) => format_memory_kib(Some(bytes / 1024)),
+        None => "-".to_string(),
+    };
+    println!("\n{:56} {:12} {:17} {:20} {:12} Used by", "Image", "Size", "Modified (UTC)", "Label", "SHA-256");
+    println!("{}", "-".repeat(140));
+    for e in &index.entries {
+        let path = match &e.host {
+            Some(h) => format!("{}:{}", h, e.path),
+            None => e.path.clone(),
+        };
+        println!(
+            "{:56} {:12} {:17} {:20} {:12} {}",
+            path,
+            size(e.size),
+            e.mtime.map(|t| format_unix_time(t)[..16].to_string()).unwrap_or_else(|| "-".to_string()),
+            e.label.as_deref().unwrap_or("-"),
+            e.sha256.as_deref().map(|h| &h[..12]).unwrap_or("-"),
+            if e.used_by.is_empty() { "-".to_string() } else { e.used_by.join(", ") }
+        );
+    }
+
+    let report = isolib::report(&index, &refs);
+    println!("\nOrphaned images (not referenced by any VM): {}", report.orphans.len());
+    for e in &report.orphans {
+        println!("  {} ({})", e.path, size(e.size));
+    }
+    println!("\nBroken references (image missing): {}", report.broken.len());
+    for r in &report.broken {
+        println!("  {} {} -> {}", r.vm, r.target, r.source.as_deref().unwrap_or_default());
+    }
+    if !report.unchecked.is_empty() {
+        println!(
+            "\nUnchecked references (on a remote host, outside its storage pools): {}",
+            report.unchecked.len()
+        );
+        for r in &report.unchecked {
+            println!("  {} {} -> {}", r.vm, r.target, r.source.as_deref().unwrap_or_default());
+        }
+    }
+    println!("\nDuplicate images (same SHA-256): {}", report.duplicates.len());
+    for group in &report.duplicates {
+        println!("  {}:", &group[0].sha256.as_deref().unwrap_or_default()[..12]);
+        for e in group {
+            println!("    {}", e.path);
+        }
+    }
+    Ok(())
+}
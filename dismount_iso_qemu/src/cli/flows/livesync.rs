@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+
+use dismount_iso_qemu::agent;
+use dismount_iso_qemu::livesync::{self, Restart, SyncEvent};
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::utils::{format_unix_time, open_in_editor, unix_now};
+use dismount_iso_qemu::virsh;
+
+use crate::cli::flows::modify::prompt_paths;
+use crate::cli::flows::service::is_windows;
+use crate::cli::hint_for;
+use crate::cli::prompts::{prompt, select_vm};
+
+pub fn live_sync_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    let Some(vm) = select_vm(probe_mgr)? else {
+        return Ok(());
+    };
+    probe_mgr.require(&vm, agent::FILE_READ)?;
+    probe_mgr.require(&vm, agent::FILE_WRITE)?;
+
+    let Some((remote_path, local_path)) = prompt_paths(probe_mgr, &vm)? else {
+        return Ok(());
+    };
+
+    let service = prompt("Service to restart after each push (empty for none): ")?;
+    let restart = if service.is_empty() {
+        None
+    } else {
+        probe_mgr.require(&vm, agent::EXEC)?;
+        Some(if is_windows(probe_mgr, &vm) { Restart::WindowsService(service) } else { Restart::SystemdUnit(service) })
+    };
+
+    println!("1) Open in editor and sync on every save until it exits");
+    println!("2) Watch only (edit with any program, press Enter to stop)");
+    let in_editor = match prompt("Select mode: ")?.as_str() {
+        "1" => true,
+        "2" => false,
+        _ => return Ok(()),
+    };
+
+    let original = virsh::ga_read_file(&vm, remote_path.as_str())?;
+    std::fs::write(&local_path, &original)?;
+
+    let stop = AtomicBool::new(false);
+    let (editor_result, log, pushes, failures) = std::thread::scope(|s| {
+        let watcher = s.spawn(|| {
+            // A terminal editor owns the screen, so its session log is
+            // printed after it exits; in watch-only mode lines go out live.
+            let mut log = Vec::new();
+            let (mut pushes, mut failures) = (0, 0);
+            livesync::run(&vm, remote_path.as_str(), &local_path, &original, restart.as_ref(), &stop, |event| {
+                match &event {
+                    SyncEvent::Pushed { .. } => pushes += 1,
+                    SyncEvent::PushFailed(_) | SyncEvent::RestartFailed(..) => failures += 1,
+                    SyncEvent::Restarted(_) => {}
+                }
+                for line in describe(&event) {
+                    if in_editor {
+                        log.push(line);
+                    } else {
+                        println!("{}", line);
+                    }
+                }
+            });
+            (log, pushes, failures)
+        });
+
+        let editor_result = if in_editor {
+            println!("Opening editor; every save is pushed to {}.", vm);
+            open_in_editor(&local_path)
+        } else {
+            println!("Watching {}; every save is pushed to {}:{}.", local_path.display(), vm, remote_path);
+            prompt("Press Enter to stop.\n").map(|_| ()).map_err(Into::into)
+        };
+        stop.store(true, Ordering::Relaxed);
+        let (log, pushes, failures) = watcher.join().unwrap();
+        (editor_result, log, pushes, failures)
+    });
+
+    for line in log {
+        println!("{}", line);
+    }
+    println!("Live sync ended: {} push(es), {} failure(s).", pushes, failures);
+    editor_result
+}
+
+/// Log lines for one event, timestamped in UTC.
+fn describe(event: &SyncEvent) -> Vec<String> {
+    let now = format_unix_time(unix_now());
+    let mut lines = vec![match event {
+        SyncEvent::Pushed { bytes, sha256 } => format!("[{}] pushed {} bytes (sha256 {})", now, bytes, &sha256[..12]),
+        SyncEvent::PushFailed(e) => format!("[{}] push failed: {:#}", now, e),
+        SyncEvent::Restarted(name) => format!("[{}] restarted '{}'", now, name),
+        SyncEvent::RestartFailed(name, e) => format!("[{}] restart of '{}' failed: {:#}", now, name, e),
+    }];
+    if let SyncEvent::PushFailed(e) | SyncEvent::RestartFailed(_, e) = event
+        && let Some(hint) = hint_for(e)
+    {
+        lines.push(format!("Hint: {}", hint));
+    }
+    lines
+}
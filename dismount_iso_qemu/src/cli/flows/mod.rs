@@ -1 +1,16 @@
 pub mod modify;
+pub mod snapshot;
+pub mod power;
+pub mod history;
+pub mod patch;
+pub mod service;
+pub mod users;
+pub mod isos;
+pub mod cdrom;
+pub mod domxml;
+pub mod livesync;
+pub mod backup;
+pub mod transfer;
+pub mod network;
+pub mod trends;
+pub mod clock;
@@ -1,36 +1,32 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
 use sha2::{Sha256, Digest};
 
-use crate::cli::prompts::prompt;
-use crate::utils::{normalize_windows_path, resolve_local_path, open_in_editor};
-use crate::virsh;
+use dismount_iso_qemu::agent;
+use dismount_iso_qemu::audit;
+use dismount_iso_qemu::config;
+use dismount_iso_qemu::guestpath::{Flavor, GuestPath};
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::utils::{open_in_editor, resolve_local_path};
+use dismount_iso_qemu::virsh;
 
-pub fn modify_file_flow() -> Result<()> {
-    let vms = virsh::list_vms()?;
-    if vms.is_empty() {
-        println!("No VMs found.");
-        return Ok(());
-    }
+use crate::cli::flows::service::{is_windows, manage_services};
+use crate::cli::prompts::{confirm, prompt, select_vm};
 
-    let vm = prompt("VM name: ")?;
-    if !vms.iter().any(|v| v == &vm) {
-        println!("VM '{}' not found.", vm);
+pub fn modify_file_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    let Some(vm) = select_vm(probe_mgr)? else {
         return Ok(());
-    }
-
-    let remote_raw = prompt("Path inside VM (e.g. C:\\nps.xml): ")?;
-    let remote_path = normalize_windows_path(&remote_raw);
+    };
+    // Find out now rather than after the edit that the file cannot be pushed.
+    probe_mgr.require(&vm, agent::FILE_READ)?;
+    probe_mgr.require(&vm, agent::FILE_WRITE)?;
 
-    let local_raw = prompt("Local file to edit (Linux path): ")?;
-    if local_raw.contains(":\\") {
-        println!("ERROR: Local path must be a Linux path.");
+    let Some((remote_path, local_path)) = prompt_paths(probe_mgr, &vm)? else {
         return Ok(());
-    }
-
-    let local_path = resolve_local_path(&local_raw, &remote_raw);
-    println!("Using local file: {}", local_path.display());
+    };
 
-    let original = virsh::ga_read_file(&vm, &remote_path)?;
+    let original = virsh::ga_read_file(&vm, remote_path.as_str())?;
     std::fs::write(&local_path, &original)?;
 
     let original_hash = Sha256::digest(&original);
@@ -46,8 +42,38 @@ pub fn modify_file_flow() -> Result<()> {
         return Ok(());
     }
 
-    virsh::ga_write_file(&vm, &remote_path, &updated)?;
+    audit::write_file(&vm, remote_path.as_str(), Some(&original), &updated)?;
     println!("File successfully updated in VM.");
 
+    // Config changes usually need the owning service restarted.
+    if is_windows(probe_mgr, &vm) && confirm("Start, stop or restart a service in this VM?")? {
+        manage_services(probe_mgr, &vm)?;
+    }
+
     Ok(())
 }
+
+/// Ask for the file inside `vm` and the local copy to edit. Returns the
+/// guest path, checked for the OS `vm` runs, and the local path, or `None`
+/// when no guest path was given.
+pub fn prompt_paths(probe_mgr: &ProbeManager, vm: &str) -> Result<Option<(GuestPath, PathBuf)>> {
+    let remote_raw = match config::get().remote_path(vm) {
+        Some(default) => {
+            let input = prompt(&format!("Path inside VM [{}]: ", default))?;
+            if input.is_empty() { default.to_string() } else { input }
+        }
+        None => match Flavor::of(probe_mgr, vm) {
+            Some(Flavor::Posix) => prompt("Path inside VM (e.g. /etc/hosts): ")?,
+            _ => prompt("Path inside VM (e.g. C:\\nps.xml): ")?,
+        },
+    };
+    if remote_raw.is_empty() {
+        return Ok(None);
+    }
+    let remote_path = GuestPath::for_vm(probe_mgr, vm, &remote_raw)?;
+
+    let local_raw = prompt("Local file to edit (Linux path): ")?;
+    let local_path = resolve_local_path(&local_raw, &remote_path)?;
+    println!("Using local file: {}", local_path.display());
+    Ok(Some((remote_path, local_path)))
+}
@@ -0,0 +1,115 @@
+use anyhow::{bail, Result};
+
+use dismount_iso_qemu::network::{self, AddressSource, Interface, Needle};
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::utils::format_memory_kib;
+use dismount_iso_qemu::virsh;
+
+use crate::cli::hint_for;
+use crate::cli::prompts::{prompt, select_vms};
+
+pub fn network_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    println!("\n--- NETWORK ---");
+    println!("1) Show VM interfaces");
+    println!("2) Find the VM holding an IP or MAC address");
+    println!("3) Back");
+    match prompt("Select option: ")?.as_str() {
+        "1" => {
+            let vms = select_vms(probe_mgr)?;
+            show(probe_mgr, &vms)
+        }
+        "2" => {
+            let needle = Needle::parse(&prompt("IP or MAC address: ")?)?;
+            find(probe_mgr, &virsh::list_vms()?, &needle)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Print the interfaces of each VM. Failures are shown per VM and make
+/// the whole run an error after all VMs are shown.
+pub fn show(probe_mgr: &ProbeManager, vms: &[String]) -> Result<()> {
+    let mut failed = 0;
+    for vm in vms {
+        println!("\n{}", vm);
+        match network::view(probe_mgr, vm) {
+            Ok(view) => {
+                match view.addresses {
+                    AddressSource::Agent => {}
+                    AddressSource::Lease => println!("(no guest agent: addresses from DHCP leases, static ones are missing)"),
+                    AddressSource::None => println!("(not running: no addresses or counters)"),
+                }
+                print_header(false);
+                for iface in &view.interfaces {
+                    print_row(None, iface);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("  error: {:#}", e);
+                if let Some(hint) = hint_for(&e) {
+                    println!("  Hint: {}", hint);
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("could not read the network of {} VM(s)", failed);
+    }
+    Ok(())
+}
+
+/// Print every interface in `vms` that matches `needle`.
+pub fn find(probe_mgr: &ProbeManager, vms: &[String], needle: &Needle) -> Result<()> {
+    println!("Searching {} VM(s)...", vms.len());
+    let network::Search { found, failed } = network::find(probe_mgr, vms, needle);
+    if found.is_empty() {
+        println!("No interface matches.");
+    } else {
+        print_header(true);
+        for (vm, iface) in &found {
+            print_row(Some(vm), iface);
+        }
+    }
+    if !failed.is_empty() {
+        eprintln!("\nNot searched ({}):", failed.len());
+        for (vm, e) in &failed {
+            eprintln!("  {}: {:#}", vm, e);
+        }
+    }
+    Ok(())
+}
+
+fn print_header(with_vm: bool) {
+    if with_vm {
+        print!("{:20} ", "VM");
+    }
+    println!(
+        "{:17}  {:12} {:8} {:20} {:>10} {:>10}  Addresses",
+        "MAC", "Guest name", "Device", "Attached to", "RX", "TX"
+    );
+    println!("{}", "-".repeat(if with_vm { 126 } else { 105 }));
+}
+
+/// One line per interface; further addresses go on continuation lines.
+fn print_row(vm: Option<&String>, iface: &Interface) {
+    let bytes = |b: Option<u64>| b.map(|b| format_memory_kib(Some(b / 1024))).unwrap_or_else(|| "-".to_string());
+    let mut addrs = iface.ipv4.iter().chain(&iface.ipv6);
+    if let Some(vm) = vm {
+        print!("{:20} ", vm);
+    }
+    println!(
+        "{:17}  {:12} {:8} {:20} {:>10} {:>10}  {}",
+        if iface.mac.is_empty() { "-" } else { &iface.mac },
+        iface.guest_name.as_deref().unwrap_or("-"),
+        iface.device.as_deref().unwrap_or("-"),
+        iface.attached.as_deref().unwrap_or("(guest only)"),
+        bytes(iface.rx_bytes),
+        bytes(iface.tx_bytes),
+        addrs.next().map(String::as_str).unwrap_or("-")
+    );
+    let indent = if vm.is_some() { 106 } else { 85 };
+    for addr in addrs {
+        println!("{:indent$}{}", "", addr, indent = indent);
+    }
+}
@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use dismount_iso_qemu::agent;
+use dismount_iso_qemu::audit;
+use dismount_iso_qemu::guestpath::GuestPath;
+use dismount_iso_qemu::patch::{Outcome, PatchSpec};
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::virsh;
+
+use crate::cli::hint_for;
+use crate::cli::prompts::{confirm, prompt, select_vms};
+
+pub fn patch_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    let spec_path = prompt("Patch spec (TOML): ")?;
+    if spec_path.is_empty() {
+        return Ok(());
+    }
+    let spec = PatchSpec::load(Path::new(&spec_path))?;
+    println!("Patching {} ({} change(s)).", spec.path, spec.changes.len());
+
+    let vms = select_vms(probe_mgr)?;
+    if vms.is_empty() {
+        return Ok(());
+    }
+
+    let pending = run_patch(probe_mgr, &spec, &vms, true)?;
+    if pending == 0 || !confirm(&format!("Apply to {} VM(s)?", pending))? {
+        return Ok(());
+    }
+    run_patch(probe_mgr, &spec, &vms, false)?;
+    Ok(())
+}
+
+/// Apply `spec` to every VM and print one result line per VM. With
+/// `dry_run` nothing is written and the changes are shown as unified diffs.
+/// VMs whose agent blocks the file commands fail without being read.
+/// Returns how many VMs needed (or, when applying, received) the patch;
+/// conflicts and failures make the whole run an error after all VMs are tried.
+pub fn run_patch(probe_mgr: &ProbeManager, spec: &PatchSpec, vms: &[String], dry_run: bool) -> Result<usize> {
+    let mut changed = 0;
+    let mut failed = 0;
+    let mut hints = Vec::new();
+
+    if dry_run {
+        println!("\nDry run: nothing is written.");
+    }
+    for vm in vms {
+        let mut preview = None;
+        let result = (|| -> Result<Outcome> {
+            // A dry run checks writes too, so blocked VMs show up before the real run.
+            probe_mgr.require(vm, agent::FILE_READ)?;
+            probe_mgr.require(vm, agent::FILE_WRITE)?;
+            // Resolved per VM, since a selector may mix Windows and Linux guests.
+            let remote_path = GuestPath::for_vm(probe_mgr, vm, &spec.path)?;
+            let original = virsh::ga_read_file(vm, remote_path.as_str())?;
+            let Ok(text) = std::str::from_utf8(&original) else {
+                return Ok(Outcome::Conflict("file is not UTF-8 text".to_string()));
+            };
+            let outcome = spec.apply(text);
+            if let Outcome::Applied(updated) = &outcome {
+                if dry_run {
+                    preview = Some(diffy::create_patch(text, updated).to_string());
+                } else {
+                    audit::write_file(vm, remote_path.as_str(), Some(&original), updated.as_bytes())?;
+                }
+            }
+            Ok(outcome)
+        })();
+
+        match result {
+            Ok(Outcome::Applied(_)) => {
+                changed += 1;
+                println!("{:20} {}", vm, if dry_run { "would apply" } else { "applied" });
+                if let Some(diff) = preview {
+                    print!("{}", diff);
+                }
+            }
+            Ok(Outcome::AlreadyApplied) => println!("{:20} already applied", vm),
+            Ok(Outcome::Conflict(reason)) => {
+                failed += 1;
+                println!("{:20} conflict: {}", vm, reason);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("{:20} error: {:#}", vm, e);
+                if let Some(hint) = hint_for(&e)
+                    && !hints.contains(&hint)
+                {
+                    hints.push(hint);
+                }
+            }
+        }
+    }
+    for hint in hints {
+        println!("Hint: {}", hint);
+    }
+
+    let verb = if dry_run { "to patch" } else { "patched" };
+    println!("\n{} {}, {} unchanged, {} failed.", changed, verb, vms.len() - changed - failed, failed);
+    if failed > 0 && !dry_run {
+        bail!("patch failed on {} VM(s)", failed);
+    }
+    Ok(changed)
+}
@@ -0,0 +1,206 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+
+use dismount_iso_qemu::config;
+use dismount_iso_qemu::dryrun;
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::virsh;
+
+use crate::cli::flows::clock;
+use crate::cli::hint_for;
+use crate::cli::prompts::{confirm, prompt, select_vms};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    Start,
+    Shutdown,
+    Reboot,
+    ForceOff,
+    Suspend,
+    Resume,
+}
+
+impl PowerAction {
+    pub const ALL: [PowerAction; 6] = [
+        PowerAction::Start,
+        PowerAction::Shutdown,
+        PowerAction::Reboot,
+        PowerAction::ForceOff,
+        PowerAction::Suspend,
+        PowerAction::Resume,
+    ];
+
+    /// Parse the CLI spelling of an action.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "start" => Some(PowerAction::Start),
+            "shutdown" => Some(PowerAction::Shutdown),
+            "reboot" => Some(PowerAction::Reboot),
+            "force-off" | "destroy" => Some(PowerAction::ForceOff),
+            "suspend" => Some(PowerAction::Suspend),
+            "resume" => Some(PowerAction::Resume),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PowerAction::Start => "start",
+            PowerAction::Shutdown => "shutdown",
+            PowerAction::Reboot => "reboot",
+            PowerAction::ForceOff => "force-off",
+            PowerAction::Suspend => "suspend",
+            PowerAction::Resume => "resume",
+        }
+    }
+
+    /// The `virsh domstate` value that means the action has completed.
+    /// A reboot keeps the domain "running", so it completes immediately.
+    fn target_state(self) -> &'static str {
+        match self {
+            PowerAction::Start | PowerAction::Reboot | PowerAction::Resume => "running",
+            PowerAction::Shutdown | PowerAction::ForceOff => "shut off",
+            PowerAction::Suspend => "paused",
+        }
+    }
+}
+
+pub fn power_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    let vms = select_vms(probe_mgr)?;
+    if vms.is_empty() {
+        return Ok(());
+    }
+
+    println!("\n--- POWER ---");
+    for (i, action) in PowerAction::ALL.iter().enumerate() {
+        println!("{}) {}", i + 1, action.name());
+    }
+    println!("{}) Back", PowerAction::ALL.len() + 1);
+
+    let choice = prompt("Select option: ")?;
+    let Some(action) = choice
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| PowerAction::ALL.get(i).copied())
+    else {
+        return Ok(());
+    };
+
+    if action == PowerAction::ForceOff
+        && !confirm(&format!("Force off {} VM(s) without a clean shutdown?", vms.len()))?
+    {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    let wait = Duration::from_secs(config::get().timeouts.power_wait);
+    run_action(probe_mgr, action, &vms, wait)
+}
+
+/// Apply `action` to every VM, then wait up to `wait` for each to reach the
+/// target state. Fails if any VM rejected the action or timed out.
+pub fn run_action(probe_mgr: &ProbeManager, action: PowerAction, vms: &[String], wait: Duration) -> Result<()> {
+    let target = action.target_state();
+    let mut pending = Vec::new();
+    let mut failed = 0usize;
+
+    for vm in vms {
+        let state = virsh::domstate(vm).unwrap_or_default();
+        if state == target && action != PowerAction::Reboot {
+            println!("{}: already {}", vm, state);
+            continue;
+        }
+        match issue(probe_mgr, action, vm) {
+            Ok(()) => pending.push(vm.clone()),
+            Err(e) => {
+                eprintln!("{}: {} failed: {:#}", vm, action.name(), e);
+                if let Some(hint) = hint_for(&e) {
+                    eprintln!("Hint: {}", hint);
+                }
+                failed += 1;
+            }
+        }
+    }
+
+    if !dryrun::enabled() {
+        let timed_out = wait_for_state(&pending, target, wait);
+        failed += timed_out.len();
+        // A guest's clock stands still while it is paused.
+        if action == PowerAction::Resume && config::get().clock.check_after_resume {
+            let resumed: Vec<String> = pending.into_iter().filter(|vm| !timed_out.contains(vm)).collect();
+            if !resumed.is_empty() && !clock::report(probe_mgr, &resumed).is_empty() {
+                println!("Correct them with `clock --fix` or \"Clock drift\" in the menu.");
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{} of {} VM(s) did not complete {}", failed, vms.len(), action.name());
+    }
+    Ok(())
+}
+
+/// Send the action to one VM. Shutdown and reboot go through the guest agent
+/// when it is known to answer and fall back to ACPI otherwise.
+fn issue(probe_mgr: &ProbeManager, action: PowerAction, vm: &str) -> Result<()> {
+    match action {
+        PowerAction::Start => virsh::start(vm)?,
+        PowerAction::Shutdown | PowerAction::Reboot => {
+            let send = if action == PowerAction::Shutdown { virsh::shutdown } else { virsh::reboot };
+            if probe_mgr.agent_responds(vm) {
+                println!("{}: {} via guest agent", vm, action.name());
+                match send(vm, "agent") {
+                    Ok(()) => return Ok(()),
+                    Err(e) => eprintln!("{}: agent {} failed ({}), falling back to ACPI", vm, action.name(), e),
+                }
+            }
+            println!("{}: {} via ACPI", vm, action.name());
+            send(vm, "acpi")?;
+        }
+        PowerAction::ForceOff => virsh::destroy(vm)?,
+        PowerAction::Suspend => virsh::suspend(vm)?,
+        PowerAction::Resume => virsh::resume(vm)?,
+    }
+    Ok(())
+}
+
+/// Poll `virsh domstate` until every VM reports `target` or `wait` elapses.
+/// Returns the VMs that timed out.
+fn wait_for_state(vms: &[String], target: &str, wait: Duration) -> Vec<String> {
+    let mut pending: Vec<String> = vms.to_vec();
+    let started = Instant::now();
+
+    while !pending.is_empty() {
+        pending.retain(|vm| match virsh::domstate(vm) {
+            Ok(state) if state == target => {
+                println!("\r{}: {} after {}s{:20}", vm, state, started.elapsed().as_secs(), "");
+                false
+            }
+            _ => true,
+        });
+        if pending.is_empty() {
+            break;
+        }
+        if started.elapsed() >= wait {
+            println!();
+            for vm in &pending {
+                eprintln!("{}: timed out after {}s waiting for '{}'", vm, wait.as_secs(), target);
+            }
+            break;
+        }
+        print!(
+            "\rWaiting for {} VM(s) to reach '{}'... {}s/{}s",
+            pending.len(),
+            target,
+            started.elapsed().as_secs(),
+            wait.as_secs()
+        );
+        let _ = std::io::stdout().flush();
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    pending
+}
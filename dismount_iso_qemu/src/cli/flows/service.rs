@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use dismount_iso_qemu::agent;
+use dismount_iso_qemu::audit::{self, AuditRecord};
+use dismount_iso_qemu::config;
+use dismount_iso_qemu::guestpath::Flavor;
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::utils::os_family;
+use dismount_iso_qemu::winsvc::{self, Action};
+
+use crate::cli::prompts::{prompt, select_vm};
+
+pub fn service_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    let Some(vm) = select_vm(probe_mgr)? else {
+        return Ok(());
+    };
+    manage_services(probe_mgr, &vm)
+}
+
+/// True when the probed OS of `vm` is Windows.
+pub fn is_windows(probe_mgr: &ProbeManager, vm: &str) -> bool {
+    Flavor::of(probe_mgr, vm) == Some(Flavor::Windows)
+}
+
+/// List the services of one Windows VM and start, stop or restart one.
+pub fn manage_services(probe_mgr: &ProbeManager, vm: &str) -> Result<()> {
+    match probe_mgr.get_os(vm)? {
+        Some(os) if os_family(&os) == "windows" => {}
+        Some(os) => bail!("'{}' runs {}; service management needs a Windows guest", vm, os),
+        None => bail!("cannot tell which OS '{}' runs; service management needs a Windows guest", vm),
+    }
+
+    probe_mgr.require(vm, agent::EXEC)?;
+
+    println!("Listing services in {}...", vm);
+    let services = winsvc::list(vm)?;
+    let filter = prompt("Filter (substring of name or display name, empty for all): ")?.to_lowercase();
+    let shown: Vec<&winsvc::Service> = services
+        .iter()
+        .filter(|s| filter.is_empty() || s.name.to_lowercase().contains(&filter) || s.display_name.to_lowercase().contains(&filter))
+        .collect();
+    if shown.is_empty() {
+        println!("No services match.");
+        return Ok(());
+    }
+
+    println!("\n{:32} {:14} {:10} Display name", "Service", "State", "Start type");
+    println!("{}", "-".repeat(100));
+    for s in &shown {
+        println!("{:32} {:14} {:10} {}", s.name, s.state, s.start_type, s.display_name);
+    }
+
+    let name = prompt("\nService name (empty to go back): ")?;
+    if name.is_empty() {
+        return Ok(());
+    }
+    let Some(service) = services.iter().find(|s| s.name.eq_ignore_ascii_case(&name)) else {
+        bail!("no service named '{}' in '{}'", name, vm);
+    };
+
+    println!("1) Start");
+    println!("2) Stop");
+    println!("3) Restart");
+    println!("4) Back");
+    let action = match prompt("Select action: ")?.as_str() {
+        "1" => Action::Start,
+        "2" => Action::Stop,
+        "3" => Action::Restart,
+        _ => return Ok(()),
+    };
+    control(vm, &service.name, action)?;
+    println!(
+        "{}: service '{}' is {}.",
+        vm,
+        service.name,
+        if action == Action::Stop { "stopped" } else { "running" }
+    );
+    Ok(())
+}
+
+/// Start, stop or restart a service and wait for it to settle, audited as
+/// `service-<action>`.
+fn control(vm: &str, name: &str, action: Action) -> Result<()> {
+    let wait = Duration::from_secs(config::get().timeouts.service_wait);
+    let doing = match action {
+        Action::Start => "Starting",
+        Action::Stop => "Stopping",
+        Action::Restart => "Restarting",
+    };
+    println!("{} '{}' (waiting up to {}s per step)...", doing, name, wait.as_secs());
+    let rec = AuditRecord::new(vm, &format!("service-{}", action.as_str()), Some(name));
+    audit::run(rec, || winsvc::control(vm, name, action, wait))
+}
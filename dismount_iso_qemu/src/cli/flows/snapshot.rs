@@ -0,0 +1,162 @@
+use anyhow::Result;
+
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::utils::{format_unix_time, parse_snapshot_xml, SnapshotInfo};
+use dismount_iso_qemu::virsh;
+
+use crate::cli::prompts::{confirm, prompt, select_vm};
+use crate::cli::report_error;
+
+pub fn snapshot_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    let Some(vm) = select_vm(probe_mgr)? else {
+        return Ok(());
+    };
+
+    loop {
+        let snapshots = load_snapshots(&vm)?;
+        print_snapshots(&vm, &snapshots);
+
+        println!("\n--- SNAPSHOTS: {} ---", vm);
+        println!("1) Create snapshot");
+        println!("2) Revert to snapshot");
+        println!("3) Delete snapshot");
+        println!("4) Export snapshot metadata");
+        println!("5) Back");
+
+        let result = match prompt("Select option: ")?.as_str() {
+            "1" => create(&vm),
+            "2" => revert(&vm, &snapshots),
+            "3" => delete(&vm, &snapshots),
+            "4" => export(&vm, &snapshots),
+            "5" => break,
+            _ => {
+                println!("Invalid option");
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            report_error(&e);
+        }
+    }
+    Ok(())
+}
+
+/// Fetch and parse metadata for every snapshot of `vm`.
+/// Snapshots whose XML cannot be read are still listed by name.
+fn load_snapshots(vm: &str) -> Result<Vec<SnapshotInfo>> {
+    let names = virsh::snapshot_list(vm)?;
+    let snapshots = names
+        .into_iter()
+        .map(|name| {
+            virsh::snapshot_dumpxml(vm, &name)
+                .ok()
+                .and_then(|xml| parse_snapshot_xml(&xml))
+                .unwrap_or(SnapshotInfo {
+                    name,
+                    description: None,
+                    state: None,
+                    parent: None,
+                    creation_time: None,
+                })
+        })
+        .collect();
+    Ok(snapshots)
+}
+
+fn print_snapshots(vm: &str, snapshots: &[SnapshotInfo]) {
+    if snapshots.is_empty() {
+        println!("\nVM '{}' has no snapshots.", vm);
+        return;
+    }
+    println!("\n{:24} {:20} {:12} {:24} Description", "Snapshot", "Created (UTC)", "State", "Parent");
+    println!("{}", "-".repeat(110));
+    for s in snapshots {
+        let created = s.creation_time.map(format_unix_time).unwrap_or_else(|| "(unknown)".to_string());
+        println!(
+            "{:24} {:20} {:12} {:24} {}",
+            s.name,
+            created,
+            s.state.as_deref().unwrap_or("(unknown)"),
+            s.parent.as_deref().unwrap_or("-"),
+            s.description.as_deref().unwrap_or("")
+        );
+    }
+}
+
+/// Ask for a snapshot name and check it against the loaded list.
+fn pick_snapshot(snapshots: &[SnapshotInfo]) -> Result<Option<&SnapshotInfo>> {
+    if snapshots.is_empty() {
+        println!("No snapshots to choose from.");
+        return Ok(None);
+    }
+    let name = prompt("Snapshot name: ")?;
+    match snapshots.iter().find(|s| s.name == name) {
+        Some(s) => Ok(Some(s)),
+        None => {
+            println!("Snapshot '{}' not found.", name);
+            Ok(None)
+        }
+    }
+}
+
+fn create(vm: &str) -> Result<()> {
+    let name = prompt("New snapshot name: ")?;
+    if name.is_empty() {
+        println!("Snapshot name must not be empty.");
+        return Ok(());
+    }
+    let description = prompt("Description (optional): ")?;
+    let kind = prompt("Type: 1) full  2) disk-only [1]: ")?;
+    let disk_only = match kind.as_str() {
+        "" | "1" => false,
+        "2" => true,
+        _ => {
+            println!("Invalid type.");
+            return Ok(());
+        }
+    };
+
+    virsh::snapshot_create(vm, &name, &description, disk_only)?;
+    println!("Snapshot '{}' created.", name);
+    Ok(())
+}
+
+fn revert(vm: &str, snapshots: &[SnapshotInfo]) -> Result<()> {
+    let Some(snap) = pick_snapshot(snapshots)? else {
+        return Ok(());
+    };
+    if !confirm(&format!("Revert '{}' to '{}'? Current state will be lost", vm, snap.name))? {
+        println!("Aborted.");
+        return Ok(());
+    }
+    virsh::snapshot_revert(vm, &snap.name)?;
+    println!("Reverted '{}' to '{}'.", vm, snap.name);
+    Ok(())
+}
+
+fn delete(vm: &str, snapshots: &[SnapshotInfo]) -> Result<()> {
+    let Some(snap) = pick_snapshot(snapshots)? else {
+        return Ok(());
+    };
+    if !confirm(&format!("Delete snapshot '{}' of '{}'?", snap.name, vm))? {
+        println!("Aborted.");
+        return Ok(());
+    }
+    virsh::snapshot_delete(vm, &snap.name)?;
+    println!("Snapshot '{}' deleted.", snap.name);
+    Ok(())
+}
+
+fn export(vm: &str, snapshots: &[SnapshotInfo]) -> Result<()> {
+    let Some(snap) = pick_snapshot(snapshots)? else {
+        return Ok(());
+    };
+    let default_name = format!("{}_{}.xml", vm.replace('/', "_"), snap.name);
+    let local_raw = prompt(&format!("Local file [{}]: ", default_name))?;
+    let local_path = if local_raw.is_empty() { default_name } else { local_raw };
+
+    let xml = virsh::snapshot_dumpxml(vm, &snap.name)?;
+    std::fs::write(&local_path, xml)?;
+    println!("Snapshot metadata written to {}", local_path);
+    Ok(())
+}
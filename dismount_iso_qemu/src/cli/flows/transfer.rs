@@ -0,0 +1,155 @@
+use std::fs::{File, OpenOptions};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use dismount_iso_qemu::agent;
+use dismount_iso_qemu::audit::{self, AuditRecord};
+use dismount_iso_qemu::guestpath::GuestPath;
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::transfer::{self, Event};
+use dismount_iso_qemu::utils::{format_memory_kib, resolve_local_path, sha256_file};
+
+use crate::cli::prompts::{confirm, prompt, select_vm};
+
+pub fn transfer_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    println!("\n--- TRANSFER FILES ---");
+    println!("1) Download a file from a VM");
+    println!("2) Upload a file to a VM");
+    println!("3) Back");
+    let choice = prompt("Select option: ")?;
+    if !matches!(choice.as_str(), "1" | "2") {
+        return Ok(());
+    }
+    let Some(vm) = select_vm(probe_mgr)? else {
+        return Ok(());
+    };
+
+    if choice == "1" {
+        probe_mgr.require(&vm, agent::FILE_READ)?;
+        let remote_raw = prompt("Path inside VM: ")?;
+        if remote_raw.is_empty() {
+            return Ok(());
+        }
+        let remote = GuestPath::for_vm(probe_mgr, &vm, &remote_raw)?;
+        let local = resolve_local_path(&prompt("Save to (Linux path, empty for the same file name): ")?, &remote)?;
+        let part = part_path(&local);
+        let resume = match std::fs::metadata(&part) {
+            Ok(m) => confirm(&format!("Resume the partial download in {} ({} bytes)?", part.display(), m.len()))?,
+            Err(_) => false,
+        };
+        pull(&vm, &remote, &local, resume)
+    } else {
+        probe_mgr.require(&vm, agent::FILE_WRITE)?;
+        let local = PathBuf::from(prompt("Local file to upload: ")?);
+        let remote_raw = prompt("Path inside VM: ")?;
+        if remote_raw.is_empty() {
+            return Ok(());
+        }
+        let remote = GuestPath::for_vm(probe_mgr, &vm, &remote_raw)?;
+        if !confirm(&format!("Replace {} in '{}' with {}?", remote, vm, local.display()))? {
+            return Ok(());
+        }
+        push(&vm, &local, &remote)
+    }
+}
+
+/// `<local>.part`, where a download is written until it is complete.
+fn part_path(local: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.part", local.display()))
+}
+
+/// Download `remote` from `vm` to `local`. The data goes to `<local>.part`
+/// first, which is kept if the transfer fails so that `resume` can carry on
+/// from its end later.
+pub fn pull(vm: &str, remote: &GuestPath, local: &Path, resume: bool) -> Result<()> {
+    let part = part_path(local);
+    let offset = if resume { std::fs::metadata(&part).map(|m| m.len()).unwrap_or(0) } else { 0 };
+    let file = OpenOptions::new()
+        .create(true)
+        .append(resume)
+        .write(true)
+        .truncate(!resume)
+        .open(&part)
+        .with_context(|| format!("opening {}", part.display()))?;
+    if offset > 0 {
+        println!("Resuming at byte {}.", offset);
+    }
+
+    let mut progress = Progress::default();
+    let result = transfer::download(vm, remote.as_str(), &mut BufWriter::new(file), offset, &mut |e| progress.report(e));
+    progress.finish();
+    let size = match result {
+        Ok(size) => size,
+        Err(e) => {
+            match std::fs::metadata(&part) {
+                Ok(m) if m.len() > 0 => eprintln!("The partial download is kept in {}; run again to resume.", part.display()),
+                _ => {
+                    let _ = std::fs::remove_file(&part);
+                }
+            }
+            return Err(e.into());
+        }
+    };
+    std::fs::rename(&part, local).with_context(|| format!("renaming {}", part.display()))?;
+    println!("Downloaded {} ({} bytes) to {}.", remote, size, local.display());
+    Ok(())
+}
+
+/// Upload `local` to `remote` in `vm`, audited as `write-file`.
+pub fn push(vm: &str, local: &Path, remote: &GuestPath) -> Result<()> {
+    let mut file = File::open(local).with_context(|| format!("opening {}", local.display()))?;
+    let mut rec = AuditRecord::new(vm, "write-file", Some(remote.as_str()));
+    rec.sha256_after = Some(sha256_file(local)?);
+
+    let mut progress = Progress::default();
+    let result = audit::run(rec, || transfer::upload(vm, remote.as_str(), &mut file, &mut |e| progress.report(e)));
+    progress.finish();
+    let size = result?;
+    println!("Uploaded {} ({} bytes) to {} in '{}'.", local.display(), size, remote, vm);
+    Ok(())
+}
+
+/// Progress line on stderr, redrawn when the percentage (or, for files of
+/// unknown size, the MiB count) changes.
+#[derive(Default)]
+struct Progress {
+    last: Option<u64>,
+}
+
+impl Progress {
+    fn report(&mut self, event: Event) {
+        match event {
+            Event::Progress { done, total } => {
+                let step = match total {
+                    Some(t) if t > 0 => done * 100 / t,
+                    _ => done >> 20,
+                };
+                if self.last == Some(step) {
+                    return;
+                }
+                self.last = Some(step);
+                let done_text = format_memory_kib(Some(done / 1024));
+                match total {
+                    Some(t) => eprint!("\r{} / {} ({}%)   ", done_text, format_memory_kib(Some(t / 1024)), step),
+                    None => eprint!("\r{}   ", done_text),
+                }
+            }
+            Event::Retry { attempt, delay, error } => {
+                if self.last.is_some() {
+                    eprintln!();
+                }
+                eprintln!("{}; retry {} in {}s", error, attempt, delay.as_secs());
+                self.last = None;
+            }
+        }
+    }
+
+    fn finish(&self) {
+        if self.last.is_some() {
+            eprintln!();
+        }
+    }
+}
+
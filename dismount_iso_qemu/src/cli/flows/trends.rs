@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use dismount_iso_qemu::config;
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::selector::Selector;
+use dismount_iso_qemu::trends::{self, WINDOWS};
+use dismount_iso_qemu::utils::{format_memory_kib, format_seconds_dhms, unix_now};
+use dismount_iso_qemu::virsh;
+
+use crate::cli::prompts::{prompt, select_vms};
+
+/// Buckets per sparkline.
+const BUCKETS: usize = 24;
+
+pub fn trends_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    println!("\n--- RESOURCE TRENDS ---");
+    println!("1) Show trends");
+    println!("2) Take a sample of every VM now");
+    println!("3) Back");
+    match prompt("Select option: ")?.as_str() {
+        "1" => {
+            let vms = select_vms(probe_mgr)?;
+            print_trends(&vms);
+            Ok(())
+        }
+        "2" => {
+            let round = trends::record(&virsh::list_vms()?);
+            report_round(&round);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Print memory and CPU trends of each VM over the 1h, 24h and 7d windows,
+/// with a right-sizing hint when the VM uses far less than its maximum.
+pub fn print_trends(vms: &[String]) {
+    let now = unix_now();
+    let (_, longest) = WINDOWS[WINDOWS.len() - 1];
+    for vm in vms {
+        let samples = match trends::load(vm, now.saturating_sub(longest)) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("\n{}: {:#}", vm, e);
+                continue;
+            }
+        };
+        let Some(last) = samples.last() else {
+            println!("\n{}: no samples yet", vm);
+            continue;
+        };
+        println!("\n{} (max memory {})", vm, format_memory_kib(last.max_kib));
+        println!(
+            "  {:6} {:>7} {:>10} {:>10}  {:w$}  {:>7}  CPU",
+            "Window", "Samples", "Mem avg", "Mem peak", "Memory", "CPU avg", w = BUCKETS
+        );
+        for (label, secs) in WINDOWS {
+            // No more buckets than samples fit in, or every other one is a gap.
+            let buckets = (secs / config::get().history.interval).clamp(1, BUCKETS as u64) as usize;
+            let w = trends::summarize(&samples, now, secs, buckets);
+            if w.samples == 0 {
+                println!("  {:6} {:>7}", label, 0);
+                continue;
+            }
+            // Memory is drawn against the maximum so unused headroom shows.
+            let mem_scale = last.max_kib.or(w.mem_peak_kib).unwrap_or(0) as f64;
+            let cpu_scale = w.cpu.iter().flatten().fold(0.0, |a: f64, &b| a.max(b));
+            println!(
+                "  {:6} {:>7} {:>10} {:>10}  {:w$}  {:>7}  {}",
+                label,
+                w.samples,
+                format_memory_kib(w.mem_avg_kib),
+                format_memory_kib(w.mem_peak_kib),
+                trends::sparkline(&w.mem, mem_scale),
+                w.cpu_avg.map(|c| format!("{:.2}", c)).unwrap_or_else(|| "-".to_string()),
+                trends::sparkline(&w.cpu, cpu_scale),
+                w = BUCKETS
+            );
+        }
+        if !samples.iter().any(|s| s.balloon) {
+            println!("  (memory is what libvirt allotted; the guest reports no balloon statistics)");
+            continue;
+        }
+
+        // Only suggest a size from at least a day of guest-reported use.
+        let week = trends::summarize(&samples, now, longest, BUCKETS);
+        if week.span >= 86_400
+            && let (Some(peak), Some(max)) = (week.mem_peak_kib, last.max_kib)
+            && peak * 2 <= max
+        {
+            println!(
+                "  Peak use over {} is {}% of max memory; {} would leave 25% headroom.",
+                format_seconds_dhms(week.span),
+                peak * 100 / max,
+                format_memory_kib(Some(trends::suggested_max_kib(peak)))
+            );
+        }
+    }
+}
+
+fn report_round(round: &trends::Round) {
+    println!("Recorded {} sample(s); {} VM(s) not running.", round.recorded, round.stopped);
+    for (vm, e) in &round.failed {
+        eprintln!("  {}: {:#}", vm, e);
+    }
+}
+
+/// Sample the VMs matched by `expr` every `[history] interval` seconds,
+/// or once with `once`. The selector is resolved each round so new VMs
+/// are picked up; old samples are pruned at start and then daily.
+pub fn run_sampler(probe_mgr: &ProbeManager, expr: &str, once: bool) -> Result<()> {
+    let selector = Selector::parse(expr)?;
+    let interval = Duration::from_secs(config::get().history.interval);
+    let mut pruned: Option<Instant> = None;
+    if !once {
+        eprintln!("Sampling '{}' every {}s into {}", expr, interval.as_secs(), trends::dir()?.display());
+    }
+    loop {
+        let started = Instant::now();
+        if pruned.is_none_or(|t| t.elapsed() >= Duration::from_secs(86_400)) {
+            match trends::prune() {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Pruned {} old sample(s).", n),
+                Err(e) => eprintln!("Warning: {:#}", e),
+            }
+            pruned = Some(started);
+        }
+        match selector.resolve(probe_mgr) {
+            Ok(vms) => {
+                let round = trends::record(&vms);
+                if once {
+                    report_round(&round);
+                } else {
+                    for (vm, e) in &round.failed {
+                        eprintln!("Warning: {}: {:#}", vm, e);
+                    }
+                }
+            }
+            Err(e) if once => return Err(e),
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+        if once {
+            return Ok(());
+        }
+        std::thread::sleep(interval.saturating_sub(started.elapsed()));
+    }
+}
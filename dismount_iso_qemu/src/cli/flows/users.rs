@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+
+use dismount_iso_qemu::agent;
+use dismount_iso_qemu::audit::{self, AuditRecord};
+use dismount_iso_qemu::config;
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::utils::format_unix_time;
+
+use crate::cli::hint_for;
+use crate::cli::prompts::{prompt, prompt_password, select_vms};
+
+pub fn users_flow(probe_mgr: &ProbeManager) -> Result<()> {
+    println!("\n--- GUEST USERS ---");
+    println!("1) List logged-in users");
+    println!("2) Reset a local account password");
+    println!("3) Back");
+    match prompt("Select option: ")?.as_str() {
+        "1" => {
+            let vms = select_vms(probe_mgr)?;
+            list_users(probe_mgr, &vms);
+            Ok(())
+        }
+        "2" => reset_password(probe_mgr),
+        _ => Ok(()),
+    }
+}
+
+/// Print the users logged into each VM. Failures are shown per VM.
+fn list_users(probe_mgr: &ProbeManager, vms: &[String]) {
+    let timeout = config::get().timeouts.probe;
+    let mut hints = Vec::new();
+
+    println!("\n{:20} {:24} {:16} Login time (UTC)", "VM", "User", "Domain");
+    println!("{}", "-".repeat(84));
+    for vm in vms {
+        let users = probe_mgr
+            .require(vm, &["guest-get-users"])
+            .and_then(|()| agent::guest_get_users(vm, timeout));
+        match users {
+            Ok(users) if users.is_empty() => println!("{:20} (nobody logged in)", vm),
+            Ok(users) => {
+                for u in users {
+                    println!(
+                        "{:20} {:24} {:16} {}",
+                        vm,
+                        u.user,
+                        u.domain.as_deref().unwrap_or("-"),
+                        format_unix_time(u.login_time as u64)
+                    );
+                }
+            }
+            Err(e) => {
+                println!("{:20} {}", vm, e.short());
+                if let Some(hint) = e.hint()
+                    && !hints.contains(&hint)
+                {
+                    hints.push(hint);
+                }
+            }
+        }
+    }
+    for hint in hints {
+        println!("Hint: {}", hint);
+    }
+}
+
+/// Set the same password for one local account on every selected VM.
+fn reset_password(probe_mgr: &ProbeManager) -> Result<()> {
+    let vms = select_vms(probe_mgr)?;
+    if vms.is_empty() {
+        return Ok(());
+    }
+    let user = prompt("Local account name: ")?;
+    if user.is_empty() {
+        return Ok(());
+    }
+    // Check every VM before asking for the password.
+    for vm in &vms {
+        probe_mgr.require(vm, &["guest-set-user-password"])?;
+    }
+    let password = prompt_password("New password: ")?;
+    if password.is_empty() {
+        bail!("empty password; nothing changed");
+    }
+    if prompt_password("Repeat password: ")? != password {
+        bail!("passwords do not match; nothing changed");
+    }
+
+    let timeout = config::get().timeouts.agent_file;
+    let mut failed = 0;
+    for vm in &vms {
+        // The record names the account only; the password is never logged.
+        let rec = AuditRecord::new(vm, "set-password", Some(&user));
+        match audit::run(rec, || agent::guest_set_user_password(vm, &user, &password, timeout)) {
+            Ok(()) => println!("{}: password for '{}' updated", vm, user),
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: password reset failed: {:#}", vm, e);
+                if let Some(hint) = hint_for(&e) {
+                    eprintln!("Hint: {}", hint);
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("password reset failed on {} of {} VM(s)", failed, vms.len());
+    }
+    Ok(())
+}
@@ -1,69 +1,62 @@
 use std::sync::Arc;
+
 use anyhow::Result;
 
-use crate::probe::ProbeManager;
+use dismount_iso_qemu::config;
+use dismount_iso_qemu::dryrun;
+use dismount_iso_qemu::error::{self, Error};
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::utils::{format_memory_kib, format_seconds_dhms, parse_cpu_time_to_seconds, parse_dominfo, DomInfo};
+use dismount_iso_qemu::virsh;
+
+use crate::cli::flows::backup::backup_flow;
+use crate::cli::flows::cdrom::cdrom_flow;
+use crate::cli::flows::clock::clock_flow;
+use crate::cli::flows::domxml::domxml_flow;
+use crate::cli::flows::history::history_flow;
+use crate::cli::flows::isos::{iso_report_flow, scan_mounted_flow};
+use crate::cli::flows::livesync::live_sync_flow;
 use crate::cli::flows::modify::modify_file_flow;
+use crate::cli::flows::network::network_flow;
+use crate::cli::flows::patch::patch_flow;
+use crate::cli::flows::power::power_flow;
+use crate::cli::flows::service::service_flow;
+use crate::cli::flows::snapshot::snapshot_flow;
+use crate::cli::flows::transfer::transfer_flow;
+use crate::cli::flows::trends::trends_flow;
+use crate::cli::flows::users::users_flow;
+use crate::cli::report_error;
 
 pub fn run(probe_mgr: Arc<ProbeManager>) -> Result<()> {
     loop {
         // Display VM status table before menu
-        match crate::virsh::list_vms() {
-            Ok(vms) => {
-                if vms.is_empty() {
-                    println!("\nNo VMs found.");
-                    println!("Make sure libvirt is running and you have VMs defined.");
-                    println!("Try: virsh list --all\n");
-                } else {
-                    println!("\n{:20} {:40} {:24} {}", "VM", "OS", "Memory (used/max)", "CPU time");
-                    println!("{}", "-".repeat(110));
-                    for vm in &vms {
-                        // OS probe (cached by ProbeManager)
-                        let os = match probe_mgr.get_os(vm) {
-                            Ok(Some(s)) => s,
-                            Ok(None) => "(unknown)".to_string(),
-                            Err(e) => format!("error: {}", e),
-                        };
-
-                        // dominfo probe (raw virsh output -> parsed DomInfo)
-                        let dominfo = match crate::virsh::dominfo_raw(vm) {
-                            Ok(raw) => crate::utils::parse_dominfo(&raw),
-                            Err(_) => crate::utils::DomInfo { max_memory_mb: None, used_memory_mb: None, cpu_time: None },
-                        };
-
-                        // Memory formatting
-                        let mem_used = crate::utils::format_memory_kib(dominfo.used_memory_mb);
-                        let mem_max = crate::utils::format_memory_kib(dominfo.max_memory_mb);
-                        let mem = if mem_used != "(unknown)" && mem_max != "(unknown)" {
-                            format!("{} / {}", mem_used, mem_max)
-                        } else if mem_used != "(unknown)" {
-                            mem_used
-                        } else if mem_max != "(unknown)" {
-                            mem_max
-                        } else {
-                            "(unknown)".to_string()
-                        };
-
-                        // CPU time
-                        let cpu = dominfo.cpu_time
-                            .as_deref()
-                            .and_then(|s| crate::utils::parse_cpu_time_to_seconds(s))
-                            .map(|secs| crate::utils::format_seconds_dhms(secs))
-                            .unwrap_or_else(|| dominfo.cpu_time.clone().unwrap_or_else(|| "(unknown)".to_string()));
-
-                        println!("{:20} {:40} {:24} {}", vm, os, mem, cpu);
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("Warning: failed to list VMs: {}", e);
-            }
-        }
+        print_status_table(&probe_mgr);
 
-        println!("\n--- MENU ---");
+        if dryrun::enabled() {
+            println!("\n--- MENU (DRY RUN: changes are printed, not made) ---");
+        } else {
+            println!("\n--- MENU ---");
+        }
         println!("1) Mount ISO");
         println!("2) Scan mounted ISOs");
         println!("3) Modify file in VM");
-        println!("4) Exit");
+        println!("4) Manage snapshots");
+        println!("5) Power management");
+        println!("6) Audit history");
+        println!("7) Patch files across VMs");
+        println!("8) Windows services");
+        println!("9) Guest users and passwords");
+        println!("10) ISO library report");
+        println!("11) CD-ROM devices");
+        println!("12) Edit domain XML");
+        println!("13) Live-sync file in VM");
+        println!("14) Backups");
+        println!("15) Transfer files");
+        println!("16) Network");
+        println!("17) Resource trends");
+        println!("18) Clock drift");
+        println!("19) Dry run: {} (toggle)", if dryrun::enabled() { "on" } else { "off" });
+        println!("20) Exit");
         print!("Select option: ");
         std::io::Write::flush(&mut std::io::stdout())?;
 
@@ -73,22 +66,229 @@ pub fn run(probe_mgr: Arc<ProbeManager>) -> Result<()> {
         match input.trim() {
             "1" => println!("Mount ISO not implemented."),
             "2" => {
-                let vms = crate::virsh::list_vms()?;
-                for vm in vms {
-                    match probe_mgr.get_os(&vm) {
-                        Ok(Some(os)) => println!("{:20} {}", vm, os),
-                        _ => println!("{:20} (unknown)", vm),
-                    }
+                if let Err(e) = scan_mounted_flow() {
+                    report_error(&e);
                 }
             }
             "3" => {
-                if let Err(e) = modify_file_flow() {
-                    eprintln!("Error: {}", e);
+                if let Err(e) = modify_file_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "4" => {
+                if let Err(e) = snapshot_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "5" => {
+                if let Err(e) = power_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "6" => {
+                if let Err(e) = history_flow() {
+                    report_error(&e);
+                }
+            }
+            "7" => {
+                if let Err(e) = patch_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "8" => {
+                if let Err(e) = service_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "9" => {
+                if let Err(e) = users_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "10" => {
+                if let Err(e) = iso_report_flow() {
+                    report_error(&e);
+                }
+            }
+            "11" => {
+                if let Err(e) = cdrom_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "12" => {
+                if let Err(e) = domxml_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "13" => {
+                if let Err(e) = live_sync_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "14" => {
+                if let Err(e) = backup_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "15" => {
+                if let Err(e) = transfer_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "16" => {
+                if let Err(e) = network_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "17" => {
+                if let Err(e) = trends_flow(&probe_mgr) {
+                    report_error(&e);
+                }
+            }
+            "18" => {
+                if let Err(e) = clock_flow(&probe_mgr) {
+                    report_error(&e);
                 }
             }
-            "4" => break,
+            "19" => dryrun::set(!dryrun::enabled()),
+            "20" => break,
             _ => println!("Invalid option"),
         }
     }
     Ok(())
 }
+
+/// One probed line of the status table.
+struct StatusRow {
+    vm: String,
+    os: String,
+    /// qemu-guest-agent version, "-" when the agent did not answer.
+    agent: String,
+    mem: String,
+    cpu: String,
+    /// Remediation for a failed probe, printed once below the table.
+    hint: Option<&'static str>,
+}
+
+/// Print the VM table. Each host is listed and probed on its own thread so a
+/// slow or unreachable hypervisor does not hold up the others; with `[hosts]`
+/// configured a Host column is added and unreachable hosts get a status row.
+fn print_status_table(probe_mgr: &ProbeManager) {
+    let hosts = config::get().host_names();
+    let results: Vec<(Option<String>, error::Result<Vec<StatusRow>>)> = std::thread::scope(|s| {
+        let handles: Vec<_> = hosts
+            .into_iter()
+            .map(|host| {
+                s.spawn(move || {
+                    let rows = virsh::list_vms_on(host.as_deref(), &[])
+                        .map(|vms| vms.iter().map(|vm| probe_row(probe_mgr, vm)).collect());
+                    (host, rows)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let multi_host = !config::get().hosts.is_empty();
+    if !multi_host {
+        match results.into_iter().next().map(|(_, r)| r) {
+            Some(Ok(rows)) if !rows.is_empty() => {
+                println!("\n{:20} {:40} {:10} {:24} CPU time", "VM", "OS", "Agent", "Memory (used/max)");
+                println!("{}", "-".repeat(121));
+                for r in &rows {
+                    println!("{:20} {:40} {:10} {:24} {}", r.vm, r.os, r.agent, r.mem, r.cpu);
+                }
+                print_hints(rows.iter().filter_map(|r| r.hint));
+            }
+            Some(Err(e)) => {
+                eprintln!("Warning: failed to list VMs: {}", e);
+                print_hints(e.hint());
+            }
+            _ => {
+                println!("\nNo VMs found.");
+                println!("Make sure libvirt is running and you have VMs defined.");
+                println!("Try: virsh list --all\n");
+            }
+        }
+        return;
+    }
+
+    println!("\n{:12} {:20} {:40} {:10} {:24} CPU time", "Host", "VM", "OS", "Agent", "Memory (used/max)");
+    println!("{}", "-".repeat(134));
+    let mut hints = Vec::new();
+    for (host, result) in results {
+        let host = host.unwrap_or_default();
+        match result {
+            Ok(rows) if rows.is_empty() => println!("{:12} {:20} (no VMs defined)", host, "-"),
+            Ok(rows) => {
+                for r in rows {
+                    let vm = r.vm.split_once('/').map(|(_, n)| n).unwrap_or(&r.vm);
+                    println!("{:12} {:20} {:40} {:10} {:24} {}", host, vm, r.os, r.agent, r.mem, r.cpu);
+                    hints.extend(r.hint);
+                }
+            }
+            Err(e) => {
+                println!("{:12} {:20} {}", host, "-", e);
+                hints.extend(e.hint());
+            }
+        }
+    }
+    print_hints(hints);
+}
+
+/// Print each distinct hint once, in first-seen order.
+fn print_hints(hints: impl IntoIterator<Item = &'static str>) {
+    let mut seen: Vec<&str> = Vec::new();
+    for hint in hints {
+        if !seen.contains(&hint) {
+            println!("Hint: {}", hint);
+            seen.push(hint);
+        }
+    }
+}
+
+/// Probe OS and dominfo for one VM and format the table cells.
+fn probe_row(probe_mgr: &ProbeManager, vm: &str) -> StatusRow {
+    // OS probe (cached by ProbeManager)
+    let probe = probe_mgr.get_os(vm);
+    // guest-info was cached by the OS probe; skip it when the agent is down.
+    let agent = match probe {
+        Ok(_) => probe_mgr.agent_info(vm).map(|i| i.version).unwrap_or_else(|_| "-".to_string()),
+        Err(_) => "-".to_string(),
+    };
+    let (os, hint) = match probe {
+        Ok(Some(s)) => (s, None),
+        Ok(None) => ("(unknown)".to_string(), None),
+        // A shut-off VM is expected, not something to fix.
+        Err(Error::DomainNotRunning(_)) => ("(not running)".to_string(), None),
+        Err(e) => (e.short(), e.hint()),
+    };
+
+    // dominfo probe (raw virsh output -> parsed DomInfo)
+    let dominfo = match virsh::dominfo_raw(vm) {
+        Ok(raw) => parse_dominfo(&raw),
+        Err(_) => DomInfo { max_memory_mb: None, used_memory_mb: None, cpu_time: None },
+    };
+
+    // Memory formatting
+    let mem_used = format_memory_kib(dominfo.used_memory_mb);
+    let mem_max = format_memory_kib(dominfo.max_memory_mb);
+    let mem = if mem_used != "(unknown)" && mem_max != "(unknown)" {
+        format!("{} / {}", mem_used, mem_max)
+    } else if mem_used != "(unknown)" {
+        mem_used
+    } else if mem_max != "(unknown)" {
+        mem_max
+    } else {
+        "(unknown)".to_string()
+    };
+
+    // CPU time
+    let cpu = dominfo.cpu_time
+        .as_deref()
+        .and_then(parse_cpu_time_to_seconds)
+        .map(format_seconds_dhms)
+        .unwrap_or_else(|| dominfo.cpu_time.clone().unwrap_or_else(|| "(unknown)".to_string()));
+
+    StatusRow { vm: vm.to_string(), os, agent, mem, cpu, hint }
+}
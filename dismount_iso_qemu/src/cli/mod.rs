@@ -1,3 +1,19 @@
+pub mod commands;
 pub mod menu;
 pub mod prompts;
 pub mod flows;
+
+use dismount_iso_qemu::error::Error;
+
+/// Print an error and, when its cause is a known failure, how to fix it.
+pub fn report_error(e: &anyhow::Error) {
+    eprintln!("Error: {:#}", e);
+    if let Some(hint) = hint_for(e) {
+        eprintln!("Hint: {}", hint);
+    }
+}
+
+/// Remediation hint for the first structured error in `e`'s chain.
+pub fn hint_for(e: &anyhow::Error) -> Option<&'static str> {
+    e.chain().find_map(|c| c.downcast_ref::<Error>()).and_then(Error::hint)
+}
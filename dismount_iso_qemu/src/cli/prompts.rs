@@ -1,5 +1,8 @@
 use std::io::{self, Write};
 
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::selector::Selector;
+
 pub fn prompt(msg: &str) -> io::Result<String> {
     print!("{}", msg);
     io::stdout().flush()?;
@@ -7,3 +10,58 @@ pub fn prompt(msg: &str) -> io::Result<String> {
     io::stdin().read_line(&mut input)?;
     Ok(input.trim().to_string())
 }
+
+/// Prompt for a secret without echoing it to the terminal.
+pub fn prompt_password(msg: &str) -> io::Result<String> {
+    rpassword::prompt_password(msg)
+}
+
+/// Ask for a VM selector that must match exactly one VM.
+/// Returns `None` (after telling the user why) when there is nothing to act on.
+pub fn select_vm(probe_mgr: &ProbeManager) -> anyhow::Result<Option<String>> {
+    let expr = prompt("VM name or selector: ")?;
+    let mut vms = Selector::parse(&expr)?.resolve(probe_mgr)?;
+    match vms.len() {
+        0 => {
+            println!("No VMs match '{}'.", expr);
+            Ok(None)
+        }
+        1 => Ok(vms.pop()),
+        n => {
+            println!("'{}' matches {} VMs; this action works on a single VM.", expr, n);
+            Ok(None)
+        }
+    }
+}
+
+/// Ask for a VM selector (see `selector`) and resolve it. Bulk selections are
+/// shown and must be confirmed. Returns an empty list when nothing is selected.
+pub fn select_vms(probe_mgr: &ProbeManager) -> anyhow::Result<Vec<String>> {
+    let expr = prompt("VM name or selector (e.g. web-*, os:windows, tag:lab): ")?;
+    let selector = Selector::parse(&expr)?;
+    let vms = selector.resolve(probe_mgr)?;
+    if vms.is_empty() {
+        println!("No VMs match '{}'.", expr);
+        return Ok(vms);
+    }
+    if selector.is_bulk() && !confirm_selection(&vms)? {
+        println!("Aborted.");
+        return Ok(Vec::new());
+    }
+    Ok(vms)
+}
+
+/// List a matched set of VMs and ask before acting on them.
+pub fn confirm_selection(vms: &[String]) -> io::Result<bool> {
+    println!("Selected {} VM(s):", vms.len());
+    for vm in vms {
+        println!("  {}", vm);
+    }
+    confirm("Proceed?")
+}
+
+/// Ask a yes/no question; anything other than "y"/"yes" counts as no.
+pub fn confirm(msg: &str) -> io::Result<bool> {
+    let answer = prompt(&format!("{} [y/N]: ", msg))?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
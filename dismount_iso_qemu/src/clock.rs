@@ -0,0 +1,110 @@
+// src/clock.rs
+
+//! Guest clock drift: guest-get-time compared with the host clock, and
+//! correction with guest-set-time.
+//!
+//! The host clock is read just before and just after the agent call and
+//! the guest time is compared with the midpoint, so a measurement is off
+//! by at most half the round trip (reported as `rtt_ms`). A round trip
+//! through virsh takes tens of milliseconds, well below the drift that
+//! follows a host suspend or a migration.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use crate::agent;
+use crate::audit::{self, AuditRecord};
+use crate::config;
+use crate::error::{self, Error};
+use crate::probe::ProbeManager;
+use crate::utils::par_map;
+use crate::virsh;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Drift {
+    /// Guest clock in nanoseconds since the epoch.
+    pub guest_ns: i64,
+    /// Guest minus host, in milliseconds; positive when the guest is ahead.
+    pub offset_ms: i64,
+    /// Round trip of the agent call.
+    pub rtt_ms: u64,
+}
+
+impl Drift {
+    /// Drift of a guest clock read as `guest_ns` between host clock
+    /// readings `before` and `after`, all in nanoseconds since the epoch.
+    fn between(guest_ns: i64, before: i64, after: i64) -> Drift {
+        Drift {
+            guest_ns,
+            offset_ms: (guest_ns - (before + (after - before) / 2)) / 1_000_000,
+            rtt_ms: ((after - before) / 1_000_000) as u64,
+        }
+    }
+
+    pub fn exceeds(&self, threshold_ms: u64) -> bool {
+        self.offset_ms.unsigned_abs() > threshold_ms
+    }
+}
+
+fn host_ns() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i64).unwrap_or(0)
+}
+
+/// Measure how far the clock of `vm` is from the host clock.
+pub fn measure(probe_mgr: &ProbeManager, vm: &str) -> error::Result<Drift> {
+    if virsh::domstate(vm)? != "running" {
+        return Err(Error::DomainNotRunning(vm.to_string()));
+    }
+    probe_mgr.require(vm, agent::CLOCK_READ)?;
+    let before = host_ns();
+    let guest_ns = agent::guest_get_time(vm, config::get().timeouts.probe)?;
+    let after = host_ns();
+    Ok(Drift::between(guest_ns, before, after))
+}
+
+/// Measure every VM in `vms`, in order.
+pub fn check(probe_mgr: &ProbeManager, vms: &[String]) -> Vec<(String, error::Result<Drift>)> {
+    vms.iter().cloned().zip(par_map(vms, |vm| measure(probe_mgr, vm))).collect()
+}
+
+/// Set the clock of `vm` to the host clock, audited as `set-time`.
+pub fn correct(probe_mgr: &ProbeManager, vm: &str) -> Result<()> {
+    probe_mgr.require(vm, agent::CLOCK_SET)?;
+    let rec = AuditRecord::new(vm, "set-time", None);
+    audit::run(rec, || agent::guest_set_time(vm, host_ns(), config::get().timeouts.probe))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const S: i64 = 1_000_000_000;
+    const MS: i64 = 1_000_000;
+
+    #[test]
+    fn offset_against_midpoint() {
+        // (guest, before, after) -> (offset_ms, rtt_ms)
+        let cases = [
+            ((100 * S, 100 * S - 20 * MS, 100 * S + 20 * MS), (0, 40)),
+            ((105 * S, 100 * S, 100 * S + 40 * MS), (4_980, 40)),
+            ((98 * S, 100 * S, 100 * S + 40 * MS), (-2_020, 40)),
+            ((100 * S + 10 * MS, 100 * S, 100 * S), (10, 0)),
+            ((100 * S + MS / 2, 100 * S, 100 * S + 999_999), (0, 0)),
+        ];
+        for ((guest, before, after), (offset, rtt)) in cases {
+            let d = Drift::between(guest, before, after);
+            assert_eq!((d.offset_ms, d.rtt_ms), (offset, rtt), "{} {} {}", guest, before, after);
+            assert_eq!(d.guest_ns, guest);
+        }
+    }
+
+    #[test]
+    fn threshold() {
+        let drift = |offset_ms| Drift { guest_ns: 0, offset_ms, rtt_ms: 0 };
+        let cases = [(0, 500, false), (500, 500, false), (501, 500, true), (-501, 500, true), (-500, 500, false), (1, 0, true)];
+        for (offset, threshold, want) in cases {
+            assert_eq!(drift(offset).exceeds(threshold), want, "{} > {}", offset, threshold);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+// src/config.rs
+
+//! User configuration loaded from `$XDG_CONFIG_HOME/dismount_iso_qemu/config.toml`.
+//!
+//! Values are resolved in this order, later sources winning:
+//! built-in defaults < config file < environment variables < CLI flags.
+//!
+//! Environment variables: `DISMOUNT_ISO_QEMU_CONFIG` (config file path),
+//! `LIBVIRT_URI` (connection URI) and `EDITOR`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::utils::config_dir;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// libvirt connection URI passed to `virsh -c`. `None` leaves the choice
+    /// to virsh (`LIBVIRT_DEFAULT_URI` or its built-in default).
+    pub uri: Option<String>,
+    /// Named libvirt hosts (`name = "uri"`). When set, every host is listed
+    /// and VMs are addressed as `host/vm`.
+    pub hosts: BTreeMap<String, String>,
+    /// Editor for in-VM file editing.
+    pub editor: String,
+    pub timeouts: Timeouts,
+    pub cache: Cache,
+    pub transfer: Transfer,
+    pub audit: Audit,
+    pub isos: Isos,
+    pub metrics: Metrics,
+    pub sync: LiveSync,
+    pub backup: Backup,
+    pub history: History,
+    pub clock: Clock,
+    /// Per-VM settings keyed by libvirt domain name, or by `host/name` for
+    /// one host's VM.
+    pub vm: HashMap<String, VmConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// Seconds to wait for OS probes and other short agent RPCs.
+    pub probe: u64,
+    /// Seconds to wait for each guest-file-* agent call.
+    pub agent_file: u64,
+    /// Seconds to wait for a power action to reach its target state.
+    pub power_wait: u64,
+    /// Seconds a program started with guest-exec may run.
+    pub exec: u64,
+    /// Seconds to wait for a guest service to reach its target state.
+    pub service_wait: u64,
+    /// Seconds to wait for guest-fsfreeze-freeze/thaw (VSS on Windows can be slow).
+    pub fsfreeze: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cache {
+    /// Seconds a probed OS stays fresh in `ProbeManager`.
+    pub ttl: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Transfer {
+    /// Bytes per guest-file-read / guest-file-write call.
+    pub chunk_size: usize,
+    /// Consecutive failed agent calls a transfer survives before giving up.
+    /// Each retry waits twice as long as the last, starting at one second.
+    pub retries: u32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Audit {
+    /// JSONL audit log of guest modifications. Defaults to
+    /// `$XDG_STATE_HOME/dismount_iso_qemu/audit.jsonl`.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Isos {
+    /// Local directories scanned (recursively) for `*.iso` images.
+    pub dirs: Vec<PathBuf>,
+    /// Also index ISO volumes in every active libvirt storage pool.
+    pub pools: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Metrics {
+    /// Address `serve-metrics` listens on.
+    pub listen: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LiveSync {
+    /// Milliseconds the local file must stay unchanged before it is pushed.
+    pub debounce_ms: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Backup {
+    /// Where backups are written. Defaults to
+    /// `$XDG_STATE_HOME/dismount_iso_qemu/backups`.
+    pub dir: Option<PathBuf>,
+    /// Backups kept per VM; older ones are pruned after each backup. 0 keeps all.
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct History {
+    /// Where resource samples are appended. Defaults to
+    /// `$XDG_STATE_HOME/dismount_iso_qemu/history`.
+    pub dir: Option<PathBuf>,
+    /// Seconds between samples taken by `sample`.
+    pub interval: u64,
+    /// Days of samples kept; older ones are pruned by the sampler. 0 keeps all.
+    pub keep_days: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Clock {
+    /// Drift between guest and host clock, in milliseconds, above which a
+    /// guest is reported and corrected.
+    pub threshold_ms: u64,
+    /// Check the clocks of VMs after resuming them.
+    pub check_after_resume: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VmConfig {
+    /// Alternative names accepted wherever this VM is asked for.
+    pub aliases: Vec<String>,
+    /// Remote path offered as the default when editing files in this VM.
+    pub remote_path: Option<String>,
+    /// Tags usable in selectors as `tag:<name>`.
+    pub tags: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            uri: None,
+            hosts: BTreeMap::new(),
+            editor: "nano".to_string(),
+            timeouts: Timeouts::default(),
+            cache: Cache::default(),
+            transfer: Transfer::default(),
+            audit: Audit::default(),
+            isos: Isos::default(),
+            metrics: Metrics::default(),
+            sync: LiveSync::default(),
+            backup: Backup::default(),
+            history: History::default(),
+            clock: Clock::default(),
+            vm: HashMap::new(),
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self { probe: 5, agent_file: 10, power_wait: 120, exec: 30, service_wait: 60, fsfreeze: 60 }
+    }
+}
+
+impl Default for Isos {
+    fn default() -> Self {
+        Self { dirs: Vec::new(), pools: true }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self { listen: "0.0.0.0:9477".to_string() }
+    }
+}
+
+impl Default for LiveSync {
+    fn default() -> Self {
+        Self { debounce_ms: 500 }
+    }
+}
+
+impl Default for Backup {
+    fn default() -> Self {
+        Self { dir: None, keep: 7 }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self { dir: None, interval: 300, keep_days: 30 }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self { threshold_ms: 1000, check_after_resume: false }
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self { ttl: 60 }
+    }
+}
+
+impl Default for Transfer {
+    fn default() -> Self {
+        Self { chunk_size: 4096, retries: 5 }
+    }
+}
+
+/// Values given on the command line; each one beats every other source.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub config_path: Option<PathBuf>,
+    pub uri: Option<String>,
+    pub editor: Option<String>,
+    pub probe_timeout: Option<u64>,
+    pub cache_ttl: Option<u64>,
+    /// `--dry-run`; not a config file setting.
+    pub dry_run: bool,
+}
+
+impl Config {
+    /// Build the effective configuration from file, environment and flags.
+    /// A missing file at the default location is not an error; a missing
+    /// file that was asked for explicitly is.
+    pub fn load(overrides: &Overrides) -> Result<Self> {
+        let explicit = overrides
+            .config_path
+            .clone()
+            .or_else(|| std::env::var_os("DISMOUNT_ISO_QEMU_CONFIG").map(PathBuf::from));
+
+        let mut cfg = match (&explicit, config_dir().map(|d| d.join("config.toml"))) {
+            (Some(path), _) => Self::read(path)?,
+            (None, Some(path)) if path.exists() => Self::read(&path)?,
+            _ => Self::default(),
+        };
+
+        if let Ok(uri) = std::env::var("LIBVIRT_URI") {
+            cfg.uri = Some(uri);
+        }
+        if let Ok(editor) = std::env::var("EDITOR") {
+            cfg.editor = editor;
+        }
+
+        if let Some(uri) = &overrides.uri {
+            cfg.uri = Some(uri.clone());
+        }
+        if let Some(editor) = &overrides.editor {
+            cfg.editor = editor.clone();
+        }
+        if let Some(secs) = overrides.probe_timeout {
+            cfg.timeouts.probe = secs;
+        }
+        if let Some(secs) = overrides.cache_ttl {
+            cfg.cache.ttl = secs;
+        }
+        cfg.transfer.chunk_size = cfg.transfer.chunk_size.max(1);
+        cfg.history.interval = cfg.history.interval.max(1);
+        Ok(cfg)
+    }
+
+    fn read(path: &PathBuf) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("parsing config {}", path.display()))
+    }
+
+    /// Hosts to list: every `[hosts]` entry, or just the default connection
+    /// (`None`) when none are configured.
+    pub fn host_names(&self) -> Vec<Option<String>> {
+        if self.hosts.is_empty() {
+            vec![None]
+        } else {
+            self.hosts.keys().cloned().map(Some).collect()
+        }
+    }
+
+    pub fn host_uri(&self, host: &str) -> Option<&str> {
+        self.hosts.get(host).map(String::as_str)
+    }
+
+    /// Settings of `vm`. A `[vm."host/name"]` table applies to that VM
+    /// only; `[vm.name]`, keyed by the bare domain name, applies to a VM of
+    /// that name on every host.
+    pub fn vm_config(&self, vm: &str) -> Option<&VmConfig> {
+        self.vm.get(vm).or_else(|| vm.split_once('/').and_then(|(_, name)| self.vm.get(name)))
+    }
+
+    /// Map an alias to its VM name; unknown names are returned unchanged.
+    /// A host qualifier is kept: with `[vm.fs00] aliases = ["fs"]`, `hv1/fs`
+    /// resolves to `hv1/fs00`.
+    pub fn resolve_alias(&self, name: &str) -> String {
+        let (host, alias) = match name.split_once('/') {
+            Some((host, alias)) => (Some(host), alias),
+            None => (None, name),
+        };
+        for (vm, settings) in &self.vm {
+            if !settings.aliases.iter().any(|a| a == alias) {
+                continue;
+            }
+            match (host, vm.split_once('/')) {
+                (Some(host), None) => return format!("{}/{}", host, vm),
+                (Some(host), Some((vm_host, _))) if host != vm_host => {}
+                _ => return vm.clone(),
+            }
+        }
+        name.to_string()
+    }
+
+    pub fn tags(&self, vm: &str) -> &[String] {
+        self.vm_config(vm).map(|v| v.tags.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn remote_path(&self, vm: &str) -> Option<&str> {
+        self.vm_config(vm).and_then(|v| v.remote_path.as_deref())
+    }
+}
+
+/// Install the effective configuration. Only the first call has an effect.
+pub fn init(cfg: Config) {
+    let _ = CONFIG.set(cfg);
+}
+
+/// The effective configuration, or defaults if `init` was never called.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
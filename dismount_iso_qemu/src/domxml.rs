@@ -0,0 +1,149 @@
+// src/domxml.rs
+
+//! Domain XML checks and the history of previous definitions.
+//!
+//! Before a definition replaced by the editor or a revert is applied, it is
+//! saved under `$XDG_STATE_HOME/dismount_iso_qemu/domains/<vm>/<unix-time>.xml`
+//! (`domains/<host>/<vm>/` for a VM on a `[hosts]` entry) so any change can
+//! be undone.
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::dryrun;
+use crate::utils::{state_dir, unix_now};
+
+/// A saved definition.
+#[derive(Debug, Clone)]
+pub struct Revision {
+    /// When it was replaced, seconds since the epoch.
+    pub saved_at: u64,
+    pub path: PathBuf,
+}
+
+/// Check that `xml` is a well-formed domain definition for the same domain
+/// as `current`: `<domain>` root, unchanged `<name>` and `<uuid>`. virsh
+/// define would otherwise create or overwrite a different domain.
+pub fn validate(xml: &str, current: &str) -> Result<()> {
+    let doc = roxmltree::Document::parse(xml).context("XML does not parse")?;
+    let root = doc.root_element();
+    if !root.has_tag_name("domain") {
+        bail!("root element is <{}>, expected <domain>", root.tag_name().name());
+    }
+    let cur = roxmltree::Document::parse(current).context("current definition does not parse")?;
+    for tag in ["name", "uuid"] {
+        let text = |d: &roxmltree::Document| {
+            d.root_element()
+                .children()
+                .find(|n| n.has_tag_name(tag))
+                .and_then(|n| n.text())
+                .map(|t| t.trim().to_string())
+        };
+        let (new, old) = (text(&doc), text(&cur));
+        if new != old {
+            match new {
+                None => bail!("<{}> was removed", tag),
+                Some(new) => bail!(
+                    "<{}> changed from '{}' to '{}'; renaming or cloning is not supported here",
+                    tag,
+                    old.unwrap_or_default(),
+                    new
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `<domains>/<vm>`, or `<domains>/<host>/<vm>` for a host-qualified VM,
+/// so `hv1/web` and a local `hv1_web` never share a history.
+fn history_dir(vm: &str) -> Result<PathBuf> {
+    let dir = state_dir().ok_or_else(|| anyhow!("cannot locate state directory: HOME is not set"))?;
+    let dir = dir.join("domains");
+    Ok(match vm.split_once('/') {
+        Some((host, name)) => dir.join(host).join(name),
+        None => dir.join(vm),
+    })
+}
+
+/// Save a definition that is about to be replaced.
+pub fn save_revision(vm: &str, xml: &str) -> Result<PathBuf> {
+    let dir = history_dir(vm)?;
+    if !dryrun::enabled() {
+        std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    let mut ts = unix_now();
+    // Two saves within a second must not overwrite each other.
+    while dir.join(format!("{}.xml", ts)).exists() {
+        ts += 1;
+    }
+    let path = dir.join(format!("{}.xml", ts));
+    if dryrun::enabled() {
+        dryrun::note(&format!("save the current definition to {}", path.display()));
+        return Ok(path);
+    }
+    std::fs::write(&path, xml).with_context(|| format!("writing {}", path.display()))?;
+    Ok(path)
+}
+
+/// Saved definitions of `vm`, newest first.
+pub fn revisions(vm: &str) -> Result<Vec<Revision>> {
+    let dir = history_dir(vm)?;
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(e) => e,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", dir.display())),
+    };
+    let mut revs: Vec<Revision> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let path = e.path();
+            let saved_at = path.file_stem()?.to_str()?.parse().ok()?;
+            (path.extension()? == "xml").then_some(Revision { saved_at, path })
+        })
+        .collect();
+    revs.sort_by_key(|r| std::cmp::Reverse(r.saved_at));
+    Ok(revs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURRENT: &str = "<domain type='kvm'>
+  <name>web</name>
+  <uuid>0b7e5f0e-4f4b-4d39-9d52-3c2f8a7a1e01</uuid>
+  <memory unit='KiB'>4194304</memory>
+</domain>";
+
+    #[test]
+    fn validate_edits() {
+        let ok = [
+            CURRENT.replace("4194304", "8388608"),
+            CURRENT.replace("<name>web</name>", "<name>\n    web\n  </name>"),
+        ];
+        for xml in &ok {
+            assert!(validate(xml, CURRENT).is_ok(), "{}", xml);
+        }
+        let bad = [
+            ("<domain><name>web</name>", "does not parse"),
+            ("<network><name>web</name></network>", "expected <domain>"),
+            (&*CURRENT.replace("<name>web</name>", "<name>web2</name>"), "<name> changed from 'web' to 'web2'"),
+            (&*CURRENT.replace("<name>web</name>", ""), "<name> was removed"),
+            (&*CURRENT.replace("0b7e5f0e", "ffffffff"), "<uuid> changed"),
+        ];
+        for (xml, want) in bad {
+            let err = format!("{:#}", validate(xml, CURRENT).unwrap_err());
+            assert!(err.contains(want), "{}: {}", xml, err);
+        }
+    }
+
+    #[test]
+    fn history_per_host() {
+        let root = state_dir().unwrap().join("domains");
+        assert_eq!(history_dir("web").unwrap(), root.join("web"));
+        assert_eq!(history_dir("hv1/web").unwrap(), root.join("hv1").join("web"));
+        assert_ne!(history_dir("hv1/web").unwrap(), history_dir("hv1_web").unwrap());
+    }
+}
@@ -0,0 +1,52 @@
+// src/dryrun.rs
+
+//! Global dry-run switch (`--dry-run`, or the menu toggle).
+//!
+//! While it is on, every call that would change a VM, its guest or the
+//! files this tool manages reports what it would do instead: the exact
+//! virsh command line, the guest agent JSON it would send, or the local
+//! file operation. Read-only calls still run, so flows see real state and
+//! the reported plan matches what a real run would do. Reports go to the
+//! sink set with `set_sink`; without one they are dropped.
+
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SINK: RwLock<Option<fn(&str)>> = RwLock::new(None);
+
+pub fn set(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Send each skipped action to `sink`, e.g. to print it.
+pub fn set_sink(sink: fn(&str)) {
+    *SINK.write().unwrap() = Some(sink);
+}
+
+/// Report one skipped action.
+pub fn note(action: &str) {
+    if let Some(sink) = *SINK.read().unwrap() {
+        sink(action);
+    }
+}
+
+/// `args` as a command line that can be pasted into a shell.
+pub fn shell_line(args: &[&str]) -> String {
+    args.iter().map(|a| shell_quote(a)).collect::<Vec<_>>().join(" ")
+}
+
+/// Quote `arg` for a POSIX shell; virsh parses its batch input the same way.
+pub(crate) fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=,@+%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
@@ -0,0 +1,227 @@
+// src/error.rs
+
+//! Error type for virsh and guest agent calls.
+//!
+//! virsh reports every failure as text on stderr; `Error::from_virsh` sorts
+//! those messages into the cases a user can act on, and `Error::hint` gives
+//! the matching remediation.
+
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// libvirtd could not be reached at the given URI.
+    Unreachable { uri: Option<String>, detail: String },
+    /// The connection or operation was refused for lack of privileges.
+    PermissionDenied(String),
+    /// A `host/vm` name referred to a host missing from `[hosts]`.
+    UnknownHost(String),
+    DomainNotFound(String),
+    DomainNotRunning(String),
+    /// qemu-guest-agent is not installed, not connected or not answering.
+    AgentUnavailable { vm: String, detail: String },
+    /// The agent is running but its policy blocks this command.
+    AgentCommandDisabled { vm: String, command: String },
+    /// The agent in the guest is too old to know this command.
+    AgentCommandUnsupported { vm: String, command: String, version: String },
+    Timeout(String),
+    /// A program run in the guest with guest-exec exited non-zero.
+    GuestCommand { vm: String, command: String, code: i64, detail: String },
+    /// Unexpected or unparsable reply from virsh or the agent.
+    Protocol(String),
+    /// Any other virsh failure, with its stderr.
+    Virsh { what: String, detail: String },
+    /// virsh could not be started or a local I/O operation failed.
+    Io(io::Error),
+}
+
+impl Error {
+    /// Classify a failed virsh invocation from its stderr. `uri` is the
+    /// connection used and `vm` the domain the command targeted, if any.
+    pub fn from_virsh(what: &str, stderr: &str, uri: Option<&str>, vm: Option<&str>) -> Self {
+        let detail = stderr.trim().trim_start_matches("error: ").to_string();
+        let lower = detail.to_lowercase();
+        let vm_name = vm.unwrap_or_default().to_string();
+
+        if lower.contains("failed to connect to the hypervisor")
+            || lower.contains("failed to connect socket")
+            || lower.contains("cannot recv data")
+            || lower.contains("no connection driver")
+        {
+            if lower.contains("permission denied") || lower.contains("authentication") {
+                return Error::PermissionDenied(detail);
+            }
+            return Error::Unreachable { uri: uri.map(str::to_string), detail };
+        }
+        if lower.contains("permission denied") || lower.contains("access denied") || lower.contains("authentication failed") {
+            return Error::PermissionDenied(detail);
+        }
+        if lower.contains("domain not found") || lower.contains("failed to get domain") {
+            return Error::DomainNotFound(vm_name);
+        }
+        if lower.contains("domain is not running") {
+            return Error::DomainNotRunning(vm_name);
+        }
+        if lower.contains("has been disabled") || lower.contains("commanddisabled") {
+            return Error::AgentCommandDisabled {
+                vm: vm_name,
+                command: what.to_string(),
+            };
+        }
+        if lower.contains("guest agent is not connected")
+            || lower.contains("guest agent is not responding")
+            || lower.contains("guest agent not available")
+            || lower.contains("agent is not configured")
+        {
+            return Error::AgentUnavailable { vm: vm_name, detail };
+        }
+        if lower.contains("timed out") || lower.contains("timeout") {
+            return Error::Timeout(detail);
+        }
+        Error::Virsh { what: what.to_string(), detail }
+    }
+
+    /// What the user can do about it, when there is something specific.
+    pub fn hint(&self) -> Option<&'static str> {
+        Some(match self {
+            Error::Unreachable { .. } => {
+                "check that libvirtd is running on the host and that the URI (LIBVIRT_URI, --connect, uri or [hosts] in the config) is right"
+            }
+            Error::PermissionDenied(_) => {
+                "run as root or add your user to the 'libvirt' group; for qemu+ssh:// URIs check the SSH key and remote user"
+            }
+            Error::UnknownHost(_) => "add the host under [hosts] in the config file",
+            Error::DomainNotFound(_) => "check the name with 'virsh list --all'; VMs on other hosts need a host/vm name",
+            Error::DomainNotRunning(_) => "start the VM first, e.g. 'dismount_iso_qemu power start <vm>'",
+            Error::AgentUnavailable { .. } => {
+                "install qemu-guest-agent and add a virtio-serial channel (org.qemu.guest_agent.0) to the VM, then start the agent service in the guest"
+            }
+            Error::AgentCommandDisabled { .. } => {
+                "the guest agent's policy blocks this command; allow it in the agent config (--allow-rpcs/--block-rpcs on Linux, the qemu-ga service options on Windows)"
+            }
+            Error::AgentCommandUnsupported { .. } => "upgrade qemu-guest-agent in the guest",
+            Error::Timeout(_) => "the guest is slow or hung; raise [timeouts] probe/agent_file in the config or retry",
+            Error::GuestCommand { .. } => return None,
+            Error::Protocol(_) => "the agent sent an unexpected reply; check the qemu-guest-agent version in the guest",
+            Error::Io(e) if e.kind() == io::ErrorKind::NotFound => "install libvirt's client tools so 'virsh' is on PATH",
+            Error::Virsh { .. } | Error::Io(_) => return None,
+        })
+    }
+
+    /// Compact description for table cells such as the status table's OS column.
+    pub fn short(&self) -> String {
+        match self {
+            Error::Unreachable { .. } => "(host unreachable)".to_string(),
+            Error::PermissionDenied(_) => "(permission denied)".to_string(),
+            Error::UnknownHost(_) => "(unknown host)".to_string(),
+            Error::DomainNotFound(_) => "(not found)".to_string(),
+            Error::DomainNotRunning(_) => "(not running)".to_string(),
+            Error::AgentUnavailable { .. } => "(no guest agent)".to_string(),
+            Error::AgentCommandDisabled { command, .. } => format!("({} disabled)", command),
+            Error::AgentCommandUnsupported { command, .. } => format!("({} unsupported)", command),
+            Error::Timeout(_) => "(agent timeout)".to_string(),
+            Error::GuestCommand { code, .. } => format!("(exit code {})", code),
+            Error::Protocol(_) => "(bad agent reply)".to_string(),
+            Error::Virsh { .. } | Error::Io(_) => "(error)".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unreachable { uri, detail } => match uri {
+                Some(uri) => write!(f, "libvirt at {} is unreachable: {}", uri, detail),
+                None => write!(f, "libvirt is unreachable: {}", detail),
+            },
+            Error::PermissionDenied(detail) => write!(f, "permission denied: {}", detail),
+            Error::UnknownHost(host) => write!(f, "unknown host '{}'", host),
+            Error::DomainNotFound(vm) => write!(f, "VM '{}' not found", vm),
+            Error::DomainNotRunning(vm) => write!(f, "VM '{}' is not running", vm),
+            Error::AgentUnavailable { vm, detail } => {
+                write!(f, "guest agent in '{}' is not installed or not responding: {}", vm, detail)
+            }
+            Error::AgentCommandDisabled { vm, command } => {
+                write!(f, "guest agent in '{}' has '{}' disabled", vm, command)
+            }
+            Error::AgentCommandUnsupported { vm, command, version } => {
+                write!(f, "guest agent {} in '{}' does not support '{}'", version, vm, command)
+            }
+            Error::Timeout(detail) => write!(f, "timed out: {}", detail),
+            Error::GuestCommand { vm, command, code, detail } => {
+                write!(f, "'{}' in '{}' exited with code {}: {}", command, vm, code, detail)
+            }
+            Error::Protocol(detail) => write!(f, "unexpected reply: {}", detail),
+            Error::Virsh { what, detail } => write!(f, "virsh {} failed: {}", what, detail),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_virsh_stderr() {
+        let cases = [
+            (
+                "error: failed to connect to the hypervisor\nerror: Failed to connect socket to '/var/run/libvirt/libvirt-sock': No such file or directory",
+                "(host unreachable)",
+            ),
+            (
+                "error: failed to connect to the hypervisor\nerror: authentication unavailable: no polkit agent available to authenticate action 'org.libvirt.unix.manage'",
+                "(permission denied)",
+            ),
+            ("error: Failed to connect socket to '/var/run/libvirt/libvirt-sock': Permission denied", "(permission denied)"),
+            ("error: access denied: 'domain-write' denied", "(permission denied)"),
+            ("error: failed to get domain 'nope'", "(not found)"),
+            ("error: Requested operation is not valid: domain is not running", "(not running)"),
+            ("error: Guest agent is not responding: QEMU guest agent is not connected", "(no guest agent)"),
+            ("error: argument unsupported: QEMU guest agent is not configured", "(no guest agent)"),
+            ("error: internal error: unable to execute QEMU agent command 'guest-exec': Command guest-exec has been disabled", "(guest-exec disabled)"),
+            ("error: Timed out during operation: cannot acquire state change lock", "(agent timeout)"),
+            ("error: Disk 'sdb' not found in domain", "(error)"),
+            ("", "(error)"),
+        ];
+        for (stderr, want) in cases {
+            let e = Error::from_virsh("guest-exec", stderr, Some("qemu:///system"), Some("web"));
+            assert_eq!(e.short(), want, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn classified_details() {
+        match Error::from_virsh("list", "error: failed to connect to the hypervisor\n", Some("qemu+ssh://hv1/system"), None) {
+            Error::Unreachable { uri, detail } => {
+                assert_eq!(uri.as_deref(), Some("qemu+ssh://hv1/system"));
+                assert_eq!(detail, "failed to connect to the hypervisor");
+            }
+            other => panic!("{:?}", other),
+        }
+        let e = Error::from_virsh("dominfo", "error: failed to get domain 'web'", None, Some("hv1/web"));
+        assert_eq!(e.to_string(), "VM 'hv1/web' not found");
+        assert!(e.hint().is_some());
+        let e = Error::from_virsh("detach-disk", "error: No disk found whose source path or target is sdz", None, Some("web"));
+        assert_eq!(e.to_string(), "virsh detach-disk failed: No disk found whose source path or target is sdz");
+        assert!(e.hint().is_none());
+    }
+}
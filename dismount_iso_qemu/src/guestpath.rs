@@ -0,0 +1,243 @@
+// src/guestpath.rs
+
+//! Paths inside a guest, in the form its OS expects.
+//!
+//! The guest agent hands paths to the guest's own file API, so a Windows
+//! VM needs `C:\dir\file` and a Linux VM `/dir/file`. [`GuestPath`] checks
+//! and normalises a user-typed path for one of the two flavours, chosen
+//! from the OS the probe reports; when the OS is unknown the flavour is
+//! guessed from the path itself.
+//!
+//! Windows: `/` becomes `\`, the drive letter is upper-cased, UNC paths
+//! (`\\server\share\...`) and `\\?\` paths are kept, and `~name\...` means
+//! `C:\Users\name\...`. POSIX: `~` is root's home (the agent runs as
+//! root) and `~name` is `/home/name`. In both, repeated separators and `.`
+//! components are dropped. Relative paths are rejected, since the agent's
+//! working directory is not something the user can see.
+
+use std::fmt;
+
+use anyhow::{bail, Result};
+
+use crate::probe::ProbeManager;
+use crate::utils::os_family;
+
+/// Characters Windows does not allow in a file name.
+const WINDOWS_RESERVED: &[char] = &['<', '>', '"', '|', '?', '*', ':'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    Windows,
+    Posix,
+}
+
+impl Flavor {
+    /// The flavour of `vm` from its probed OS, or `None` when the OS is not
+    /// known (agent down, VM off).
+    pub fn of(probe_mgr: &ProbeManager, vm: &str) -> Option<Flavor> {
+        match probe_mgr.get_os(vm) {
+            Ok(Some(os)) if os_family(&os) == "windows" => Some(Flavor::Windows),
+            Ok(Some(_)) => Some(Flavor::Posix),
+            _ => None,
+        }
+    }
+
+    /// Best guess from the text alone: a drive letter, UNC prefix or
+    /// backslash means Windows.
+    pub fn guess(raw: &str) -> Flavor {
+        if has_drive(raw) || raw.starts_with("\\\\") || raw.contains('\\') {
+            Flavor::Windows
+        } else {
+            Flavor::Posix
+        }
+    }
+}
+
+/// A checked, absolute path inside a guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestPath {
+    flavor: Flavor,
+    path: String,
+}
+
+impl GuestPath {
+    /// Normalise `raw` for `flavor`, or for the flavour it looks like when
+    /// `flavor` is `None`.
+    pub fn parse(raw: &str, flavor: Option<Flavor>) -> Result<GuestPath> {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            bail!("empty guest path");
+        }
+        let flavor = flavor.unwrap_or_else(|| Flavor::guess(raw));
+        let path = match flavor {
+            Flavor::Windows => windows(raw)?,
+            Flavor::Posix => posix(raw)?,
+        };
+        Ok(GuestPath { flavor, path })
+    }
+
+    /// Parse `raw` for the OS that `vm` runs.
+    pub fn for_vm(probe_mgr: &ProbeManager, vm: &str, raw: &str) -> Result<GuestPath> {
+        let flavor = Flavor::of(probe_mgr, vm);
+        GuestPath::parse(raw, flavor).map_err(|e| match flavor {
+            Some(Flavor::Windows) => e.context(format!("'{}' runs Windows", vm)),
+            Some(Flavor::Posix) => e.context(format!("'{}' does not run Windows", vm)),
+            None => e,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.path
+    }
+
+    /// Last component, or `None` for a root such as `/` or `C:\`.
+    pub fn file_name(&self) -> Option<&str> {
+        let sep = match self.flavor {
+            Flavor::Windows => '\\',
+            Flavor::Posix => '/',
+        };
+        self.path.rsplit(sep).next().filter(|name| !name.is_empty())
+    }
+}
+
+impl fmt::Display for GuestPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.path)
+    }
+}
+
+/// `X:` at the start of `s`.
+pub fn has_drive(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() >= 2 && b[0].is_ascii_alphabetic() && b[1] == b':'
+}
+
+fn windows(raw: &str) -> Result<String> {
+    let p = raw.replace('/', "\\");
+
+    // \\?\ and \\.\ paths bypass Win32 normalisation, so they are passed on as typed.
+    if p.starts_with("\\\\?\\") || p.starts_with("\\\\.\\") {
+        return Ok(p);
+    }
+
+    let (prefix, rest) = if let Some(unc) = p.strip_prefix("\\\\") {
+        let mut parts = unc.split('\\').filter(|c| !c.is_empty());
+        let (Some(server), Some(share)) = (parts.next(), parts.next()) else {
+            bail!("UNC path '{}' needs a server and a share (\\\\server\\share\\...)", raw);
+        };
+        let rest: Vec<&str> = parts.collect();
+        (format!("\\\\{}\\{}", server, share), rest.join("\\"))
+    } else if let Some(home) = p.strip_prefix('~') {
+        let (user, rest) = home.split_once('\\').unwrap_or((home, ""));
+        if user.is_empty() {
+            bail!("'~' has no meaning on Windows (the agent runs as SYSTEM); use ~name or C:\\Users\\name");
+        }
+        (format!("C:\\Users\\{}", user), rest.to_string())
+    } else if has_drive(&p) {
+        let rest = &p[2..];
+        if !rest.is_empty() && !rest.starts_with('\\') {
+            bail!("'{}' is relative to the current directory of drive {}; write {}\\{}", raw, &p[..2], &p[..2], rest);
+        }
+        (p[..2].to_ascii_uppercase(), rest.to_string())
+    } else {
+        bail!("'{}' is not an absolute Windows path (e.g. C:\\dir\\file)", raw);
+    };
+
+    let mut out = prefix;
+    for part in rest.split('\\').filter(|c| !c.is_empty() && *c != ".") {
+        if let Some(c) = part.chars().find(|c| WINDOWS_RESERVED.contains(c) || c.is_control()) {
+            bail!("'{}' contains '{}', which Windows does not allow in file names", raw, c);
+        }
+        out.push('\\');
+        out.push_str(part);
+    }
+    if has_drive(&out) && out.len() == 2 {
+        out.push('\\');
+    }
+    Ok(out)
+}
+
+fn posix(raw: &str) -> Result<String> {
+    if has_drive(raw) || raw.starts_with("\\\\") {
+        bail!("'{}' is a Windows path", raw);
+    }
+    let p = match raw.strip_prefix('~') {
+        Some(home) => {
+            let (user, rest) = home.split_once('/').unwrap_or((home, ""));
+            match user {
+                "" | "root" => format!("/root/{}", rest),
+                user => format!("/home/{}/{}", user, rest),
+            }
+        }
+        None if raw.starts_with('/') => raw.to_string(),
+        None => bail!("'{}' is not an absolute path (e.g. /etc/hosts)", raw),
+    };
+
+    let mut out = String::new();
+    for part in p.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        out.push('/');
+        out.push_str(part);
+    }
+    if out.is_empty() {
+        out.push('/');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalise() {
+        use Flavor::{Posix, Windows};
+        // (raw, flavour, normalised or None for an error)
+        let cases = [
+            ("c:/Users/Admin/file.txt", Some(Windows), Some(r"C:\Users\Admin\file.txt")),
+            (r"C:\dir\\.\file", Some(Windows), Some(r"C:\dir\file")),
+            ("  C:\\x  ", Some(Windows), Some(r"C:\x")),
+            ("c:", Some(Windows), Some(r"C:\")),
+            (r"C:\", Some(Windows), Some(r"C:\")),
+            (r"\\server\share\dir\f", Some(Windows), Some(r"\\server\share\dir\f")),
+            ("//server/share", Some(Windows), Some(r"\\server\share")),
+            (r"\\?\C:\a/b", Some(Windows), Some(r"\\?\C:\a\b")),
+            ("~bob/Desktop/x", Some(Windows), Some(r"C:\Users\bob\Desktop\x")),
+            (r"\\server", Some(Windows), None),
+            ("~", Some(Windows), None),
+            ("C:dir", Some(Windows), None),
+            (r"dir\file", Some(Windows), None),
+            (r"C:\a?b", Some(Windows), None),
+            ("/etc//./hosts", Some(Posix), Some("/etc/hosts")),
+            ("/a/../b", Some(Posix), Some("/a/../b")),
+            ("/", Some(Posix), Some("/")),
+            ("~", Some(Posix), Some("/root")),
+            ("~/.ssh/config", Some(Posix), Some("/root/.ssh/config")),
+            ("~root/x", Some(Posix), Some("/root/x")),
+            ("~bob", Some(Posix), Some("/home/bob")),
+            ("etc/hosts", Some(Posix), None),
+            (r"C:\x", Some(Posix), None),
+            ("", Some(Posix), None),
+            (r"d:\x", None, Some(r"D:\x")),
+            (r"\\srv\sh", None, Some(r"\\srv\sh")),
+            ("/x//y", None, Some("/x/y")),
+            (r"dir\f", None, None),
+        ];
+        for (raw, flavor, want) in cases {
+            let got = GuestPath::parse(raw, flavor).ok();
+            assert_eq!(got.as_ref().map(GuestPath::as_str), want, "{:?} as {:?}", raw, flavor);
+        }
+    }
+
+    #[test]
+    fn file_names() {
+        let cases = [
+            (r"C:\a\b.txt", Some("b.txt")),
+            (r"C:\", None),
+            ("/etc/hosts", Some("hosts")),
+            ("/", None),
+        ];
+        for (raw, want) in cases {
+            assert_eq!(GuestPath::parse(raw, None).unwrap().file_name(), want, "{}", raw);
+        }
+    }
+}
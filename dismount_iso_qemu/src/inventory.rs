@@ -0,0 +1,281 @@
+// src/inventory.rs
+
+//! Fleet inventory: one record per VM, exported as CSV, JSON or HTML.
+//!
+//! Every field is best effort. A VM that is shut off or has no guest agent
+//! still gets a record, with the agent-backed fields left empty.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::probe::ProbeManager;
+use crate::utils::{format_rfc3339, par_map, parse_cpu_time_to_seconds, parse_domblklist, parse_domifaddr, parse_dominfo, unix_now};
+use crate::virsh;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VmRecord {
+    pub name: String,
+    /// Configured host name, or the hypervisor's hostname for the default connection.
+    pub host: String,
+    pub state: String,
+    pub os: Option<String>,
+    pub memory_max_kib: Option<u64>,
+    pub memory_used_kib: Option<u64>,
+    pub cpu_time_secs: Option<u64>,
+    /// `target=source` for each disk.
+    pub disks: Vec<String>,
+    /// Images in CD-ROM drives.
+    pub isos: Vec<String>,
+    pub ips: Vec<String>,
+    pub agent_version: Option<String>,
+    pub snapshots: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+    Html,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "csv" => Format::Csv,
+            "json" => Format::Json,
+            "html" | "htm" => Format::Html,
+            other => bail!("unknown export format '{}' (use csv, json or html)", other),
+        })
+    }
+}
+
+/// Collect a record for every VM in `vms`, in the same order.
+pub fn collect(probe_mgr: &ProbeManager, vms: &[String]) -> Vec<VmRecord> {
+    // Resolve each host's name once rather than per VM.
+    let mut hosts: HashMap<Option<String>, String> = HashMap::new();
+    for vm in vms {
+        let host = vm.split_once('/').map(|(h, _)| h.to_string());
+        hosts.entry(host.clone()).or_insert_with(|| match &host {
+            Some(h) => h.clone(),
+            None => virsh::hostname_on(None).unwrap_or_default(),
+        });
+    }
+
+    par_map(vms, |vm| {
+        let host = hosts[&vm.split_once('/').map(|(h, _)| h.to_string())].clone();
+        record(probe_mgr, vm, host)
+    })
+}
+
+fn record(probe_mgr: &ProbeManager, vm: &str, host: String) -> VmRecord {
+    let state = virsh::domstate(vm).unwrap_or_else(|e| e.short());
+    let running = state == "running";
+    let dominfo = virsh::dominfo_raw(vm).map(|raw| parse_dominfo(&raw)).ok();
+    let devices = virsh::domblklist_raw(vm).map(|raw| parse_domblklist(&raw)).unwrap_or_default();
+    let source = |d: &crate::utils::BlockDevice| d.source.clone().unwrap_or_else(|| "-".to_string());
+
+    VmRecord {
+        name: vm.split_once('/').map(|(_, n)| n).unwrap_or(vm).to_string(),
+        host,
+        os: probe_mgr.get_os(vm).ok().flatten(),
+        memory_max_kib: dominfo.as_ref().and_then(|d| d.max_memory_mb),
+        memory_used_kib: dominfo.as_ref().and_then(|d| d.used_memory_mb),
+        cpu_time_secs: dominfo
+            .as_ref()
+            .and_then(|d| d.cpu_time.as_deref())
+            .and_then(parse_cpu_time_to_seconds),
+        disks: devices
+            .iter()
+            .filter(|d| d.device == "disk")
+            .map(|d| format!("{}={}", d.target, source(d)))
+            .collect(),
+        isos: devices
+            .iter()
+            .filter(|d| d.device == "cdrom")
+            .filter_map(|d| d.source.clone())
+            .collect(),
+        ips: if running {
+            virsh::domifaddr_raw(vm).map(|raw| parse_domifaddr(&raw)).unwrap_or_default()
+        } else {
+            Vec::new()
+        },
+        agent_version: if running { probe_mgr.agent_info(vm).ok().map(|i| i.version) } else { None },
+        snapshots: virsh::snapshot_list(vm).ok().map(|s| s.len()),
+        state,
+    }
+}
+
+/// Render records in `format`.
+pub fn render(records: &[VmRecord], format: Format) -> Result<String> {
+    Ok(match format {
+        Format::Json => {
+            #[derive(Serialize)]
+            struct Export<'a> {
+                generated: String,
+                vms: &'a [VmRecord],
+            }
+            let doc = Export { generated: format_rfc3339(unix_now()), vms: records };
+            serde_json::to_string_pretty(&doc)? + "\n"
+        }
+        Format::Csv => to_csv(records),
+        Format::Html => to_html(records),
+    })
+}
+
+const COLUMNS: [&str; 12] = [
+    "name",
+    "host",
+    "state",
+    "os",
+    "memory_max_kib",
+    "memory_used_kib",
+    "cpu_time_secs",
+    "disks",
+    "isos",
+    "ips",
+    "agent_version",
+    "snapshots",
+];
+
+/// Cell values in `COLUMNS` order; lists are joined with "; ".
+fn cells(r: &VmRecord) -> [String; 12] {
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    let num = |v: Option<u64>| v.map(|n| n.to_string()).unwrap_or_default();
+    [
+        r.name.clone(),
+        r.host.clone(),
+        r.state.clone(),
+        opt(r.os.clone()),
+        num(r.memory_max_kib),
+        num(r.memory_used_kib),
+        num(r.cpu_time_secs),
+        r.disks.join("; "),
+        r.isos.join("; "),
+        r.ips.join("; "),
+        opt(r.agent_version.clone()),
+        r.snapshots.map(|n| n.to_string()).unwrap_or_default(),
+    ]
+}
+
+fn to_csv(records: &[VmRecord]) -> String {
+    let quote = |s: &str| {
+        if s.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        } else {
+            s.to_string()
+        }
+    };
+    let mut out = COLUMNS.join(",") + "\n";
+    for r in records {
+        let row: Vec<String> = cells(r).iter().map(|c| quote(c)).collect();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn to_html(records: &[VmRecord]) -> String {
+    let generated = format_rfc3339(unix_now());
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str(&format!("<title>VM inventory {}</title>\n", generated));
+    out.push_str(
+        "<style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; font-size: 0.9em; }\n\
+         th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: top; }\n\
+         th { background: #eee; }\n\
+         tr:nth-child(even) td { background: #f8f8f8; }\n\
+         </style>\n</head>\n<body>\n",
+    );
+    out.push_str(&format!("<h1>VM inventory</h1>\n<p>{} VM(s), generated {}</p>\n<table>\n<tr>", records.len(), generated));
+    for c in COLUMNS {
+        out.push_str(&format!("<th>{}</th>", c));
+    }
+    out.push_str("</tr>\n");
+    for r in records {
+        out.push_str("<tr>");
+        for c in cells(r) {
+            out.push_str(&format!("<td>{}</td>", html_escape(&c).replace("; ", "<br>")));
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</table>\n</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<VmRecord> {
+        vec![
+            VmRecord {
+                name: "web".to_string(),
+                host: "hv1".to_string(),
+                state: "running".to_string(),
+                os: Some("Microsoft Windows Server 2022 \"Datacenter\", 64-bit".to_string()),
+                memory_max_kib: Some(8_388_608),
+                memory_used_kib: Some(4_194_304),
+                cpu_time_secs: Some(123),
+                disks: vec!["vda=/images/web.qcow2".to_string(), "vdb=-".to_string()],
+                isos: Vec::new(),
+                ips: vec!["10.0.0.5".to_string(), "fe80::1".to_string()],
+                agent_version: Some("8.2.0".to_string()),
+                snapshots: Some(0),
+            },
+            VmRecord {
+                name: "<lab>&db".to_string(),
+                host: "hv2".to_string(),
+                state: "shut off".to_string(),
+                os: None,
+                memory_max_kib: None,
+                memory_used_kib: None,
+                cpu_time_secs: None,
+                disks: Vec::new(),
+                isos: vec!["/isos/a b.iso".to_string()],
+                ips: Vec::new(),
+                agent_version: None,
+                snapshots: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn csv() {
+        let want = "name,host,state,os,memory_max_kib,memory_used_kib,cpu_time_secs,disks,isos,ips,agent_version,snapshots\n\
+                    web,hv1,running,\"Microsoft Windows Server 2022 \"\"Datacenter\"\", 64-bit\",8388608,4194304,123,\
+                    vda=/images/web.qcow2; vdb=-,,10.0.0.5; fe80::1,8.2.0,0\n\
+                    <lab>&db,hv2,shut off,,,,,,/isos/a b.iso,,,\n";
+        assert_eq!(render(&records(), Format::Csv).unwrap(), want);
+        assert_eq!(render(&[], Format::Csv).unwrap(), format!("{}\n", COLUMNS.join(",")));
+    }
+
+    #[test]
+    fn html() {
+        let html = render(&records(), Format::Html).unwrap();
+        assert!(html.contains("<p>2 VM(s), generated "));
+        assert!(html.contains("<th>name</th><th>host</th>"));
+        assert!(html.contains("<td>&lt;lab&gt;&amp;db</td>"));
+        assert!(html.contains("<td>Microsoft Windows Server 2022 &quot;Datacenter&quot;, 64-bit</td>"));
+        assert!(html.contains("<td>vda=/images/web.qcow2<br>vdb=-</td>"));
+        assert!(!html.contains("<lab>"));
+        assert_eq!(html.matches("<tr>").count(), 3);
+    }
+
+    #[test]
+    fn json_and_formats() {
+        let json: serde_json::Value = serde_json::from_str(&render(&records(), Format::Json).unwrap()).unwrap();
+        assert_eq!(json["vms"][0]["ips"][1], "fe80::1");
+        assert!(json["vms"][1]["os"].is_null());
+        assert!(json["generated"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(Format::parse("HTM").unwrap(), Format::Html);
+        assert!(Format::parse("xml").is_err());
+    }
+}
@@ -0,0 +1,389 @@
+// src/isolib.rs
+
+//! ISO image library: index, CD-ROM references and the orphan report.
+//!
+//! The index covers `*.iso` files under `[isos] dirs` and ISO volumes in the
+//! active storage pools of every host. It is saved as `iso_index.json` in the
+//! state directory, and a file's hash is reused from there while its size
+//! and mtime are unchanged, so only new or modified images are re-read.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::error;
+use crate::utils::{iso_volume_label, parse_domblklist, sha256_file, state_dir, BlockDevice};
+use crate::virsh;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IsoEntry {
+    pub path: String,
+    /// Host whose storage pool holds the image; `None` for local files.
+    pub host: Option<String>,
+    /// Where the image was found: `"dir"` or `"pool:<name>"`.
+    pub origin: String,
+    pub size: Option<u64>,
+    /// Modification time, seconds since the epoch (local files only).
+    pub mtime: Option<u64>,
+    /// SHA-256 of the content (local files only).
+    pub sha256: Option<String>,
+    /// ISO 9660 volume label (local files only).
+    pub label: Option<String>,
+    /// VMs whose CD-ROM drives reference this image.
+    #[serde(default)]
+    pub used_by: Vec<String>,
+}
+
+/// One CD-ROM drive of a VM.
+#[derive(Debug, Clone)]
+pub struct CdromRef {
+    pub vm: String,
+    pub target: String,
+    /// Source type: "file", "block", "network", ...
+    pub kind: String,
+    /// Inserted image, or `None` for an empty tray.
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Index {
+    pub entries: Vec<IsoEntry>,
+    /// Pools or directories that could not be read.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Report<'a> {
+    /// Indexed images no VM references.
+    pub orphans: Vec<&'a IsoEntry>,
+    /// File-backed CD-ROM sources missing from their VM's host: not
+    /// indexed there and, for a local host, not on disk either.
+    pub broken: Vec<&'a CdromRef>,
+    /// File-backed sources on remote hosts that are neither indexed nor a
+    /// storage volume there. libvirt cannot tell whether such a file
+    /// exists, so these are neither broken nor known to be fine.
+    pub unchecked: Vec<&'a CdromRef>,
+    /// Images with identical content, grouped by hash.
+    pub duplicates: Vec<Vec<&'a IsoEntry>>,
+}
+
+/// Location of the saved index.
+pub fn index_path() -> Option<PathBuf> {
+    state_dir().map(|d| d.join("iso_index.json"))
+}
+
+/// CD-ROM drives of `vms` from `virsh domblklist`, one result per VM.
+pub fn cdroms(vms: &[String]) -> Vec<(String, error::Result<Vec<CdromRef>>)> {
+    std::thread::scope(|s| {
+        let handles: Vec<_> = vms
+            .iter()
+            .map(|vm| {
+                s.spawn(move || {
+                    let devices = parse_domblklist(&virsh::domblklist_raw(vm)?);
+                    Ok(devices
+                        .into_iter()
+                        .filter(|d: &BlockDevice| d.device == "cdrom")
+                        .map(|d| CdromRef { vm: vm.clone(), target: d.target, kind: d.kind, source: d.source })
+                        .collect())
+                })
+            })
+            .collect();
+        vms.iter().cloned().zip(handles.into_iter().map(|h| h.join().unwrap())).collect()
+    })
+}
+
+fn is_iso(path: &str) -> bool {
+    path.to_lowercase().ends_with(".iso")
+}
+
+/// Host part of a `host/vm` name.
+fn vm_host(vm: &str) -> Option<&str> {
+    vm.split_once('/').map(|(h, _)| h)
+}
+
+/// True when `host` (a `[hosts]` name, or `None` for the default
+/// connection) is this machine, so its VMs see the local filesystem.
+fn host_is_local(host: Option<&str>) -> bool {
+    let cfg = config::get();
+    let uri = match host {
+        Some(h) => cfg.host_uri(h),
+        None => cfg.uri.as_deref(),
+    };
+    uri.is_none_or(virsh::uri_is_local)
+}
+
+/// True when `entry` is on `host`, where a VM of that host can use it.
+/// Images in `[isos] dirs` are on whichever hosts are this machine;
+/// pool volumes are on the host whose pool listed them.
+fn on_host(entry: &IsoEntry, host: Option<&str>) -> bool {
+    match &entry.host {
+        Some(h) => host == Some(h.as_str()),
+        None if entry.origin == "dir" => host_is_local(host),
+        None => host.is_none(),
+    }
+}
+
+/// Index every configured directory and pool and record which of `refs`
+/// use each image. With `rehash`, cached hashes are ignored. The result is
+/// saved for the next run.
+pub fn build_index(refs: &[CdromRef], rehash: bool) -> Result<Index> {
+    let cfg = config::get();
+    let previous: HashMap<String, IsoEntry> = load_index()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|e| (e.path.clone(), e))
+        .collect();
+    let mut index = Index::default();
+
+    let mut files = Vec::new();
+    for dir in &cfg.isos.dirs {
+        if let Err(e) = collect_isos(dir, &mut files) {
+            index.warnings.push(format!("{}: {}", dir.display(), e));
+        }
+    }
+    for path in files {
+        let path_str = path.to_string_lossy().to_string();
+        match local_entry(&path, previous.get(&path_str), rehash) {
+            Ok(mut entry) => {
+                entry.origin = "dir".to_string();
+                index.entries.push(entry);
+            }
+            Err(e) => index.warnings.push(format!("{}: {:#}", path.display(), e)),
+        }
+    }
+
+    if cfg.isos.pools {
+        for host in cfg.host_names() {
+            let host = host.as_deref();
+            let pools = match virsh::pool_list_on(host) {
+                Ok(p) => p,
+                Err(e) => {
+                    index.warnings.push(format!("{}: {}", host.unwrap_or("default host"), e));
+                    continue;
+                }
+            };
+            for pool in pools {
+                let paths = match virsh::vol_paths_on(host, &pool) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        index.warnings.push(format!("pool {}: {}", pool, e));
+                        continue;
+                    }
+                };
+                for path in paths.into_iter().filter(|p| is_iso(p)) {
+                    if index.entries.iter().any(|e| e.path == path && on_host(e, host)) {
+                        continue;
+                    }
+                    // Pools on the default connection are usually local; read
+                    // them directly when possible, otherwise ask libvirt.
+                    let local = host.is_none() && Path::new(&path).is_file();
+                    let mut entry = if local {
+                        match local_entry(Path::new(&path), previous.get(&path), rehash) {
+                            Ok(e) => e,
+                            Err(e) => {
+                                index.warnings.push(format!("{}: {:#}", path, e));
+                                continue;
+                            }
+                        }
+                    } else {
+                        IsoEntry {
+                            size: virsh::vol_size_on(host, &path).ok(),
+                            path: path.clone(),
+                            host: host.map(str::to_string),
+                            origin: String::new(),
+                            mtime: None,
+                            sha256: None,
+                            label: None,
+                            used_by: Vec::new(),
+                        }
+                    };
+                    entry.origin = format!("pool:{}", pool);
+                    index.entries.push(entry);
+                }
+            }
+        }
+    }
+
+    for r in refs {
+        let Some(source) = &r.source else { continue };
+        let host = vm_host(&r.vm);
+        for entry in index.entries.iter_mut().filter(|e| &e.path == source && on_host(e, host)) {
+            if !entry.used_by.contains(&r.vm) {
+                entry.used_by.push(r.vm.clone());
+            }
+        }
+    }
+    index.entries.sort_by(|a, b| a.path.cmp(&b.path).then(a.host.cmp(&b.host)));
+
+    save_index(&index.entries)?;
+    Ok(index)
+}
+
+/// Sort the index and CD-ROM references into orphans, broken references
+/// and duplicates. A reference is looked up on its VM's host: in the index,
+/// then on disk for a local host or with `virsh vol-info` for a remote one.
+pub fn report<'a>(index: &'a Index, refs: &'a [CdromRef]) -> Report<'a> {
+    let orphans = index.entries.iter().filter(|e| e.used_by.is_empty()).collect();
+    let mut broken = Vec::new();
+    let mut unchecked = Vec::new();
+    for r in refs.iter().filter(|r| r.kind == "file") {
+        let Some(source) = r.source.as_deref() else { continue };
+        let host = vm_host(&r.vm);
+        if index.entries.iter().any(|e| e.path == source && on_host(e, host)) {
+            continue;
+        }
+        if host_is_local(host) {
+            if !Path::new(source).exists() {
+                broken.push(r);
+            }
+        } else if virsh::vol_size_on(host, source).is_err() {
+            unchecked.push(r);
+        }
+    }
+
+    let mut by_hash: BTreeMap<&str, Vec<&IsoEntry>> = BTreeMap::new();
+    for e in &index.entries {
+        if let Some(h) = &e.sha256 {
+            by_hash.entry(h.as_str()).or_default().push(e);
+        }
+    }
+    let duplicates = by_hash.into_values().filter(|g| g.len() > 1).collect();
+
+    Report { orphans, broken, unchecked, duplicates }
+}
+
+/// Collect `*.iso` files under `dir`, recursively.
+fn collect_isos(dir: &Path, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let ft = entry.file_type()?;
+        if ft.is_dir() {
+            collect_isos(&path, out)?;
+        } else if ft.is_file() && is_iso(&path.to_string_lossy()) {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Stat, label and hash a local image, reusing `cached`'s hash while size
+/// and mtime match.
+fn local_entry(path: &Path, cached: Option<&IsoEntry>, rehash: bool) -> Result<IsoEntry> {
+    let meta = std::fs::metadata(path)?;
+    let size = meta.len();
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+
+    let sha256 = match cached {
+        Some(c) if !rehash && c.size == Some(size) && c.mtime == mtime && c.sha256.is_some() => c.sha256.clone(),
+        _ => Some(sha256_file(path).context("hashing")?),
+    };
+    Ok(IsoEntry {
+        path: path.to_string_lossy().to_string(),
+        host: None,
+        origin: String::new(),
+        size: Some(size),
+        mtime,
+        sha256,
+        label: iso_volume_label(path),
+        used_by: Vec::new(),
+    })
+}
+
+fn load_index() -> Option<Vec<IsoEntry>> {
+    let text = std::fs::read_to_string(index_path()?).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+fn save_index(entries: &[IsoEntry]) -> Result<()> {
+    let Some(path) = index_path() else {
+        return Ok(());
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    std::fs::write(&path, serde_json::to_string_pretty(entries)?)
+        .with_context(|| format!("writing ISO index {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_belong_to_their_host() {
+        let entry = |host: Option<&str>, origin: &str| IsoEntry {
+            path: "/isos/a.iso".to_string(),
+            host: host.map(str::to_string),
+            origin: origin.to_string(),
+            size: None,
+            mtime: None,
+            sha256: None,
+            label: None,
+            used_by: Vec::new(),
+        };
+        assert!(on_host(&entry(Some("hv1"), "pool:isos"), Some("hv1")));
+        assert!(!on_host(&entry(Some("hv1"), "pool:isos"), Some("hv2")));
+        assert!(!on_host(&entry(Some("hv1"), "pool:isos"), None));
+        assert!(on_host(&entry(None, "pool:isos"), None));
+        assert!(!on_host(&entry(None, "pool:isos"), Some("hv1")));
+        // The default connection of the default config is this machine.
+        assert!(on_host(&entry(None, "dir"), None));
+    }
+
+    #[test]
+    fn local_report() {
+        let dir = std::env::temp_dir().join(format!("dismount_iso_qemu-isolib-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let present = dir.join("present.iso").to_string_lossy().to_string();
+        std::fs::write(&present, b"x").unwrap();
+        let cdrom = |vm: &str, kind: &str, source: Option<&str>| CdromRef {
+            vm: vm.to_string(),
+            target: "sda".to_string(),
+            kind: kind.to_string(),
+            source: source.map(str::to_string),
+        };
+        let indexed = |path: &str, sha: &str, used_by: &[&str]| IsoEntry {
+            path: path.to_string(),
+            host: None,
+            origin: "dir".to_string(),
+            size: Some(1),
+            mtime: None,
+            sha256: Some(sha.to_string()),
+            label: None,
+            used_by: used_by.iter().map(|v| v.to_string()).collect(),
+        };
+        let index = Index {
+            entries: vec![
+                indexed("/isos/used.iso", "aa", &["vm1"]),
+                indexed("/isos/orphan.iso", "bb", &[]),
+                indexed("/isos/copy.iso", "aa", &["vm2"]),
+            ],
+            warnings: Vec::new(),
+        };
+        let refs = [
+            cdrom("vm1", "file", Some("/isos/used.iso")),
+            cdrom("vm2", "file", Some("/isos/copy.iso")),
+            cdrom("vm3", "file", Some(&present)),
+            cdrom("vm4", "file", Some("/nonexistent/gone.iso")),
+            cdrom("vm5", "file", None),
+            cdrom("vm6", "block", Some("/dev/sr0-missing")),
+        ];
+        let report = report(&index, &refs);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let paths = |v: &[&IsoEntry]| v.iter().map(|e| e.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths(&report.orphans), ["/isos/orphan.iso"]);
+        assert_eq!(report.broken.iter().map(|r| r.vm.as_str()).collect::<Vec<_>>(), ["vm4"]);
+        assert!(report.unchecked.is_empty());
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(paths(&report.duplicates[0]), ["/isos/used.iso", "/isos/copy.iso"]);
+    }
+}
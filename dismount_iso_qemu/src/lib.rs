@@ -0,0 +1,88 @@
+// src/lib.rs
+
+//! Library behind the `dismount_iso_qemu` tool: libvirt domains and their
+//! QEMU guest agents, driven through `virsh`.
+//!
+//! The stable API is:
+//!
+//! - [`virsh`]: virsh subcommands, guest file primitives and guest-exec
+//! - [`agent`]: typed guest agent RPCs (OS info, capabilities, users, fsfreeze)
+//! - [`probe`]: [`probe::ProbeManager`], a cached, timeout-bounded OS and
+//!   capability prober
+//! - [`utils`]: parsers for virsh output and small helpers
+//!
+//! with the types they use: [`error::Error`], [`config::Config`],
+//! [`guestpath::GuestPath`], streaming transfers in [`transfer`] and the
+//! [`dryrun`] switch.
+//!
+//! These functions are not self-contained: they read process-wide state.
+//! Before the first call, set it up once:
+//!
+//! - [`config::init`] installs the configuration (hosts, aliases,
+//!   timeouts). Only the first call counts; without one, every call uses
+//!   [`config::Config::default`], which knows no remote hosts.
+//! - [`dryrun::set`] switches dry-run mode, which is off by default. While
+//!   it is on, calls that change a VM or guest only describe what they
+//!   would do, through the sink set with [`dryrun::set_sink`].
+//! - [`virsh::set_warning_sink`] receives warnings about hosts that were
+//!   skipped because they did not answer. Without it they are dropped.
+//!
+//! Both are global, so a process cannot use two configurations at once.
+//! Nothing here writes to stdout: results are returned.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use dismount_iso_qemu::{config, dryrun, probe::ProbeManager, virsh};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! config::init(config::Config::load(&config::Overrides::default())?);
+//! dryrun::set(false);
+//! let probe = ProbeManager::new(Duration::from_secs(5), Duration::from_secs(60))?;
+//! for vm in virsh::list_vms()? {
+//!     let os = probe.get_os(&vm)?.unwrap_or_else(|| "unknown".to_string());
+//!     let hosts = virsh::ga_read_file(&vm, "/etc/hosts")?;
+//!     eprintln!("{}: {}, /etc/hosts is {} bytes", vm, os, hosts.len());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod agent;
+pub mod config;
+pub mod dryrun;
+pub mod error;
+pub mod guestpath;
+pub mod probe;
+pub mod transfer;
+pub mod utils;
+pub mod virsh;
+
+// Used by the binary; not part of the stable API.
+#[doc(hidden)]
+pub mod audit;
+#[doc(hidden)]
+pub mod backup;
+#[doc(hidden)]
+pub mod cdrom;
+#[doc(hidden)]
+pub mod clock;
+#[doc(hidden)]
+pub mod domxml;
+#[doc(hidden)]
+pub mod inventory;
+#[doc(hidden)]
+pub mod isolib;
+#[doc(hidden)]
+pub mod livesync;
+#[doc(hidden)]
+pub mod metrics;
+#[doc(hidden)]
+pub mod network;
+#[doc(hidden)]
+pub mod patch;
+#[doc(hidden)]
+pub mod selector;
+#[doc(hidden)]
+pub mod trends;
+#[doc(hidden)]
+pub mod winsvc;
@@ -0,0 +1,172 @@
+// src/livesync.rs
+
+//! Live sync: push a local copy of a guest file back into the VM every time
+//! it is saved, rather than once when the editor exits.
+//!
+//! The local file is polled for size and mtime changes. A change is pushed
+//! once the file has been quiet for `[sync] debounce_ms`, and only when its
+//! SHA-256 differs from the last pushed content, so editors that save in
+//! several steps or rewrite an unchanged buffer do not cause extra pushes.
+//! Every push and restart goes through the audit log.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Result;
+
+use crate::audit::{self, AuditRecord};
+use crate::config;
+use crate::error::Error;
+use crate::utils::sha256_hex;
+use crate::virsh;
+use crate::winsvc;
+
+/// How often the local file is checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Service restarted after each successful push.
+#[derive(Debug, Clone)]
+pub enum Restart {
+    /// Restarted with sc.exe, waiting for it to stop and run again.
+    WindowsService(String),
+    /// Restarted with `systemctl restart`.
+    SystemdUnit(String),
+}
+
+impl Restart {
+    pub fn name(&self) -> &str {
+        match self {
+            Restart::WindowsService(name) | Restart::SystemdUnit(name) => name,
+        }
+    }
+}
+
+/// What happened during a sync session, reported as it happens.
+#[derive(Debug)]
+pub enum SyncEvent {
+    Pushed { bytes: usize, sha256: String },
+    PushFailed(anyhow::Error),
+    Restarted(String),
+    RestartFailed(String, anyhow::Error),
+}
+
+/// Watches one local file for settled changes.
+struct Watcher {
+    path: PathBuf,
+    debounce: Duration,
+    /// Hash of the content the guest has now.
+    pushed: String,
+    /// Modification time and size at the last poll.
+    stamp: Option<(Option<SystemTime>, u64)>,
+    /// When `stamp` last changed, while a change is waiting to settle.
+    changed_at: Option<Instant>,
+}
+
+impl Watcher {
+    fn new(path: &Path, initial: &[u8], debounce: Duration) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            debounce,
+            pushed: sha256_hex(initial),
+            stamp: Self::stamp(path),
+            changed_at: None,
+        }
+    }
+
+    fn stamp(path: &Path) -> Option<(Option<SystemTime>, u64)> {
+        std::fs::metadata(path).ok().map(|m| (m.modified().ok(), m.len()))
+    }
+
+    /// Block until the file has settled with content that differs from what
+    /// was last pushed. Once `stop` is set the file is checked one last time
+    /// and `None` is returned when nothing is left to push.
+    fn next_change(&mut self, stop: &AtomicBool) -> Option<Vec<u8>> {
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                let content = std::fs::read(&self.path).ok()?;
+                return (sha256_hex(&content) != self.pushed).then_some(content);
+            }
+
+            let stamp = Self::stamp(&self.path);
+            if stamp != self.stamp {
+                self.stamp = stamp;
+                self.changed_at = Some(Instant::now());
+            }
+            // A missing file is an editor halfway through replacing it.
+            if let Some(at) = self.changed_at
+                && at.elapsed() >= self.debounce
+                && self.stamp.is_some()
+            {
+                self.changed_at = None;
+                if let Ok(content) = std::fs::read(&self.path)
+                    && sha256_hex(&content) != self.pushed
+                {
+                    return Some(content);
+                }
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Push `local_path` to `remote_path` in `vm` on every settled change until
+/// `stop` is set, then push any last change. `initial` is what the guest
+/// file held when it was downloaded. A failed push is reported and retried
+/// on the next save.
+pub fn run(
+    vm: &str,
+    remote_path: &str,
+    local_path: &Path,
+    initial: &[u8],
+    restart: Option<&Restart>,
+    stop: &AtomicBool,
+    mut on_event: impl FnMut(SyncEvent),
+) {
+    let debounce = Duration::from_millis(config::get().sync.debounce_ms);
+    let mut watcher = Watcher::new(local_path, initial, debounce);
+    let mut before = initial.to_vec();
+
+    while let Some(content) = watcher.next_change(stop) {
+        if let Err(e) = audit::write_file(vm, remote_path, Some(&before), &content) {
+            on_event(SyncEvent::PushFailed(e));
+            // The last check after stop is not retried.
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            continue;
+        }
+        watcher.pushed = sha256_hex(&content);
+        on_event(SyncEvent::Pushed { bytes: content.len(), sha256: watcher.pushed.clone() });
+        before = content;
+
+        if let Some(restart) = restart {
+            match restart_service(vm, restart) {
+                Ok(()) => on_event(SyncEvent::Restarted(restart.name().to_string())),
+                Err(e) => on_event(SyncEvent::RestartFailed(restart.name().to_string(), e)),
+            }
+        }
+    }
+}
+
+/// Restart the service, audited as `service-restart`.
+pub fn restart_service(vm: &str, restart: &Restart) -> Result<()> {
+    let rec = AuditRecord::new(vm, "service-restart", Some(restart.name()));
+    audit::run(rec, || match restart {
+        Restart::WindowsService(name) => {
+            winsvc::control(vm, name, winsvc::Action::Restart, Duration::from_secs(config::get().timeouts.service_wait))
+        }
+        Restart::SystemdUnit(unit) => {
+            let out = virsh::ga_exec(vm, "systemctl", &["restart", unit])?;
+            if out.exit_code != 0 {
+                return Err(Error::GuestCommand {
+                    vm: vm.to_string(),
+                    command: format!("systemctl restart {}", unit),
+                    code: out.exit_code,
+                    detail: out.stderr.trim().to_string(),
+                });
+            }
+            Ok(())
+        }
+    })
+}
@@ -1,21 +1,37 @@
 mod cli;
-mod virsh;
-mod agent;
-mod probe;
-mod utils;
 
 use std::sync::Arc;
 use std::time::Duration;
-use probe::ProbeManager;
+use dismount_iso_qemu::probe::ProbeManager;
+use dismount_iso_qemu::{config, dryrun, virsh};
 
-/// Entry point: create the ProbeManager and enter the interactive CLI.
+/// Entry point: create the ProbeManager, then run the given subcommand or,
+/// with no arguments, enter the interactive CLI.
 /// The menu will handle displaying VM information.
-fn main() -> anyhow::Result<()> {
-    let libvirt_uri = std::env::var("LIBVIRT_URI").unwrap_or_else(|_| "qemu:///system".into());
-    let timeout = Duration::from_secs(5);
-    let cache_ttl = Duration::from_secs(60);
+fn main() {
+    if let Err(e) = run() {
+        cli::report_error(&e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (overrides, args) = cli::commands::parse_global_args(&args)?;
+    let cfg = config::Config::load(&overrides)?;
+    dryrun::set(overrides.dry_run);
+    dryrun::set_sink(|action| println!("[dry-run] {}", action));
+    virsh::set_warning_sink(|message| eprintln!("Warning: {}", message));
+
+    let timeout = Duration::from_secs(cfg.timeouts.probe);
+    let cache_ttl = Duration::from_secs(cfg.cache.ttl);
+    config::init(cfg);
+
+    let probe_mgr = Arc::new(ProbeManager::new(timeout, cache_ttl)?);
 
-    let probe_mgr = Arc::new(ProbeManager::new(libvirt_uri, timeout, cache_ttl)?);
+    if !args.is_empty() {
+        return cli::commands::run(&probe_mgr, &args);
+    }
 
     // Enter interactive CLI (blocking)
     cli::menu::run(probe_mgr)?;
@@ -0,0 +1,235 @@
+// src/metrics.rs
+
+//! Prometheus exporter behind `serve-metrics`.
+//!
+//! Each scrape lists the VMs and reads dominfo, domstate and domblklist from
+//! libvirt, which is cheap. Guest agent probes go through `ProbeManager`, so
+//! an agent is asked at most once per cache TTL however often Prometheus
+//! scrapes, and agents that do not answer are not retried until then.
+
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use crate::probe::ProbeManager;
+use crate::utils::{par_map, parse_cpu_time_to_seconds, parse_domblklist, parse_dominfo};
+use crate::virsh;
+
+/// What happened while serving, reported as it happens.
+#[derive(Debug)]
+pub enum ServeEvent {
+    /// The socket is bound; scrapes are answered from now on.
+    Listening,
+    SendFailed(std::io::Error),
+}
+
+/// Serve `/metrics` on `listen` until the process is stopped.
+pub fn serve(probe_mgr: &ProbeManager, listen: &str, mut on_event: impl FnMut(ServeEvent)) -> Result<()> {
+    let server = tiny_http::Server::http(listen).map_err(|e| anyhow!("cannot listen on {}: {}", listen, e))?;
+    on_event(ServeEvent::Listening);
+
+    for request in server.incoming_requests() {
+        let response = match request.url() {
+            "/metrics" => {
+                let header = "Content-Type: text/plain; version=0.0.4; charset=utf-8".parse::<tiny_http::Header>().unwrap();
+                tiny_http::Response::from_string(render(probe_mgr)).with_header(header)
+            }
+            "/" => {
+                let header = "Content-Type: text/html; charset=utf-8".parse::<tiny_http::Header>().unwrap();
+                tiny_http::Response::from_string(
+                    "<html><body><h1>dismount_iso_qemu exporter</h1><a href=\"/metrics\">Metrics</a></body></html>\n",
+                )
+                .with_header(header)
+            }
+            _ => tiny_http::Response::from_string("not found\n").with_status_code(404),
+        };
+        if let Err(e) = request.respond(response) {
+            on_event(ServeEvent::SendFailed(e));
+        }
+    }
+    Ok(())
+}
+
+/// Values gathered for one VM during a scrape.
+struct Sample {
+    vm: String,
+    host: String,
+    state: Option<String>,
+    memory_used_kib: Option<u64>,
+    memory_max_kib: Option<u64>,
+    cpu_seconds: Option<u64>,
+    agent_up: bool,
+    probe_seconds: Option<f64>,
+    media_mounted: Option<bool>,
+}
+
+fn sample(probe_mgr: &ProbeManager, vm: &str) -> Sample {
+    let (host, name) = match vm.split_once('/') {
+        Some((h, n)) => (h.to_string(), n.to_string()),
+        None => (String::new(), vm.to_string()),
+    };
+    let state = virsh::domstate(vm).ok();
+    let dominfo = virsh::dominfo_raw(vm).map(|raw| parse_dominfo(&raw)).ok();
+    let media_mounted = virsh::domblklist_raw(vm)
+        .map(|raw| parse_domblklist(&raw).iter().any(|d| d.device == "cdrom" && d.source.is_some()))
+        .ok();
+
+    // Only running VMs have an agent to ask.
+    let probe = (state.as_deref() == Some("running")).then(|| probe_mgr.status(vm));
+
+    Sample {
+        vm: name,
+        host,
+        memory_used_kib: dominfo.as_ref().and_then(|d| d.used_memory_mb),
+        memory_max_kib: dominfo.as_ref().and_then(|d| d.max_memory_mb),
+        cpu_seconds: dominfo
+            .as_ref()
+            .and_then(|d| d.cpu_time.as_deref())
+            .and_then(parse_cpu_time_to_seconds),
+        agent_up: probe.is_some_and(|p| p.responds),
+        probe_seconds: probe.filter(|p| p.responds).map(|p| p.latency.as_secs_f64()),
+        media_mounted,
+        state,
+    }
+}
+
+/// Escape a label value per the exposition format.
+fn label(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Render one scrape in the Prometheus text exposition format.
+pub fn render(probe_mgr: &ProbeManager) -> String {
+    let started = Instant::now();
+    let mut hosts = Vec::new();
+    let mut vms = Vec::new();
+    for (host, result) in virsh::inventory(&[]) {
+        hosts.push((host.unwrap_or_default(), result.is_ok()));
+        vms.extend(result.unwrap_or_default());
+    }
+    let samples = par_map(&vms, |vm| sample(probe_mgr, vm));
+    exposition(&hosts, &samples, started.elapsed())
+}
+
+/// Format `hosts` (name, listed) and `samples` as exposition text.
+fn exposition(hosts: &[(String, bool)], samples: &[Sample], took: Duration) -> String {
+    let mut out = String::new();
+    writeln!(out, "# HELP dismount_iso_qemu_host_up Whether the libvirt host could be listed.").unwrap();
+    writeln!(out, "# TYPE dismount_iso_qemu_host_up gauge").unwrap();
+    for (host, up) in hosts {
+        writeln!(out, "dismount_iso_qemu_host_up{{host=\"{}\"}} {}", label(host), u8::from(*up)).unwrap();
+    }
+
+    let metric = |out: &mut String, name: &str, kind: &str, help: &str, value: &dyn Fn(&Sample) -> Option<String>| {
+        writeln!(out, "# HELP dismount_iso_qemu_{} {}", name, help).unwrap();
+        writeln!(out, "# TYPE dismount_iso_qemu_{} {}", name, kind).unwrap();
+        for s in samples {
+            if let Some(v) = value(s) {
+                writeln!(out, "dismount_iso_qemu_{}{{host=\"{}\",vm=\"{}\"}} {}", name, label(&s.host), label(&s.vm), v).unwrap();
+            }
+        }
+    };
+
+    metric(&mut out, "vm_memory_used_bytes", "gauge", "Memory currently used by the VM.", &|s| {
+        s.memory_used_kib.map(|k| (k * 1024).to_string())
+    });
+    metric(&mut out, "vm_memory_max_bytes", "gauge", "Maximum memory of the VM.", &|s| {
+        s.memory_max_kib.map(|k| (k * 1024).to_string())
+    });
+    metric(&mut out, "vm_cpu_seconds_total", "counter", "Cumulative CPU time used by the VM.", &|s| {
+        s.cpu_seconds.map(|c| c.to_string())
+    });
+    metric(&mut out, "vm_running", "gauge", "Whether the VM is running.", &|s| {
+        s.state.as_ref().map(|st| u8::from(st == "running").to_string())
+    });
+    metric(&mut out, "vm_agent_up", "gauge", "Whether the guest agent answered its last probe.", &|s| {
+        Some(u8::from(s.agent_up).to_string())
+    });
+    metric(&mut out, "vm_agent_probe_seconds", "gauge", "Duration of the last successful guest agent probe.", &|s| {
+        s.probe_seconds.map(|p| format!("{:.6}", p))
+    });
+    metric(&mut out, "vm_media_mounted", "gauge", "Whether an image is inserted in any CD-ROM drive.", &|s| {
+        s.media_mounted.map(|m| u8::from(m).to_string())
+    });
+
+    writeln!(out, "# HELP dismount_iso_qemu_vm_state libvirt state of the VM (value is always 1).").unwrap();
+    writeln!(out, "# TYPE dismount_iso_qemu_vm_state gauge").unwrap();
+    for s in samples {
+        if let Some(state) = &s.state {
+            writeln!(
+                out,
+                "dismount_iso_qemu_vm_state{{host=\"{}\",vm=\"{}\",state=\"{}\"}} 1",
+                label(&s.host),
+                label(&s.vm),
+                label(state)
+            )
+            .unwrap();
+        }
+    }
+
+    writeln!(out, "# HELP dismount_iso_qemu_scrape_duration_seconds Time taken to collect these metrics.").unwrap();
+    writeln!(out, "# TYPE dismount_iso_qemu_scrape_duration_seconds gauge").unwrap();
+    writeln!(out, "dismount_iso_qemu_scrape_duration_seconds {:.6}", took.as_secs_f64()).unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_escaping() {
+        let cases = [
+            ("web", "web"),
+            ("a\"b", "a\\\"b"),
+            ("C:\\vm", "C:\\\\vm"),
+            ("two\nlines", "two\\nlines"),
+            ("\\\"", "\\\\\\\""),
+        ];
+        for (raw, want) in cases {
+            assert_eq!(label(raw), want, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn exposition_text() {
+        let vm = |vm: &str, state: Option<&str>, agent_up: bool| Sample {
+            vm: vm.to_string(),
+            host: "hv1".to_string(),
+            state: state.map(str::to_string),
+            memory_used_kib: Some(2),
+            memory_max_kib: Some(4),
+            cpu_seconds: Some(7),
+            agent_up,
+            probe_seconds: agent_up.then_some(0.0125),
+            media_mounted: Some(false),
+        };
+        let hosts = [("hv1".to_string(), true), ("hv\"2".to_string(), false)];
+        let samples = [vm("web", Some("running"), true), vm("odd\"name", None, false)];
+        let text = exposition(&hosts, &samples, Duration::from_millis(1500));
+        let lines: Vec<&str> = text.lines().filter(|l| !l.starts_with('#')).collect();
+        for want in [
+            "dismount_iso_qemu_host_up{host=\"hv1\"} 1",
+            "dismount_iso_qemu_host_up{host=\"hv\\\"2\"} 0",
+            "dismount_iso_qemu_vm_memory_used_bytes{host=\"hv1\",vm=\"web\"} 2048",
+            "dismount_iso_qemu_vm_memory_max_bytes{host=\"hv1\",vm=\"odd\\\"name\"} 4096",
+            "dismount_iso_qemu_vm_cpu_seconds_total{host=\"hv1\",vm=\"web\"} 7",
+            "dismount_iso_qemu_vm_running{host=\"hv1\",vm=\"web\"} 1",
+            "dismount_iso_qemu_vm_agent_up{host=\"hv1\",vm=\"odd\\\"name\"} 0",
+            "dismount_iso_qemu_vm_agent_probe_seconds{host=\"hv1\",vm=\"web\"} 0.012500",
+            "dismount_iso_qemu_vm_media_mounted{host=\"hv1\",vm=\"web\"} 0",
+            "dismount_iso_qemu_vm_state{host=\"hv1\",vm=\"web\",state=\"running\"} 1",
+            "dismount_iso_qemu_scrape_duration_seconds 1.500000",
+        ] {
+            assert!(lines.contains(&want), "missing {}", want);
+        }
+        // No state means no vm_running or vm_state line, and no probe time without an answer.
+        assert!(!text.contains("vm_running{host=\"hv1\",vm=\"odd"));
+        assert!(!text.contains("vm_state{host=\"hv1\",vm=\"odd"));
+        assert!(!text.contains("probe_seconds{host=\"hv1\",vm=\"odd"));
+        // Every metric has HELP and TYPE before its samples.
+        assert_eq!(text.matches("# HELP ").count(), text.matches("# TYPE ").count());
+        assert_eq!(text.matches("# TYPE ").count(), 10);
+    }
+}
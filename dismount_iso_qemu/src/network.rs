@@ -0,0 +1,259 @@
+// src/network.rs
+
+//! Per-VM network view and fleet-wide address search.
+//!
+//! The host side (`virsh domiflist`) lists every NIC with its libvirt
+//! network or bridge and MAC; the guest side (guest-network-get-interfaces)
+//! adds the addresses the guest configured and its byte counters. The two
+//! are joined on the MAC. Without an agent the addresses come from the
+//! libvirt DHCP leases and the counters from `virsh domifstat`.
+
+use std::net::IpAddr;
+
+use anyhow::{bail, Result};
+
+use crate::agent::{self, GuestInterface};
+use crate::config;
+use crate::probe::ProbeManager;
+use crate::utils::{par_map, parse_domifaddr_macs, parse_domiflist, parse_domifstat};
+use crate::virsh;
+
+/// One NIC of a VM, host and guest side combined.
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub mac: String,
+    /// Name inside the guest (`eth0`, `Ethernet 2`), when the agent answered.
+    pub guest_name: Option<String>,
+    /// Host-side device (`vnet0`); `None` while the VM is off or for
+    /// interfaces only the guest knows about.
+    pub device: Option<String>,
+    /// Libvirt network or bridge, as `network:default` or `bridge:br0`.
+    pub attached: Option<String>,
+    pub model: Option<String>,
+    /// Addresses as `ip/prefix`.
+    pub ipv4: Vec<String>,
+    pub ipv6: Vec<String>,
+    pub rx_bytes: Option<u64>,
+    pub tx_bytes: Option<u64>,
+}
+
+/// Where the addresses of a `NetworkView` came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSource {
+    /// guest-network-get-interfaces.
+    Agent,
+    /// Libvirt DHCP leases; static addresses are missing.
+    Lease,
+    /// The VM is not running.
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct NetworkView {
+    pub interfaces: Vec<Interface>,
+    pub addresses: AddressSource,
+}
+
+/// Build the network view of `vm`.
+pub fn view(probe_mgr: &ProbeManager, vm: &str) -> Result<NetworkView> {
+    let host = parse_domiflist(&virsh::domiflist_raw(vm)?);
+    let running = virsh::domstate(vm)? == "running";
+
+    let guest = if running && probe_mgr.require(vm, agent::NETWORK).is_ok() {
+        agent::guest_network_get_interfaces(vm, config::get().timeouts.probe).ok()
+    } else {
+        None
+    };
+    let addresses = match (&guest, running) {
+        (Some(_), _) => AddressSource::Agent,
+        (None, true) => AddressSource::Lease,
+        (None, false) => AddressSource::None,
+    };
+    let leases = match addresses {
+        AddressSource::Lease => virsh::domifaddr_lease_raw(vm).map(|raw| parse_domifaddr_macs(&raw)).unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    let guest = guest.unwrap_or_default();
+
+    let mut interfaces: Vec<Interface> = host
+        .into_iter()
+        .map(|h| {
+            let mut iface = Interface {
+                guest_name: None,
+                device: h.device,
+                attached: Some(format!("{}:{}", h.kind, h.source)),
+                model: Some(h.model),
+                ipv4: Vec::new(),
+                ipv6: Vec::new(),
+                rx_bytes: None,
+                tx_bytes: None,
+                mac: h.mac,
+            };
+            if let Some(g) = guest.iter().find(|g| g.mac.as_deref() == Some(iface.mac.as_str())) {
+                merge_guest(&mut iface, g);
+            }
+            for (_, addr) in leases.iter().filter(|(mac, _)| *mac == iface.mac) {
+                if addr.contains(':') {
+                    iface.ipv6.push(addr.clone());
+                } else {
+                    iface.ipv4.push(addr.clone());
+                }
+            }
+            if running
+                && iface.rx_bytes.is_none()
+                && let Some(device) = &iface.device
+                && let Ok(raw) = virsh::domifstat_raw(vm, device)
+            {
+                (iface.rx_bytes, iface.tx_bytes) = parse_domifstat(&raw);
+            }
+            iface
+        })
+        .collect();
+
+    // Interfaces only the guest has (VPNs, container bridges), if they
+    // carry a usable address; loopback is left out.
+    for g in &guest {
+        let known = g.mac.as_ref().is_some_and(|m| interfaces.iter().any(|i| &i.mac == m));
+        let usable = g.ipv4.iter().chain(&g.ipv6).any(|a| parse_ip(a).is_some_and(|ip| !ip.is_loopback()));
+        if known || !usable {
+            continue;
+        }
+        let mut iface = Interface {
+            mac: g.mac.clone().unwrap_or_default(),
+            guest_name: None,
+            device: None,
+            attached: None,
+            model: None,
+            ipv4: Vec::new(),
+            ipv6: Vec::new(),
+            rx_bytes: None,
+            tx_bytes: None,
+        };
+        merge_guest(&mut iface, g);
+        interfaces.push(iface);
+    }
+    Ok(NetworkView { interfaces, addresses })
+}
+
+fn merge_guest(iface: &mut Interface, g: &GuestInterface) {
+    iface.guest_name = Some(g.name.clone());
+    iface.ipv4 = g.ipv4.clone();
+    iface.ipv6 = g.ipv6.clone();
+    iface.rx_bytes = g.rx_bytes;
+    iface.tx_bytes = g.tx_bytes;
+}
+
+/// The address part of `ip/prefix`.
+fn parse_ip(addr: &str) -> Option<IpAddr> {
+    addr.split('/').next()?.parse().ok()
+}
+
+/// What `find` looks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Needle {
+    Ip(IpAddr),
+    /// Lower-case `aa:bb:cc:dd:ee:ff`.
+    Mac(String),
+}
+
+impl Needle {
+    /// Parse an IPv4/IPv6 address or a MAC written with `:`, `-` or no
+    /// separators.
+    pub fn parse(s: &str) -> Result<Needle> {
+        let s = s.trim();
+        if let Ok(ip) = s.parse() {
+            return Ok(Needle::Ip(ip));
+        }
+        let hex: String = s.chars().filter(|c| !matches!(c, ':' | '-' | '.')).collect();
+        if hex.len() == 12 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            let hex = hex.to_lowercase();
+            let pairs: Vec<&str> = (0..12).step_by(2).map(|i| &hex[i..i + 2]).collect();
+            return Ok(Needle::Mac(pairs.join(":")));
+        }
+        bail!("'{}' is neither an IP address nor a MAC address", s);
+    }
+
+    pub fn matches(&self, iface: &Interface) -> bool {
+        match self {
+            Needle::Ip(ip) => iface.ipv4.iter().chain(&iface.ipv6).any(|a| parse_ip(a) == Some(*ip)),
+            Needle::Mac(mac) => iface.mac == *mac,
+        }
+    }
+}
+
+/// Outcome of `find`.
+#[derive(Debug, Default)]
+pub struct Search {
+    /// Matching interfaces with their VM, in VM order.
+    pub found: Vec<(String, Interface)>,
+    /// VMs that could not be looked at, with the error.
+    pub failed: Vec<(String, anyhow::Error)>,
+}
+
+/// Search `vms` for interfaces matching `needle`.
+pub fn find(probe_mgr: &ProbeManager, vms: &[String], needle: &Needle) -> Search {
+    let mut search = Search::default();
+    for (vm, result) in vms.iter().zip(par_map(vms, |vm| view(probe_mgr, vm))) {
+        match result {
+            Ok(v) => search.found.extend(
+                v.interfaces.into_iter().filter(|i| needle.matches(i)).map(|i| (vm.clone(), i)),
+            ),
+            Err(e) => search.failed.push((vm.clone(), e)),
+        }
+    }
+    search
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_needles() {
+        let mac = |m: &str| Some(Needle::Mac(m.to_string()));
+        let ip = |a: &str| Some(Needle::Ip(a.parse().unwrap()));
+        let cases = [
+            ("192.168.122.10", ip("192.168.122.10")),
+            (" fe80::1 ", ip("fe80::1")),
+            ("2001:db8::5", ip("2001:db8::5")),
+            ("52:54:00:AA:bb:cc", mac("52:54:00:aa:bb:cc")),
+            ("52-54-00-aa-bb-cc", mac("52:54:00:aa:bb:cc")),
+            ("5254.00aa.bbcc", mac("52:54:00:aa:bb:cc")),
+            ("525400aabbcc", mac("52:54:00:aa:bb:cc")),
+            ("52:54:00:aa:bb", None),
+            ("52:54:00:aa:bb:cg", None),
+            ("192.168.122.10/24", None),
+            ("", None),
+        ];
+        for (input, want) in cases {
+            assert_eq!(Needle::parse(input).ok(), want, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn needle_matches() {
+        let iface = Interface {
+            mac: "52:54:00:aa:bb:cc".to_string(),
+            guest_name: Some("eth0".to_string()),
+            device: Some("vnet0".to_string()),
+            attached: Some("network:default".to_string()),
+            model: Some("virtio".to_string()),
+            ipv4: vec!["192.168.122.10/24".to_string()],
+            ipv6: vec!["fe80::5054:ff:feaa:bbcc/64".to_string()],
+            rx_bytes: None,
+            tx_bytes: None,
+        };
+        let cases = [
+            ("192.168.122.10", true),
+            ("192.168.122.1", false),
+            ("fe80:0:0:0:5054:ff:feaa:bbcc", true),
+            ("52-54-00-AA-BB-CC", true),
+            ("52:54:00:aa:bb:cd", false),
+        ];
+        for (needle, want) in cases {
+            assert_eq!(Needle::parse(needle).unwrap().matches(&iface), want, "{}", needle);
+        }
+        assert_eq!(parse_ip("10.0.0.2/8"), Some("10.0.0.2".parse().unwrap()));
+        assert_eq!(parse_ip("not an ip"), None);
+    }
+}
@@ -0,0 +1,451 @@
+// src/patch.rs
+
+//! Declarative patch specs for guest files.
+//!
+//! A spec is a TOML file naming one remote path and an ordered list of
+//! changes:
+//!
+//! ```toml
+//! path = 'C:\nps.xml'
+//!
+//! [[change]]
+//! type = "replace"              # regex replace
+//! pattern = 'Timeout="\d+"'
+//! replacement = 'Timeout="30"'
+//! expect = 'Timeout="30"'       # optional: present once applied
+//!
+//! [[change]]
+//! type = "diff"                 # unified diff, path relative to the spec
+//! file = "nps.diff"
+//!
+//! [[change]]
+//! type = "xml"                  # element text, or an attribute when given
+//! element = "/Root/Clients/Client[@name='sw1']"
+//! attribute = "Secret"
+//! value = "s3cret"
+//!
+//! [[change]]
+//! type = "ini"
+//! section = "main"              # omit for keys before the first section
+//! key = "timeout"
+//! value = "30"
+//! ```
+//!
+//! Every change is idempotent: applying a spec to an already patched file
+//! reports `AlreadyApplied`, and a file that matches neither the original
+//! nor the patched form is a `Conflict`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatchSpec {
+    /// Remote path the changes apply to, the same on every VM.
+    pub path: String,
+    #[serde(default, rename = "change")]
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Change {
+    Replace {
+        pattern: String,
+        replacement: String,
+        #[serde(default)]
+        expect: Option<String>,
+    },
+    Diff {
+        file: PathBuf,
+        /// Diff text, read from `file` when the spec is loaded.
+        #[serde(skip)]
+        text: String,
+    },
+    Xml {
+        element: String,
+        #[serde(default)]
+        attribute: Option<String>,
+        value: String,
+    },
+    Ini {
+        #[serde(default)]
+        section: Option<String>,
+        key: String,
+        value: String,
+    },
+}
+
+/// Result of applying a spec to one file.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The file changes; holds the new content.
+    Applied(String),
+    AlreadyApplied,
+    Conflict(String),
+}
+
+/// Result of a single change.
+enum Step {
+    Changed(String),
+    Unchanged,
+    Conflict(String),
+}
+
+impl PatchSpec {
+    /// Read and validate a spec. Diff files are loaded and regexes and
+    /// diffs are checked up front so bad specs fail before any VM is touched.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading patch spec {}", path.display()))?;
+        let mut spec: PatchSpec =
+            toml::from_str(&text).with_context(|| format!("parsing patch spec {}", path.display()))?;
+        if spec.changes.is_empty() {
+            bail!("patch spec {} has no [[change]] entries", path.display());
+        }
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        for (i, change) in spec.changes.iter_mut().enumerate() {
+            match change {
+                Change::Replace { pattern, expect, .. } => {
+                    Regex::new(pattern).with_context(|| format!("change {}: invalid pattern", i + 1))?;
+                    if let Some(e) = expect {
+                        Regex::new(e).with_context(|| format!("change {}: invalid expect", i + 1))?;
+                    }
+                }
+                Change::Diff { file, text } => {
+                    let diff_path = base.join(&file);
+                    *text = std::fs::read_to_string(&diff_path)
+                        .with_context(|| format!("change {}: reading {}", i + 1, diff_path.display()))?;
+                    diffy::Patch::from_str(text).with_context(|| format!("change {}: invalid diff", i + 1))?;
+                }
+                Change::Xml { element, .. } => {
+                    parse_element_path(element).with_context(|| format!("change {}: invalid element path", i + 1))?;
+                }
+                Change::Ini { .. } => {}
+            }
+        }
+        Ok(spec)
+    }
+
+    /// Apply every change in order to `content`.
+    pub fn apply(&self, content: &str) -> Outcome {
+        let mut current = content.to_string();
+        for (i, change) in self.changes.iter().enumerate() {
+            let step = match change {
+                Change::Replace { pattern, replacement, expect } => {
+                    apply_replace(&current, pattern, replacement, expect.as_deref())
+                }
+                Change::Diff { text, .. } => apply_diff(&current, text),
+                Change::Xml { element, attribute, value } => apply_xml(&current, element, attribute.as_deref(), value),
+                Change::Ini { section, key, value } => apply_ini(&current, section.as_deref(), key, value),
+            };
+            match step {
+                Step::Changed(next) => current = next,
+                Step::Unchanged => {}
+                Step::Conflict(reason) => return Outcome::Conflict(format!("change {}: {}", i + 1, reason)),
+            }
+        }
+        if current == content { Outcome::AlreadyApplied } else { Outcome::Applied(current) }
+    }
+}
+
+fn apply_replace(content: &str, pattern: &str, replacement: &str, expect: Option<&str>) -> Step {
+    // Patterns were validated in `load`.
+    let re = Regex::new(pattern).expect("validated pattern");
+    if re.is_match(content) {
+        let next = re.replace_all(content, replacement);
+        return if next == content { Step::Unchanged } else { Step::Changed(next.into_owned()) };
+    }
+    let applied = match expect {
+        Some(e) => Regex::new(e).expect("validated expect").is_match(content),
+        // Without `expect`, a literal replacement already present counts as applied.
+        None => !replacement.contains('$') && content.contains(replacement),
+    };
+    if applied { Step::Unchanged } else { Step::Conflict(format!("pattern '{}' not found", pattern)) }
+}
+
+fn apply_diff(content: &str, text: &str) -> Step {
+    let patch = match diffy::Patch::from_str(text) {
+        Ok(p) => p,
+        Err(e) => return Step::Conflict(format!("invalid diff: {}", e)),
+    };
+    match diffy::apply(content, &patch) {
+        Ok(next) => Step::Changed(next),
+        Err(_) if diffy::apply(content, &patch.reverse()).is_ok() => Step::Unchanged,
+        Err(e) => Step::Conflict(format!("diff does not apply: {}", e)),
+    }
+}
+
+/// One step of an element path: a tag name with an optional
+/// `[@attr='value']` predicate.
+struct PathSegment {
+    name: String,
+    predicate: Option<(String, String)>,
+}
+
+/// Parse `/Root/Child[@name='x']/Leaf` into segments.
+fn parse_element_path(path: &str) -> Result<Vec<PathSegment>> {
+    let mut segments = Vec::new();
+    for seg in path.trim_start_matches('/').split('/') {
+        if seg.is_empty() {
+            bail!("empty segment in '{}'", path);
+        }
+        let (name, predicate) = match seg.split_once('[') {
+            None => (seg, None),
+            Some((name, rest)) => {
+                let inner = rest
+                    .strip_suffix(']')
+                    .and_then(|r| r.strip_prefix('@'))
+                    .ok_or_else(|| anyhow::anyhow!("expected [@attr='value'] in '{}'", seg))?;
+                let (attr, value) = inner
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("expected [@attr='value'] in '{}'", seg))?;
+                let value = value.trim_matches(|c| c == '\'' || c == '"');
+                (name, Some((attr.to_string(), value.to_string())))
+            }
+        };
+        segments.push(PathSegment { name: name.to_string(), predicate });
+    }
+    Ok(segments)
+}
+
+fn xml_escape(s: &str, attribute: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if attribute => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Set an element's text or attribute by splicing the source text, so the
+/// rest of the document keeps its formatting.
+fn apply_xml(content: &str, element: &str, attribute: Option<&str>, value: &str) -> Step {
+    let doc = match roxmltree::Document::parse(content) {
+        Ok(d) => d,
+        Err(e) => return Step::Conflict(format!("not well-formed XML: {}", e)),
+    };
+    let segments = match parse_element_path(element) {
+        Ok(s) => s,
+        Err(e) => return Step::Conflict(e.to_string()),
+    };
+    let matches = |node: &roxmltree::Node, seg: &PathSegment| {
+        node.tag_name().name() == seg.name
+            && seg.predicate.as_ref().is_none_or(|(a, v)| node.attribute(a.as_str()) == Some(v.as_str()))
+    };
+
+    let mut node = doc.root_element();
+    if !matches(&node, &segments[0]) {
+        return Step::Conflict(format!("root element is not '{}'", segments[0].name));
+    }
+    for seg in &segments[1..] {
+        match node.children().find(|c| c.is_element() && matches(c, seg)) {
+            Some(child) => node = child,
+            None => return Step::Conflict(format!("element '{}' not found", element)),
+        }
+    }
+
+    let splice = |range: std::ops::Range<usize>, with: &str| {
+        let mut next = content.to_string();
+        next.replace_range(range, with);
+        Step::Changed(next)
+    };
+    let start = node.range().start;
+
+    if let Some(attr) = attribute {
+        return match node.attributes().find(|a| a.name() == attr) {
+            Some(a) if a.value() == value => Step::Unchanged,
+            Some(a) => splice(a.range_value(), &xml_escape(value, true)),
+            None => {
+                // Insert right after the tag name.
+                let name_end = content[start + 1..]
+                    .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+                    .map(|i| start + 1 + i)
+                    .unwrap_or(start + 1);
+                splice(name_end..name_end, &format!(" {}=\"{}\"", attr, xml_escape(value, true)))
+            }
+        };
+    }
+
+    if node.children().any(|c| c.is_element()) {
+        return Step::Conflict(format!("element '{}' has child elements", element));
+    }
+    if node.text().unwrap_or("") == value {
+        return Step::Unchanged;
+    }
+    let escaped = xml_escape(value, false);
+    if let Some(text) = node.children().find(|c| c.is_text()) {
+        return splice(text.range(), &escaped);
+    }
+    let end = node.range().end;
+    let source = &content[start..end];
+    if source.ends_with("/>") {
+        let qname_end = source[1..]
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .map(|i| i + 1)
+            .unwrap_or(1);
+        let qname = &source[1..qname_end];
+        splice(end - 2..end, &format!(">{}</{}>", escaped, qname))
+    } else {
+        match source.rfind("</") {
+            Some(i) => splice(start + i..start + i, &escaped),
+            None => Step::Conflict(format!("cannot locate end tag of '{}'", element)),
+        }
+    }
+}
+
+/// Set `key = value` in an INI section, adding the key or section if needed.
+fn apply_ini(content: &str, section: Option<&str>, key: &str, value: &str) -> Step {
+    let eol = if content.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let in_target = |current: &Option<String>| match (current, section) {
+        (None, None) => true,
+        (Some(c), Some(s)) => c.eq_ignore_ascii_case(s),
+        _ => false,
+    };
+
+    let mut current: Option<String> = None;
+    let mut section_found = section.is_none();
+    let mut last_in_section: Option<usize> = None;
+    for (i, line) in lines.iter().enumerate() {
+        let t = line.trim();
+        if let Some(name) = t.strip_prefix('[').and_then(|r| r.strip_suffix(']')) {
+            current = Some(name.trim().to_string());
+            if in_target(&current) {
+                section_found = true;
+                last_in_section = Some(i);
+            }
+            continue;
+        }
+        if !in_target(&current) {
+            continue;
+        }
+        if !t.is_empty() {
+            last_in_section = Some(i);
+        }
+        if t.starts_with(';') || t.starts_with('#') {
+            continue;
+        }
+        if let Some((k, v)) = line.split_once('=')
+            && k.trim().eq_ignore_ascii_case(key)
+        {
+            if v.trim() == value {
+                return Step::Unchanged;
+            }
+            let pad = &v[..v.len() - v.trim_start().len()];
+            lines[i] = format!("{}={}{}", k, pad, value);
+            return Step::Changed(join_lines(&lines, eol, content));
+        }
+    }
+
+    let entry = format!("{}={}", key, value);
+    match (section_found, last_in_section, section) {
+        (true, Some(i), _) => lines.insert(i + 1, entry),
+        // Global key in a file that starts with a section (or is empty).
+        (true, None, _) => lines.insert(0, entry),
+        (false, _, Some(s)) => {
+            if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{}]", s));
+            lines.push(entry);
+        }
+        (false, _, None) => unreachable!("a missing section implies one was named"),
+    }
+    Step::Changed(join_lines(&lines, eol, content))
+}
+
+/// Re-join lines, keeping a trailing newline if the original had one.
+fn join_lines(lines: &[String], eol: &str, original: &str) -> String {
+    let mut out = lines.join(eol);
+    if original.ends_with('\n') || original.is_empty() {
+        out.push_str(eol);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `Ok(Some(new))` when changed, `Ok(None)` when already applied and
+    /// `Err(())` on a conflict.
+    type Outcome = std::result::Result<Option<String>, ()>;
+
+    fn result(step: Step) -> Outcome {
+        match step {
+            Step::Changed(next) => Ok(Some(next)),
+            Step::Unchanged => Ok(None),
+            Step::Conflict(_) => Err(()),
+        }
+    }
+
+    #[test]
+    fn replace() {
+        let changed = |s: &str| Ok(Some(s.to_string()));
+        let cases: &[(&str, &str, &str, Option<&str>, Outcome)] = &[
+            ("Timeout=\"5\"", r#"Timeout="\d+""#, r#"Timeout="30""#, None, changed("Timeout=\"30\"")),
+            ("a=1 a=2", r"a=\d", "a=9", None, changed("a=9 a=9")),
+            // Already patched: the literal replacement is present.
+            ("Timeout=\"30\"", r#"Timeout="5""#, r#"Timeout="30""#, None, Ok(None)),
+            // The pattern matches the patched form too.
+            ("Timeout=\"30\"", r#"Timeout="\d+""#, r#"Timeout="30""#, None, Ok(None)),
+            ("x = 2", r"x = 1", "x = ${0}0", Some(r"x = 10"), Err(())),
+            ("x = 10", r"x = 1$", "x = ${0}0", Some(r"x = 10"), Ok(None)),
+            ("nothing here", "missing", "found", None, Err(())),
+        ];
+        for (content, pattern, replacement, expect, want) in cases {
+            assert_eq!(&result(apply_replace(content, pattern, replacement, *expect)), want, "{} / {}", content, pattern);
+        }
+    }
+
+    #[test]
+    fn ini() {
+        let changed = |s: &str| Ok(Some(s.to_string()));
+        let cases: &[(&str, Option<&str>, &str, &str, Outcome)] = &[
+            ("[main]\ntimeout = 5\n", Some("main"), "timeout", "30", changed("[main]\ntimeout = 30\n")),
+            ("[main]\ntimeout = 30\n", Some("main"), "timeout", "30", Ok(None)),
+            ("[Main]\nTimeout=30\n", Some("main"), "timeout", "30", Ok(None)),
+            ("[main]\nretries=2\n\n[other]\nx=1\n", Some("main"), "timeout", "30", changed("[main]\nretries=2\ntimeout=30\n\n[other]\nx=1\n")),
+            ("[main]\nx=1\n", Some("new"), "timeout", "30", changed("[main]\nx=1\n\n[new]\ntimeout=30\n")),
+            ("[main]\r\nx=1\r\n", Some("main"), "x", "2", changed("[main]\r\nx=2\r\n")),
+            ("; timeout=5\n[main]\n", Some("main"), "timeout", "30", changed("; timeout=5\n[main]\ntimeout=30\n")),
+            ("debug=0\n[main]\nx=1\n", None, "debug", "1", changed("debug=1\n[main]\nx=1\n")),
+            ("[main]\nx=1\n", None, "debug", "1", changed("debug=1\n[main]\nx=1\n")),
+            ("", Some("main"), "x", "1", changed("[main]\nx=1\n")),
+        ];
+        for (content, section, key, value, want) in cases {
+            assert_eq!(&result(apply_ini(content, *section, key, value)), want, "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn xml() {
+        let doc = "<Root>\n  <Clients>\n    <Client name=\"sw1\" Secret=\"old\"/>\n    <Client name=\"sw2\">x</Client>\n  </Clients>\n  <Empty/>\n</Root>\n";
+        let changed = |from: &str, to: &str| Ok(Some(doc.replace(from, to)));
+        let cases: &[(&str, Option<&str>, &str, Outcome)] = &[
+            ("/Root/Clients/Client[@name='sw1']", Some("Secret"), "new", changed("Secret=\"old\"", "Secret=\"new\"")),
+            ("/Root/Clients/Client[@name='sw1']", Some("Secret"), "old", Ok(None)),
+            ("/Root/Clients/Client[@name='sw2']", Some("Port"), "1812", changed("<Client name=\"sw2\">", "<Client Port=\"1812\" name=\"sw2\">")),
+            ("/Root/Clients/Client[@name='sw2']", None, "a<b", changed(">x<", ">a&lt;b<")),
+            ("/Root/Clients/Client[@name='sw2']", None, "x", Ok(None)),
+            ("/Root/Empty", None, "v", changed("<Empty/>", "<Empty>v</Empty>")),
+            ("/Root/Clients/Client[@name='sw3']", None, "v", Err(())),
+            ("/Root/Clients", None, "v", Err(())),
+            ("/Other", None, "v", Err(())),
+            ("/Root//Clients", None, "v", Err(())),
+        ];
+        for (element, attribute, value, want) in cases {
+            assert_eq!(&result(apply_xml(doc, element, *attribute, value)), want, "{} {:?}", element, attribute);
+        }
+        assert!(result(apply_xml("<Root>", "/Root", None, "v")).is_err());
+    }
+}
@@ -1,52 +1,179 @@
+// src/probe.rs
+
+//! [`ProbeManager`]: asks each VM's guest agent for its OS and command
+//! list, with a timeout per call and a TTL cache so repeated lookups during
+//! one run are cheap.
+
 use std::collections::HashMap;
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
-use crate::agent;
+use crate::agent::{self, AgentInfo};
+use crate::error::{self, Error};
 use anyhow::Result;
 
+/// Outcome of the last real agent probe of a VM.
+#[derive(Debug, Clone, Copy)]
+pub struct ProbeStatus {
+    /// The agent answered (even if it could not name the OS).
+    pub responds: bool,
+    /// How long the probe took.
+    pub latency: Duration,
+    pub at: Instant,
+}
+
 /// Simple cached probe manager. Not production hardened — illustrative only.
 pub struct ProbeManager {
-    uri: String,
     timeout_secs: u64,
     cache_ttl: Duration,
     cache: Mutex<HashMap<String, (String, Instant)>>,
+    status: Mutex<HashMap<String, ProbeStatus>>,
+    info: Mutex<HashMap<String, (AgentInfo, Instant)>>,
 }
 
 impl ProbeManager {
-    pub fn new(uri: String, timeout: Duration, cache_ttl: Duration) -> Result<Self> {
+    pub fn new(timeout: Duration, cache_ttl: Duration) -> Result<Self> {
         Ok(Self {
-            uri,
             timeout_secs: timeout.as_secs(),
             cache_ttl,
             cache: Mutex::new(HashMap::new()),
+            status: Mutex::new(HashMap::new()),
+            info: Mutex::new(HashMap::new()),
         })
     }
 
     /// Get OS string for a VM, using cache if fresh.
-    pub fn get_os(&self, vm: &str) -> Result<Option<String>> {
+    /// Fails with the reason the agent could not be asked (VM not running,
+    /// agent missing, ...) so callers can tell the user what to fix.
+    pub fn get_os(&self, vm: &str) -> error::Result<Option<String>> {
         {
             let c = self.cache.lock().unwrap();
-            if let Some((val, ts)) = c.get(vm) {
-                if ts.elapsed() < self.cache_ttl {
-                    return Ok(Some(val.clone()));
-                }
+            if let Some((val, ts)) = c.get(vm)
+                && ts.elapsed() < self.cache_ttl
+            {
+                return Ok(Some(val.clone()));
             }
         }
 
-        // 1) guest-get-osinfo
-        if let Ok(Some(s)) = agent::try_guest_get_osinfo(vm, self.timeout_secs) {
-            self.store_cache(vm, &s);
-            return Ok(Some(s));
+        let started = Instant::now();
+        let result = self.probe_os(vm);
+        let status = ProbeStatus { responds: result.is_ok(), latency: started.elapsed(), at: Instant::now() };
+        self.status.lock().unwrap().insert(vm.to_string(), status);
+        result
+    }
+
+    /// Result of the last agent probe of `vm`, probing again once it is
+    /// older than the cache TTL. Failed probes are remembered as well, so
+    /// repeated callers such as metrics scrapes do not keep waiting on
+    /// agents that do not answer.
+    pub fn status(&self, vm: &str) -> ProbeStatus {
+        if let Some(s) = self.status.lock().unwrap().get(vm)
+            && s.at.elapsed() < self.cache_ttl
+        {
+            return *s;
+        }
+        let _ = self.get_os(vm);
+        let status = self.status.lock().unwrap().get(vm).copied();
+        match status {
+            Some(s) if s.at.elapsed() < self.cache_ttl => s,
+            // get_os answered from the OS cache, so the agent replied within
+            // the TTL; keep the last measured latency.
+            last => ProbeStatus {
+                responds: true,
+                latency: last.map(|s| s.latency).unwrap_or_default(),
+                at: Instant::now(),
+            },
+        }
+    }
+
+    /// guest-info for `vm` (agent version and command policy), using cache
+    /// if fresh.
+    pub fn agent_info(&self, vm: &str) -> error::Result<AgentInfo> {
+        {
+            let c = self.info.lock().unwrap();
+            if let Some((info, ts)) = c.get(vm)
+                && ts.elapsed() < self.cache_ttl
+            {
+                return Ok(info.clone());
+            }
         }
+        let info = agent::guest_info(vm, self.timeout_secs)?;
+        self.info.lock().unwrap().insert(vm.to_string(), (info.clone(), Instant::now()));
+        Ok(info)
+    }
+
+    /// Fail before any work is done when the agent in `vm` is unreachable
+    /// or does not allow all of `commands`. If guest-info itself is blocked
+    /// or garbled the check is skipped and the commands are left to fail
+    /// on their own.
+    pub fn require(&self, vm: &str, commands: &[&str]) -> error::Result<()> {
+        match self.agent_info(vm) {
+            Ok(info) => info.check(vm, commands),
+            Err(Error::AgentCommandDisabled { .. } | Error::Protocol(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn probe_os(&self, vm: &str) -> error::Result<Option<String>> {
+        // 0) guest-info, which also tells whether the agent answers at all
+        let info = match self.agent_info(vm) {
+            Ok(info) => Some(info),
+            Err(Error::AgentCommandDisabled { .. } | Error::Protocol(_)) => None,
+            Err(e) => return Err(e),
+        };
+        let allowed = |command: &str| info.as_ref().is_none_or(|i| i.supports(command));
+
+        // 1) guest-get-osinfo
+        let attempt = if allowed("guest-get-osinfo") {
+            agent::try_guest_get_osinfo(vm, self.timeout_secs)
+        } else {
+            Ok(None)
+        };
+        let first_err = match attempt {
+            Ok(Some(s)) => {
+                self.store_cache(vm, &s);
+                return Ok(Some(s));
+            }
+            Ok(None) => None,
+            // No point trying other RPCs when the agent itself is unreachable.
+            Err(e @ (Error::DomainNotRunning(_)
+            | Error::DomainNotFound(_)
+            | Error::AgentUnavailable { .. }
+            | Error::Unreachable { .. }
+            | Error::PermissionDenied(_)
+            | Error::UnknownHost(_)
+            | Error::Timeout(_)
+            | Error::Io(_))) => return Err(e),
+            Err(e) => Some(e),
+        };
 
         // 2) guest-get-os
-        if let Ok(Some(s)) = agent::try_guest_get_os(vm, self.timeout_secs) {
+        if allowed("guest-get-os")
+            && let Ok(Some(s)) = agent::try_guest_get_os(vm, self.timeout_secs)
+        {
             self.store_cache(vm, &s);
             return Ok(Some(s));
         }
 
         // 3) guest-exec fallback (not implemented here; call into utils/virsh)
-        Ok(None)
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether the guest agent in `vm` is known to answer.
+    /// A fresh OS cache entry proves the agent replied recently; otherwise
+    /// a `guest-ping` is sent.
+    pub fn agent_responds(&self, vm: &str) -> bool {
+        {
+            let c = self.cache.lock().unwrap();
+            if let Some((_, ts)) = c.get(vm)
+                && ts.elapsed() < self.cache_ttl
+            {
+                return true;
+            }
+        }
+        agent::guest_ping(vm, self.timeout_secs).is_ok()
     }
 
     fn store_cache(&self, vm: &str, val: &str) {
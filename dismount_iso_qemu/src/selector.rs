@@ -0,0 +1,320 @@
+// src/selector.rs
+
+//! VM selector expressions shared by every flow and subcommand.
+//!
+//! A selector is a comma-separated list of groups; a VM is selected when it
+//! matches any group. Terms inside a group are joined with `+` and must all
+//! match. Terms:
+//!
+//! - `name`          exact VM name
+//! - `web-*`, `db?`  shell glob
+//! - `/regex/`       regular expression on the VM name
+//! - `all`           every defined VM
+//! - `state:running` libvirt state: running, paused or shutoff (alias off)
+//! - `os:windows`    OS family from the probe cache, or an OS name substring
+//! - `tag:prod`      user-defined tag from `[vm.<name>] tags` in the config
+//!
+//! Plain names may also be aliases from `[vm.<name>] aliases`, qualified
+//! with a host (`hv1/fs`) when `[hosts]` are configured.
+//!
+//! Example: `os:windows+state:running,tag:lab`.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+
+use crate::config::{self, Config};
+use crate::probe::ProbeManager;
+use crate::utils::{glob_match, os_family};
+use crate::virsh;
+
+#[derive(Debug)]
+enum Term {
+    All,
+    Name(String),
+    Glob(String),
+    Regex(Regex),
+    State(&'static str),
+    Os(String),
+    Tag(String),
+}
+
+#[derive(Debug)]
+pub struct Selector {
+    groups: Vec<Vec<Term>>,
+}
+
+impl Selector {
+    pub fn parse(expr: &str) -> Result<Self> {
+        Self::parse_with(config::get(), expr)
+    }
+
+    fn parse_with(cfg: &Config, expr: &str) -> Result<Self> {
+        let mut groups = Vec::new();
+        for group in split_outside_regex(expr, ',').into_iter().map(str::trim).filter(|g| !g.is_empty()) {
+            let terms = split_outside_regex(group, '+')
+                .into_iter()
+                .map(|t| parse_term(cfg, t.trim()))
+                .collect::<Result<Vec<_>>>()?;
+            groups.push(terms);
+        }
+        if groups.is_empty() {
+            bail!("empty VM selector");
+        }
+        Ok(Self { groups })
+    }
+
+    /// True unless the selector is a single exact VM name.
+    pub fn is_bulk(&self) -> bool {
+        !matches!(self.groups.as_slice(), [g] if matches!(g.as_slice(), [Term::Name(_)]))
+    }
+
+    /// Return the matching VMs in `virsh list` order.
+    pub fn resolve(&self, probe_mgr: &ProbeManager) -> Result<Vec<String>> {
+        let vms = virsh::list_vms()?;
+        self.select(&vms, &mut MatchContext { probe_mgr, cfg: config::get(), states: HashMap::new() })
+    }
+
+    fn select(&self, vms: &[String], ctx: &mut MatchContext) -> Result<Vec<String>> {
+        // Surface typos in plain names instead of silently matching nothing.
+        for term in self.groups.iter().flatten() {
+            if let Term::Name(name) = term
+                && !vms.contains(name)
+            {
+                bail!("VM '{}' not found.", name);
+            }
+        }
+
+        let mut selected = Vec::new();
+        for vm in vms {
+            let mut hit = false;
+            for group in &self.groups {
+                let mut all = true;
+                for term in group {
+                    if !ctx.matches(term, vm)? {
+                        all = false;
+                        break;
+                    }
+                }
+                if all {
+                    hit = true;
+                    break;
+                }
+            }
+            if hit {
+                selected.push(vm.clone());
+            }
+        }
+        Ok(selected)
+    }
+}
+
+/// Split on `sep`, ignoring separators inside `/regex/` terms so patterns
+/// like `/^db-\d+$/` survive intact. Only a `/` that opens a term starts
+/// a regex, so qualified names such as `hv1/web` split normally; inside a
+/// regex `\/` does not close it.
+fn split_outside_regex(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_regex = false;
+    let mut escaped = false;
+    let mut term_start = true;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if in_regex {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '/' => in_regex = false,
+                _ => {}
+            }
+            continue;
+        }
+        if c == sep {
+            parts.push(&s[start..i]);
+            start = i + c.len_utf8();
+        } else if c == '/' && term_start {
+            in_regex = true;
+        }
+        term_start = matches!(c, ',' | '+') || (term_start && c.is_whitespace());
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn parse_term(cfg: &Config, t: &str) -> Result<Term> {
+    if t.is_empty() {
+        bail!("empty term in VM selector");
+    }
+    if t == "all" {
+        return Ok(Term::All);
+    }
+    if let Some(state) = t.strip_prefix("state:") {
+        let flag = match state {
+            "running" => "--state-running",
+            "paused" => "--state-paused",
+            "shutoff" | "off" => "--state-shutoff",
+            other => bail!("unknown state '{}' (use running, paused or shutoff)", other),
+        };
+        return Ok(Term::State(flag));
+    }
+    if let Some(os) = t.strip_prefix("os:") {
+        return Ok(Term::Os(os.to_lowercase()));
+    }
+    if let Some(tag) = t.strip_prefix("tag:") {
+        return Ok(Term::Tag(tag.to_string()));
+    }
+    if let Some(re) = t.strip_prefix('/').and_then(|r| r.strip_suffix('/')) {
+        let re = Regex::new(re).with_context(|| format!("invalid regex '{}'", re))?;
+        return Ok(Term::Regex(re));
+    }
+    if t.contains(['*', '?']) {
+        return Ok(Term::Glob(t.to_string()));
+    }
+    Ok(Term::Name(cfg.resolve_alias(t)))
+}
+
+/// Lazily fetched data needed by some terms, shared across all VMs.
+struct MatchContext<'a> {
+    probe_mgr: &'a ProbeManager,
+    cfg: &'a Config,
+    states: HashMap<&'static str, Vec<String>>,
+}
+
+impl MatchContext<'_> {
+    fn matches(&mut self, term: &Term, vm: &str) -> Result<bool> {
+        Ok(match term {
+            Term::All => true,
+            Term::Name(n) => n == vm,
+            Term::Glob(g) => glob_match(g, vm),
+            Term::Regex(re) => re.is_match(vm),
+            Term::State(flag) => {
+                if !self.states.contains_key(flag) {
+                    self.states.insert(flag, virsh::list_vms_filtered(&[flag])?);
+                }
+                self.states[flag].iter().any(|v| v == vm)
+            }
+            Term::Os(want) => match self.probe_mgr.get_os(vm) {
+                Ok(Some(os)) => os_family(&os) == want || os.to_lowercase().contains(want.as_str()),
+                _ => false,
+            },
+            Term::Tag(tag) => self.cfg.tags(vm).iter().any(|t| t == tag),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Terms of each group, rendered for comparison.
+    fn groups(expr: &str) -> Vec<Vec<String>> {
+        Selector::parse(expr)
+            .unwrap()
+            .groups
+            .iter()
+            .map(|g| {
+                g.iter()
+                    .map(|t| match t {
+                        Term::All => "all".to_string(),
+                        Term::Name(n) => format!("name:{}", n),
+                        Term::Glob(g) => format!("glob:{}", g),
+                        Term::Regex(re) => format!("regex:{}", re.as_str()),
+                        Term::State(flag) => format!("state:{}", flag),
+                        Term::Os(os) => format!("os:{}", os),
+                        Term::Tag(tag) => format!("tag:{}", tag),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_selectors() {
+        let cases: &[(&str, &[&[&str]])] = &[
+            ("fs00", &[&["name:fs00"]]),
+            ("hv1/a,hv2/b", &[&["name:hv1/a"], &["name:hv2/b"]]),
+            ("hv1/*+state:running", &[&["glob:hv1/*", "state:--state-running"]]),
+            ("hv1/a, hv2/web-*+tag:lab", &[&["name:hv1/a"], &["glob:hv2/web-*", "tag:lab"]]),
+            ("/^db-\\d{1,3}$/,web", &[&["regex:^db-\\d{1,3}$"], &["name:web"]]),
+            ("os:windows+/a,b/", &[&["os:windows", "regex:a,b"]]),
+            ("/a\\/b+c/+all", &[&["regex:a\\/b+c", "all"]]),
+            ("state:off,tag:prod", &[&["state:--state-shutoff"], &["tag:prod"]]),
+            ("a,,b,", &[&["name:a"], &["name:b"]]),
+        ];
+        for (expr, want) in cases {
+            let want: Vec<Vec<String>> = want.iter().map(|g| g.iter().map(|t| t.to_string()).collect()).collect();
+            assert_eq!(groups(expr), want, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn split_terms() {
+        let cases: &[(&str, char, &[&str])] = &[
+            ("a,b", ',', &["a", "b"]),
+            ("hv1/a,hv2/b", ',', &["hv1/a", "hv2/b"]),
+            ("/x,y/,z", ',', &["/x,y/", "z"]),
+            ("a+ /x+y/+b", '+', &["a", " /x+y/", "b"]),
+            ("a/b+c", '+', &["a/b", "c"]),
+            ("", ',', &[""]),
+        ];
+        for (s, sep, want) in cases {
+            assert_eq!(split_outside_regex(s, *sep), *want, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn parse_term_errors() {
+        for t in ["", "state:sleeping", "/[/"] {
+            assert!(parse_term(&Config::default(), t).is_err(), "{:?}", t);
+        }
+        for expr in [" , ", "a++b", "web+/[/"] {
+            assert!(Selector::parse(expr).is_err(), "{:?}", expr);
+        }
+    }
+
+    #[test]
+    fn hosts_tags_and_aliases() {
+        let cfg: Config = toml::from_str(
+            r#"
+            [hosts]
+            hv1 = "qemu+ssh://hv1/system"
+            hv2 = "qemu+ssh://hv2/system"
+
+            [vm.fs00]
+            aliases = ["fs"]
+            tags = ["lab"]
+            remote_path = "/srv/share"
+
+            [vm."hv2/web"]
+            aliases = ["www"]
+            tags = ["prod"]
+            "#,
+        )
+        .unwrap();
+        let vms: Vec<String> = ["hv1/fs00", "hv2/fs00", "hv1/web", "hv2/web"].iter().map(|v| v.to_string()).collect();
+        let probe_mgr = ProbeManager::new(std::time::Duration::from_secs(1), std::time::Duration::from_secs(1)).unwrap();
+        let cases: &[(&str, &[&str])] = &[
+            ("tag:lab", &["hv1/fs00", "hv2/fs00"]),
+            ("tag:prod", &["hv2/web"]),
+            ("hv1/fs", &["hv1/fs00"]),
+            ("hv2/fs+tag:lab", &["hv2/fs00"]),
+            ("www", &["hv2/web"]),
+            ("hv2/www", &["hv2/web"]),
+            ("hv1/*+tag:lab,tag:prod", &["hv1/fs00", "hv2/web"]),
+        ];
+        for (expr, want) in cases {
+            let selector = Selector::parse_with(&cfg, expr).unwrap();
+            let mut ctx = MatchContext { probe_mgr: &probe_mgr, cfg: &cfg, states: HashMap::new() };
+            assert_eq!(selector.select(&vms, &mut ctx).unwrap(), *want, "{}", expr);
+        }
+
+        // An alias of another host's VM is not resolved, and is reported.
+        let selector = Selector::parse_with(&cfg, "hv1/www").unwrap();
+        let mut ctx = MatchContext { probe_mgr: &probe_mgr, cfg: &cfg, states: HashMap::new() };
+        assert!(selector.select(&vms, &mut ctx).is_err());
+
+        assert_eq!(cfg.remote_path("hv1/fs00"), Some("/srv/share"));
+        assert_eq!(cfg.tags("hv1/web"), &[] as &[String]);
+    }
+}
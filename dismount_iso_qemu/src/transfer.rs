@@ -0,0 +1,435 @@
+// src/transfer.rs
+
+//! Streaming guest file transfers that survive agent hiccups.
+//!
+//! Data is moved one `[transfer] chunk_size` block at a time and handed to
+//! the sink (or taken from the source) as it goes, so nothing larger than a
+//! chunk is held in memory. When an agent call fails with an error that can
+//! clear up by itself (timeout, agent restarting, garbled reply), the
+//! handle is dropped, and after a backoff the file is reopened and
+//! `guest-file-seek` moves to the last offset that was confirmed: written
+//! to the sink for downloads, acknowledged by guest-file-write for uploads.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+use crate::config;
+use crate::dryrun;
+use crate::error::{Error, Result};
+use crate::virsh;
+
+/// Longest wait between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Progress of a transfer, reported as it happens.
+#[derive(Debug)]
+pub enum Event {
+    /// Bytes confirmed so far, and the file size when known.
+    Progress { done: u64, total: Option<u64> },
+    /// An agent call failed; the transfer resumes after `delay`.
+    Retry { attempt: u32, delay: Duration, error: Error },
+}
+
+/// Errors that may go away on their own. Anything else (missing file,
+/// permission, disabled command, VM gone) fails the transfer at once.
+fn transient(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Timeout(_) | Error::AgentUnavailable { .. } | Error::Protocol(_) | Error::Unreachable { .. } | Error::Virsh { .. }
+    )
+}
+
+/// The guest-file-* calls a transfer makes.
+trait GuestFiles {
+    fn open(&mut self, path: &str, mode: &str) -> Result<i64>;
+    fn read(&mut self, handle: i64, count: usize) -> Result<(Vec<u8>, bool)>;
+    fn write(&mut self, handle: i64, data: &[u8]) -> Result<usize>;
+    fn seek(&mut self, handle: i64, offset: i64, whence: &str) -> Result<u64>;
+    fn close(&mut self, handle: i64) -> Result<()>;
+}
+
+/// The agent of a VM, through virsh.
+struct Agent<'a>(&'a str);
+
+impl GuestFiles for Agent<'_> {
+    fn open(&mut self, path: &str, mode: &str) -> Result<i64> {
+        virsh::ga_file_open(self.0, path, mode)
+    }
+
+    fn read(&mut self, handle: i64, count: usize) -> Result<(Vec<u8>, bool)> {
+        virsh::ga_file_read(self.0, handle, count)
+    }
+
+    fn write(&mut self, handle: i64, data: &[u8]) -> Result<usize> {
+        virsh::ga_file_write(self.0, handle, data)
+    }
+
+    fn seek(&mut self, handle: i64, offset: i64, whence: &str) -> Result<u64> {
+        virsh::ga_file_seek(self.0, handle, offset, whence)
+    }
+
+    fn close(&mut self, handle: i64) -> Result<()> {
+        virsh::ga_file_close(self.0, handle)
+    }
+}
+
+/// Counts consecutive failures and sleeps with exponential backoff.
+struct Backoff {
+    failures: u32,
+    sleep: fn(Duration),
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Backoff { failures: 0, sleep: std::thread::sleep }
+    }
+
+    /// Sleep before the next attempt, or give `e` back when it is not worth
+    /// retrying or the retries are used up.
+    fn wait(&mut self, e: Error, on_event: &mut dyn FnMut(Event)) -> Result<()> {
+        if !transient(&e) || self.failures >= config::get().transfer.retries {
+            return Err(e);
+        }
+        self.failures += 1;
+        let delay = Duration::from_secs(1 << (self.failures - 1).min(5)).min(MAX_BACKOFF);
+        on_event(Event::Retry { attempt: self.failures, delay, error: e });
+        (self.sleep)(delay);
+        Ok(())
+    }
+}
+
+/// Open `path` and position it at `offset`. Also returns the file size when
+/// the agent can seek to its end (not for pipes and some devices).
+fn open_at(files: &mut impl GuestFiles, path: &str, mode: &str, offset: u64) -> Result<(i64, Option<u64>)> {
+    let handle = files.open(path, mode)?;
+    let total = files.seek(handle, 0, "end").ok();
+    if (total.is_some() || offset > 0)
+        && let Err(e) = files.seek(handle, offset as i64, "set")
+    {
+        let _ = files.close(handle);
+        return Err(e);
+    }
+    Ok((handle, total))
+}
+
+/// Stream `path` in `vm` into `sink`, starting at byte `offset` (the
+/// length of a partial earlier download, or 0). Returns the number of
+/// bytes in the whole file.
+pub fn download(vm: &str, path: &str, sink: &mut dyn Write, offset: u64, on_event: &mut dyn FnMut(Event)) -> Result<u64> {
+    download_via(&mut Agent(vm), Backoff::new(), vm, path, sink, offset, on_event)
+}
+
+fn download_via(
+    files: &mut impl GuestFiles,
+    mut backoff: Backoff,
+    vm: &str,
+    path: &str,
+    sink: &mut dyn Write,
+    offset: u64,
+    on_event: &mut dyn FnMut(Event),
+) -> Result<u64> {
+    let chunk_size = config::get().transfer.chunk_size;
+
+    // A failing first open is about the path, so it is not retried.
+    let (first, total) = open_at(files, path, "r", offset)?;
+    if let Some(total) = total
+        && total < offset
+    {
+        let _ = files.close(first);
+        return Err(Error::Protocol(format!(
+            "'{}' in '{}' is {} bytes, shorter than the {} bytes already downloaded",
+            path, vm, total, offset
+        )));
+    }
+
+    let mut handle = Some(first);
+    let mut done = offset;
+    loop {
+        let result = match handle {
+            Some(h) => files.read(h, chunk_size),
+            None => open_at(files, path, "r", done).and_then(|(h, _)| {
+                handle = Some(h);
+                files.read(h, chunk_size)
+            }),
+        }
+        .and_then(|(data, eof)| {
+            if data.is_empty() && !eof {
+                return Err(Error::Protocol("guest-file-read returned nothing before end of file".to_string()));
+            }
+            Ok((data, eof))
+        });
+        match result {
+            Ok((data, eof)) => {
+                sink.write_all(&data)?;
+                done += data.len() as u64;
+                backoff.failures = 0;
+                on_event(Event::Progress { done, total });
+                if eof {
+                    break;
+                }
+            }
+            Err(e) => {
+                if let Some(h) = handle.take() {
+                    let _ = files.close(h);
+                }
+                backoff.wait(e, on_event)?;
+            }
+        }
+    }
+    sink.flush()?;
+    if let Some(h) = handle {
+        // Everything has been read; a failed close loses nothing.
+        let _ = files.close(h);
+    }
+    Ok(done)
+}
+
+/// Stream `source` into `path` in `vm`, replacing the file. Returns the
+/// number of bytes written.
+pub fn upload<S: Read + Seek>(vm: &str, path: &str, source: &mut S, on_event: &mut dyn FnMut(Event)) -> Result<u64> {
+    if dryrun::enabled() {
+        let total = source.seek(SeekFrom::End(0))?;
+        plan_upload(vm, path, total, config::get().transfer.chunk_size)?;
+        return Ok(total);
+    }
+    upload_via(&mut Agent(vm), Backoff::new(), path, source, on_event)
+}
+
+fn upload_via<S: Read + Seek>(
+    files: &mut impl GuestFiles,
+    mut backoff: Backoff,
+    path: &str,
+    source: &mut S,
+    on_event: &mut dyn FnMut(Event),
+) -> Result<u64> {
+    let chunk_size = config::get().transfer.chunk_size;
+    let total = source.seek(SeekFrom::End(0))?;
+
+    // "w" truncates; reopening after a failure must not, hence "r+".
+    let mut handle = Some(files.open(path, "w")?);
+    let mut done = 0u64;
+    let mut buf = vec![0u8; chunk_size];
+    while done < total {
+        source.seek(SeekFrom::Start(done))?;
+        let n = read_full(source, &mut buf)?;
+        let result = match handle {
+            Some(h) => files.write(h, &buf[..n]),
+            None => open_at(files, path, "r+", done).and_then(|(h, _)| {
+                handle = Some(h);
+                files.write(h, &buf[..n])
+            }),
+        }
+        .and_then(|count| match count {
+            0 => Err(Error::Protocol("guest-file-write wrote nothing".to_string())),
+            count => Ok(count),
+        });
+        match result {
+            Ok(count) => {
+                done += count as u64;
+                backoff.failures = 0;
+                on_event(Event::Progress { done, total: Some(total) });
+            }
+            Err(e) => {
+                if let Some(h) = handle.take() {
+                    let _ = files.close(h);
+                }
+                backoff.wait(e, on_event)?;
+            }
+        }
+    }
+
+    // Closing flushes the guest's buffers, so its failure is a failed write.
+    let h = match handle {
+        Some(h) => h,
+        None => open_at(files, path, "r+", done)?.0,
+    };
+    files.close(h)?;
+    Ok(done)
+}
+
+/// Print the agent calls an upload of `total` bytes would make. The file
+/// data is shown by size only.
+fn plan_upload(vm: &str, path: &str, total: u64, chunk_size: usize) -> Result<()> {
+    let timeout = config::get().timeouts.agent_file;
+    let call = |payload: serde_json::Value| virsh::agent_dry_run(vm, &payload.to_string(), timeout);
+    call(serde_json::json!({"execute": "guest-file-open", "arguments": {"path": path, "mode": "w"}}))?;
+    let writes = total.div_ceil(chunk_size as u64);
+    if writes > 0 {
+        let first = total.min(chunk_size as u64);
+        call(serde_json::json!({
+            "execute": "guest-file-write",
+            "arguments": {"handle": "<handle>", "buf-b64": format!("<{} bytes>", first)}
+        }))?;
+    }
+    if writes > 1 {
+        dryrun::note(&format!("... {} guest-file-write calls in all, {} bytes", writes, total));
+    }
+    call(serde_json::json!({"execute": "guest-file-close", "arguments": {"handle": "<handle>"}}))?;
+    Ok(())
+}
+
+/// Fill `buf` as far as the source allows; short only at end of input.
+fn read_full(source: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match source.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A guest file behind an agent that fails the calls numbered in
+    /// `fail` (counting from 1) and writes at most `max_write` bytes a call.
+    struct Flaky {
+        file: Vec<u8>,
+        pos: usize,
+        calls: u32,
+        fail: Vec<u32>,
+        max_write: usize,
+        /// Modes of every open and offsets of every `set` seek.
+        opens: Vec<String>,
+        seeks: Vec<u64>,
+    }
+
+    impl Flaky {
+        fn new(file: Vec<u8>, fail: &[u32]) -> Self {
+            Flaky { file, pos: 0, calls: 0, fail: fail.to_vec(), max_write: usize::MAX, opens: Vec::new(), seeks: Vec::new() }
+        }
+
+        fn call(&mut self) -> Result<()> {
+            self.calls += 1;
+            if self.fail.contains(&self.calls) {
+                return Err(Error::Timeout(format!("call {}", self.calls)));
+            }
+            Ok(())
+        }
+    }
+
+    impl GuestFiles for Flaky {
+        fn open(&mut self, _path: &str, mode: &str) -> Result<i64> {
+            self.call()?;
+            if mode == "w" {
+                self.file.clear();
+            }
+            self.opens.push(mode.to_string());
+            self.pos = 0;
+            Ok(1)
+        }
+
+        fn read(&mut self, _handle: i64, count: usize) -> Result<(Vec<u8>, bool)> {
+            self.call()?;
+            let end = (self.pos + count).min(self.file.len());
+            let data = self.file[self.pos..end].to_vec();
+            self.pos = end;
+            Ok((data, end == self.file.len()))
+        }
+
+        fn write(&mut self, _handle: i64, data: &[u8]) -> Result<usize> {
+            self.call()?;
+            let n = data.len().min(self.max_write);
+            let end = self.pos + n;
+            if self.file.len() < end {
+                self.file.resize(end, 0);
+            }
+            self.file[self.pos..end].copy_from_slice(&data[..n]);
+            self.pos = end;
+            Ok(n)
+        }
+
+        fn seek(&mut self, _handle: i64, offset: i64, whence: &str) -> Result<u64> {
+            self.call()?;
+            self.pos = match whence {
+                "end" => self.file.len(),
+                _ => {
+                    self.seeks.push(offset as u64);
+                    offset as usize
+                }
+            };
+            Ok(self.pos as u64)
+        }
+
+        fn close(&mut self, _handle: i64) -> Result<()> {
+            self.call()
+        }
+    }
+
+    fn no_wait() -> Backoff {
+        Backoff { failures: 0, sleep: |_| {} }
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn download_resumes_at_confirmed_offset() {
+        let chunk = config::get().transfer.chunk_size;
+        let file = data(chunk * 3 + 100);
+        // Calls: open, seek end, seek set, read, read, then the third read fails.
+        let mut guest = Flaky::new(file.clone(), &[6]);
+        let mut sink = Vec::new();
+        let mut retries = 0;
+        let total = download_via(&mut guest, no_wait(), "vm", "/f", &mut sink, 0, &mut |e| {
+            if let Event::Retry { .. } = e {
+                retries += 1;
+            }
+        })
+        .unwrap();
+        assert_eq!(total, file.len() as u64);
+        assert_eq!(sink, file);
+        assert_eq!(retries, 1);
+        assert_eq!(guest.seeks, [0, 2 * chunk as u64]);
+    }
+
+    #[test]
+    fn download_continues_partial_file() {
+        let file = data(10_000);
+        let mut guest = Flaky::new(file.clone(), &[]);
+        let mut sink = Vec::new();
+        let total = download_via(&mut guest, no_wait(), "vm", "/f", &mut sink, 6_000, &mut |_| {}).unwrap();
+        assert_eq!(total, 10_000);
+        assert_eq!(sink, &file[6_000..]);
+        assert_eq!(guest.seeks, [6_000]);
+
+        let mut guest = Flaky::new(data(100), &[]);
+        let err = download_via(&mut guest, no_wait(), "vm", "/f", &mut Vec::new(), 200, &mut |_| {}).unwrap_err();
+        assert!(err.to_string().contains("shorter than the 200 bytes"), "{}", err);
+    }
+
+    #[test]
+    fn upload_resumes_without_truncating() {
+        let chunk = config::get().transfer.chunk_size;
+        let source = data(chunk * 2 + 7);
+        // Short writes move the confirmed offset off chunk boundaries.
+        // Calls: open, write, write, then the third write fails.
+        let mut guest = Flaky::new(Vec::new(), &[4]);
+        guest.max_write = chunk / 2 + 1;
+        let done = upload_via(&mut guest, no_wait(), "/f", &mut Cursor::new(source.clone()), &mut |_| {}).unwrap();
+        assert_eq!(done, source.len() as u64);
+        assert_eq!(guest.file, source);
+        assert_eq!(guest.opens, ["w", "r+"]);
+        assert_eq!(guest.seeks, [2 * (chunk / 2 + 1) as u64]);
+    }
+
+    #[test]
+    fn gives_up_on_lasting_errors() {
+        // Every call after the first open and its seeks fails.
+        let fail: Vec<u32> = (4..100).collect();
+        let mut guest = Flaky::new(data(100), &fail);
+        let mut retries = 0;
+        let result = download_via(&mut guest, no_wait(), "vm", "/f", &mut Vec::new(), 0, &mut |e| {
+            if let Event::Retry { .. } = e {
+                retries += 1;
+            }
+        });
+        assert!(matches!(result, Err(Error::Timeout(_))));
+        assert_eq!(retries, config::get().transfer.retries);
+    }
+}
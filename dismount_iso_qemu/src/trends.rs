@@ -0,0 +1,380 @@
+// src/trends.rs
+
+//! Resource history: periodic samples of memory use and CPU time per VM,
+//! summarised over fixed windows for right-sizing.
+//!
+//! Each VM has an append-only JSONL file `<history dir>/<vm>.jsonl`, or
+//! `<history dir>/<host>/<vm>.jsonl` for a VM on a `[hosts]` entry, with
+//! one `Sample` per line. Memory use is what the guest reports through its
+//! balloon driver (`virsh dommemstat`) when it does, since that is what a
+//! right-sizing decision needs; otherwise it falls back to the memory
+//! libvirt has given the VM, which for most guests is simply the maximum.
+//! CPU time is cumulative, so the CPU used over an interval is the
+//! difference between two samples.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::dryrun;
+use crate::utils::{par_map, parse_cpu_time_to_seconds, parse_dominfo, parse_dommemstat, state_dir, unix_now};
+use crate::virsh;
+
+/// Windows shown by the trends report, with their labels.
+pub const WINDOWS: [(&str, u64); 3] = [("1h", 3_600), ("24h", 86_400), ("7d", 604_800)];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    /// Seconds since the epoch.
+    pub ts: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub used_kib: Option<u64>,
+    /// `used_kib` came from the guest's balloon statistics rather than
+    /// from the memory libvirt allotted.
+    #[serde(default)]
+    pub balloon: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_kib: Option<u64>,
+    /// Cumulative CPU seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_s: Option<u64>,
+}
+
+/// Directory holding every VM's samples.
+pub fn dir() -> Result<PathBuf> {
+    match &config::get().history.dir {
+        Some(dir) => Ok(dir.clone()),
+        None => Ok(state_dir()
+            .ok_or_else(|| anyhow!("cannot locate state directory: HOME is not set"))?
+            .join("history")),
+    }
+}
+
+/// `<dir>/<vm>.jsonl` for a local VM and `<dir>/<host>/<vm>.jsonl` for a
+/// host-qualified one, so `hv1/web` and a local `hv1_web` stay apart.
+fn file(vm: &str) -> Result<PathBuf> {
+    let dir = dir()?;
+    Ok(match vm.split_once('/') {
+        Some((host, name)) => dir.join(host).join(format!("{}.jsonl", name)),
+        None => dir.join(format!("{}.jsonl", vm)),
+    })
+}
+
+/// Every history file: those in `dir` and in its per-host subdirectories.
+fn files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", dir.display())),
+    };
+    let mut files = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            let host = std::fs::read_dir(&path).with_context(|| format!("reading {}", path.display()))?;
+            files.extend(host.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| is_history(p)));
+        } else if is_history(&path) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn is_history(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "jsonl")
+}
+
+/// Take one sample of `vm`; `None` when it is not running.
+pub fn sample(vm: &str) -> Result<Option<Sample>> {
+    if virsh::domstate(vm)? != "running" {
+        return Ok(None);
+    }
+    let info = parse_dominfo(&virsh::dominfo_raw(vm)?);
+    // Balloon statistics are optional; without them only the host view is left.
+    let stats = virsh::dommemstat_raw(vm).map(|raw| parse_dommemstat(&raw)).unwrap_or_default();
+    let guest_used = stats
+        .get("available")
+        .and_then(|available| stats.get("usable").or(stats.get("unused")).map(|free| available.saturating_sub(*free)));
+    Ok(Some(Sample {
+        ts: unix_now(),
+        used_kib: guest_used.or(info.used_memory_mb),
+        balloon: guest_used.is_some(),
+        max_kib: info.max_memory_mb,
+        cpu_s: info.cpu_time.as_deref().and_then(parse_cpu_time_to_seconds),
+    }))
+}
+
+/// Append `sample` to the history of `vm`.
+pub fn append(vm: &str, sample: &Sample) -> Result<()> {
+    let path = file(vm)?;
+    if dryrun::enabled() {
+        dryrun::note(&format!("append a sample of '{}' to {}", vm, path.display()));
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    let mut out = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("opening {}", path.display()))?;
+    writeln!(out, "{}", serde_json::to_string(sample)?).with_context(|| format!("writing {}", path.display()))
+}
+
+/// Outcome of one `record` round.
+#[derive(Debug, Default)]
+pub struct Round {
+    pub recorded: usize,
+    /// VMs skipped because they are not running.
+    pub stopped: usize,
+    pub failed: Vec<(String, anyhow::Error)>,
+}
+
+/// Sample every VM in `vms` and append the samples.
+pub fn record(vms: &[String]) -> Round {
+    let mut round = Round::default();
+    let results = par_map(vms, |vm| -> Result<bool> {
+        match sample(vm)? {
+            Some(sample) => append(vm, &sample).map(|_| true),
+            None => Ok(false),
+        }
+    });
+    for (vm, result) in vms.iter().zip(results) {
+        match result {
+            Ok(true) => round.recorded += 1,
+            Ok(false) => round.stopped += 1,
+            Err(e) => round.failed.push((vm.clone(), e)),
+        }
+    }
+    round
+}
+
+/// Samples of `vm` taken at or after `since`, oldest first. Lines that do
+/// not parse are skipped.
+pub fn load(vm: &str, since: u64) -> Result<Vec<Sample>> {
+    let path = file(vm)?;
+    let f = match File::open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("opening {}", path.display())),
+    };
+    let mut samples = Vec::new();
+    for line in BufReader::new(f).lines() {
+        let line = line.with_context(|| format!("reading {}", path.display()))?;
+        if let Ok(s) = serde_json::from_str::<Sample>(&line)
+            && s.ts >= since
+        {
+            samples.push(s);
+        }
+    }
+    Ok(samples)
+}
+
+/// Drop samples older than `[history] keep_days` from every file. Returns
+/// how many were removed.
+pub fn prune() -> Result<usize> {
+    let keep_days = config::get().history.keep_days;
+    if keep_days == 0 {
+        return Ok(0);
+    }
+    let cutoff = unix_now().saturating_sub(keep_days * 86_400);
+    let mut removed = 0;
+    for path in files(&dir()?)? {
+        let text = std::fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        let kept: Vec<&str> = text
+            .lines()
+            .filter(|l| serde_json::from_str::<Sample>(l).is_ok_and(|s| s.ts >= cutoff))
+            .collect();
+        let dropped = text.lines().count() - kept.len();
+        if dropped == 0 {
+            continue;
+        }
+        removed += dropped;
+        if dryrun::enabled() {
+            dryrun::note(&format!("remove {} old sample(s) from {}", dropped, path.display()));
+            continue;
+        }
+        // Write then rename so a crash never leaves a truncated history.
+        let tmp = path.with_extension("jsonl.tmp");
+        let mut out = kept.join("\n");
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        std::fs::write(&tmp, out).with_context(|| format!("writing {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("renaming {}", tmp.display()))?;
+    }
+    Ok(removed)
+}
+
+/// Summary of the samples in one window, split into equal buckets for
+/// sparklines. Empty buckets (no samples, VM off) are `None`.
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub samples: usize,
+    /// Seconds between the first and last sample in the window.
+    pub span: u64,
+    pub mem_avg_kib: Option<u64>,
+    pub mem_peak_kib: Option<u64>,
+    /// Average memory use per bucket, in KiB.
+    pub mem: Vec<Option<f64>>,
+    /// Average CPU use in cores (1.0 is one core busy).
+    pub cpu_avg: Option<f64>,
+    pub cpu: Vec<Option<f64>>,
+}
+
+/// Summarise the `secs` seconds of `samples` up to `now` into `buckets`
+/// buckets. CPU use comes from consecutive sample pairs; a pair whose CPU
+/// time went backwards spans a restart and is left out.
+pub fn summarize(samples: &[Sample], now: u64, secs: u64, buckets: usize) -> Window {
+    let start = now.saturating_sub(secs);
+    let window: Vec<&Sample> = samples.iter().filter(|s| s.ts >= start && s.ts <= now).collect();
+    let bucket = |ts: u64| ((ts - start) as usize * buckets / secs.max(1) as usize).min(buckets - 1);
+
+    let mut mem = vec![(0.0, 0usize); buckets];
+    let mut mem_total = (0u64, 0u64);
+    for s in &window {
+        if let Some(used) = s.used_kib {
+            let b = &mut mem[bucket(s.ts)];
+            b.0 += used as f64;
+            b.1 += 1;
+            mem_total.0 += used;
+            mem_total.1 += 1;
+        }
+    }
+
+    // (CPU seconds, wall seconds) per bucket, by the end of each pair.
+    let mut cpu = vec![(0.0, 0.0); buckets];
+    let mut cpu_total = (0u64, 0u64);
+    for pair in window.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if let (Some(ca), Some(cb)) = (a.cpu_s, b.cpu_s)
+            && cb >= ca
+            && b.ts > a.ts
+        {
+            let c = &mut cpu[bucket(b.ts)];
+            c.0 += (cb - ca) as f64;
+            c.1 += (b.ts - a.ts) as f64;
+            cpu_total.0 += cb - ca;
+            cpu_total.1 += b.ts - a.ts;
+        }
+    }
+
+    Window {
+        samples: window.len(),
+        span: match (window.first(), window.last()) {
+            (Some(first), Some(last)) => last.ts - first.ts,
+            _ => 0,
+        },
+        mem_avg_kib: (mem_total.1 > 0).then(|| mem_total.0 / mem_total.1),
+        mem_peak_kib: window.iter().filter_map(|s| s.used_kib).max(),
+        mem: mem.into_iter().map(|(sum, n)| (n > 0).then(|| sum / n as f64)).collect(),
+        cpu_avg: (cpu_total.1 > 0).then(|| cpu_total.0 as f64 / cpu_total.1 as f64),
+        cpu: cpu.into_iter().map(|(c, t)| (t > 0.0).then(|| c / t)).collect(),
+    }
+}
+
+/// Render `values` as a sparkline scaled to `max`; gaps are blank.
+pub fn sparkline(values: &[Option<f64>], max: f64) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    values
+        .iter()
+        .map(|v| match v {
+            Some(v) if max > 0.0 => BARS[((v / max * 7.0).round() as usize).min(7)],
+            Some(_) => BARS[0],
+            None => ' ',
+        })
+        .collect()
+}
+
+/// Maximum memory for a VM whose peak use is `peak_kib`: the peak plus a
+/// quarter, rounded up to 256 MiB.
+pub fn suggested_max_kib(peak_kib: u64) -> u64 {
+    const STEP: u64 = 256 * 1024;
+    (peak_kib + peak_kib / 4).div_ceil(STEP) * STEP
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(ts: u64, used_kib: u64, cpu_s: u64) -> Sample {
+        Sample { ts, used_kib: Some(used_kib), balloon: true, max_kib: Some(1000), cpu_s: Some(cpu_s) }
+    }
+
+    fn close(a: Option<f64>, b: f64) -> bool {
+        a.is_some_and(|a| (a - b).abs() < 1e-9)
+    }
+
+    #[test]
+    fn summarize_window() {
+        let now = 10_000;
+        let start = now - 3_600;
+        let samples = [
+            sample(start - 300, 999, 0), // before the window
+            sample(start, 100, 0),
+            sample(start + 900, 200, 90),
+            sample(start + 1_800, 300, 180),
+            sample(start + 2_700, 400, 180),
+        ];
+        let w = summarize(&samples, now, 3_600, 2);
+        assert_eq!(w.samples, 4);
+        assert_eq!(w.span, 2_700);
+        assert_eq!(w.mem_avg_kib, Some(250));
+        assert_eq!(w.mem_peak_kib, Some(400));
+        assert_eq!(w.mem, vec![Some(150.0), Some(350.0)]);
+        // 90 CPU seconds in the first 900s, 90 in the next 1800s.
+        assert!(close(w.cpu[0], 0.1));
+        assert!(close(w.cpu[1], 0.05));
+        assert!(close(w.cpu_avg, 180.0 / 2_700.0));
+    }
+
+    #[test]
+    fn summarize_gaps_and_restarts() {
+        let now = 10_000;
+        let start = now - 3_600;
+        // CPU time drops after a restart; that pair is left out.
+        let samples = [sample(start + 100, 100, 500), sample(start + 400, 100, 5), sample(start + 700, 100, 35)];
+        let w = summarize(&samples, now, 3_600, 4);
+        assert_eq!(w.mem, vec![Some(100.0), None, None, None]);
+        assert_eq!(w.cpu, vec![Some(0.1), None, None, None]);
+        assert!(close(w.cpu_avg, 0.1));
+
+        let empty = summarize(&[], now, 3_600, 4);
+        assert_eq!(empty.samples, 0);
+        assert_eq!(empty.mem_avg_kib, None);
+        assert_eq!(empty.cpu_avg, None);
+        assert_eq!(empty.mem, vec![None; 4]);
+    }
+
+    #[test]
+    fn sparkline_and_suggestion() {
+        assert_eq!(sparkline(&[Some(0.0), None, Some(50.0), Some(100.0), Some(150.0)], 100.0), "▁ ▅██");
+        assert_eq!(sparkline(&[Some(1.0)], 0.0), "▁");
+        assert_eq!(suggested_max_kib(1_000_000), 1_310_720);
+        assert_eq!(suggested_max_kib(0), 0);
+    }
+
+    #[test]
+    fn history_paths() {
+        let dir = dir().unwrap();
+        assert_eq!(file("web").unwrap(), dir.join("web.jsonl"));
+        assert_eq!(file("hv1/web").unwrap(), dir.join("hv1").join("web.jsonl"));
+        assert_ne!(file("hv1/web").unwrap(), file("hv1_web").unwrap());
+
+        let root = std::env::temp_dir().join(format!("dismount_iso_qemu-trends-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("hv1")).unwrap();
+        for name in ["web.jsonl", "notes.txt", "hv1/web.jsonl", "hv1/web.jsonl.tmp"] {
+            std::fs::write(root.join(name), "").unwrap();
+        }
+        let mut found = files(&root).unwrap();
+        found.sort();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(found, [root.join("hv1/web.jsonl"), root.join("web.jsonl")]);
+        assert!(files(&root).unwrap().is_empty());
+    }
+}
@@ -1,33 +1,131 @@
 // src/utils.rs
 
+//! Parsers for virsh output, formatting helpers and local paths.
+
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use anyhow::{Result, bail};
 
-/// Normalize Windows paths for QEMU-GA
-pub fn normalize_windows_path(path: &str) -> String {
-    path.replace("\\", "\\\\")
+use crate::guestpath::{has_drive, GuestPath};
+
+/// Local file used for the guest file `remote`: `local` when given,
+/// otherwise the guest file's name in the current directory.
+pub fn resolve_local_path(local: &str, remote: &GuestPath) -> Result<PathBuf> {
+    let local = local.trim();
+    if !local.is_empty() {
+        if has_drive(local) || local.contains('\\') {
+            bail!("'{}' looks like a Windows path; the local file needs a Linux path", local);
+        }
+        return Ok(PathBuf::from(local));
+    }
+    match remote.file_name() {
+        Some(name) => Ok(PathBuf::from(name)),
+        None => bail!("{} is not a file; give a local path", remote),
+    }
+}
+
+/// Per-user configuration directory: `$XDG_CONFIG_HOME/dismount_iso_qemu`,
+/// falling back to `~/.config/dismount_iso_qemu`.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("dismount_iso_qemu"))
+}
+
+/// Per-user state directory for logs and history: `$XDG_STATE_HOME/dismount_iso_qemu`,
+/// falling back to `~/.local/state/dismount_iso_qemu`.
+pub fn state_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(base.join("dismount_iso_qemu"))
+}
+
+/// Lower-case hex SHA-256 of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Seconds since the unix epoch, or 0 if the clock is before 1970.
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Calls `par_map` runs at the same time: enough to hide virsh round trips
+/// without flooding libvirtd.
+const PARALLEL: usize = 8;
+
+/// Apply `f` to every item, `PARALLEL` at a time, and return the results
+/// in the order of `items`.
+pub fn par_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let f = &f;
+    let mut results = Vec::with_capacity(items.len());
+    for chunk in items.chunks(PARALLEL) {
+        std::thread::scope(|s| {
+            let handles: Vec<_> = chunk.iter().map(|item| s.spawn(move || f(item))).collect();
+            results.extend(handles.into_iter().map(|h| h.join().unwrap()));
+        });
+    }
+    results
+}
+
+/// Shell-style glob match supporting `*` (any run) and `?` (one char).
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
 }
 
-/// Resolve a local Linux path safely
-pub fn resolve_local_path(local: &str, remote: &str) -> PathBuf {
-    if local.trim().is_empty() {
-        let filename = Path::new(remote)
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy();
-        PathBuf::from(filename.as_ref())
+/// Classify a probed OS string into a coarse family: "windows", "linux",
+/// "bsd" or "other".
+pub fn os_family(os: &str) -> &'static str {
+    const LINUX: [&str; 14] = [
+        "linux", "ubuntu", "debian", "centos", "red hat", "rhel", "fedora", "rocky",
+        "alma", "suse", "arch", "alpine", "oracle", "gentoo",
+    ];
+    let os = os.to_lowercase();
+    if os.contains("windows") {
+        "windows"
+    } else if LINUX.iter().any(|n| os.contains(n)) {
+        "linux"
+    } else if os.contains("bsd") {
+        "bsd"
     } else {
-        PathBuf::from(local)
+        "other"
     }
 }
 
-/// Open a file in the user's editor
+/// Open a file in the configured editor (`editor` in the config, `$EDITOR`
+/// or `--editor`; nano by default).
 pub fn open_in_editor(path: &Path) -> Result<()> {
-    let editor = std::env::var("EDITOR")
-        .unwrap_or_else(|_| "nano".to_string());
+    let editor = &crate::config::get().editor;
 
-    let status = Command::new(&editor)
+    let status = Command::new(editor)
         .arg(path)
         .status()
         .map_err(|e| anyhow::anyhow!("Failed to launch editor '{}': {}", editor, e))?;
@@ -60,30 +158,179 @@ pub fn parse_dominfo(s: &str) -> DomInfo {
 
     for line in s.lines() {
         let l = line.trim();
-        if l.starts_with("Max memory:") {
-            if let Some(val) = l.splitn(2, ':').nth(1) {
-                let v = val.trim().split_whitespace().next().unwrap_or("");
-                if let Ok(n) = v.parse::<u64>() {
-                    max_memory_mb = Some(n);
-                }
-            }
-        } else if l.starts_with("Used memory:") {
-            if let Some(val) = l.splitn(2, ':').nth(1) {
-                let v = val.trim().split_whitespace().next().unwrap_or("");
-                if let Ok(n) = v.parse::<u64>() {
-                    used_memory_mb = Some(n);
-                }
+        if let Some(val) = l.strip_prefix("Max memory:") {
+            let v = val.split_whitespace().next().unwrap_or("");
+            if let Ok(n) = v.parse::<u64>() {
+                max_memory_mb = Some(n);
             }
-        } else if l.starts_with("CPU time:") {
-            if let Some(val) = l.splitn(2, ':').nth(1) {
-                cpu_time = Some(val.trim().to_string());
+        } else if let Some(val) = l.strip_prefix("Used memory:") {
+            let v = val.split_whitespace().next().unwrap_or("");
+            if let Ok(n) = v.parse::<u64>() {
+                used_memory_mb = Some(n);
             }
+        } else if let Some(val) = l.strip_prefix("CPU time:") {
+            cpu_time = Some(val.trim().to_string());
         }
     }
 
     DomInfo { max_memory_mb, used_memory_mb, cpu_time }
 }
 
+/// Parse `virsh dommemstat` output (`<name> <KiB>` per line) into a map.
+/// Which statistics appear depends on the balloon driver in the guest and
+/// on whether a stats period is set.
+pub fn parse_dommemstat(s: &str) -> HashMap<String, u64> {
+    s.lines()
+        .filter_map(|l| {
+            let mut parts = l.split_whitespace();
+            let name = parts.next()?;
+            let value = parts.next()?.parse().ok()?;
+            Some((name.to_string(), value))
+        })
+        .collect()
+}
+
+/// One row of `virsh domblklist --details`.
+#[derive(Debug, Clone)]
+pub struct BlockDevice {
+    /// "file", "block", "network", ...
+    pub kind: String,
+    /// "disk", "cdrom" or "floppy".
+    pub device: String,
+    pub target: String,
+    /// Backing path; `None` for an empty drive ("-").
+    pub source: Option<String>,
+}
+
+/// Parse `virsh domblklist --details` output. The source column keeps any
+/// spaces in the path.
+pub fn parse_domblklist(s: &str) -> Vec<BlockDevice> {
+    s.lines()
+        .filter(|l| !l.trim().is_empty() && !l.trim_start().starts_with('-'))
+        .filter_map(|l| {
+            let mut cols = l.split_whitespace();
+            let kind = cols.next()?;
+            let device = cols.next()?;
+            let target = cols.next()?;
+            if kind == "Type" && device == "Device" {
+                return None;
+            }
+            // Everything after the target column is the source.
+            let after_target = &l[l.find(target)? + target.len()..];
+            let source = after_target.trim();
+            Some(BlockDevice {
+                kind: kind.to_string(),
+                device: device.to_string(),
+                target: target.to_string(),
+                source: (!source.is_empty() && source != "-").then(|| source.to_string()),
+            })
+        })
+        .collect()
+}
+
+/// IP addresses from `virsh domifaddr` output, without prefix length and
+/// skipping loopback addresses. Each address appears once, in the order
+/// first listed.
+pub fn parse_domifaddr(s: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    s.lines()
+        .skip(2)
+        .filter_map(|l| l.split_whitespace().last())
+        .filter_map(|a| a.split('/').next())
+        .filter(|a| a.parse::<std::net::IpAddr>().is_ok_and(|ip| !ip.is_loopback()))
+        .filter(|a| seen.insert(*a))
+        .map(str::to_string)
+        .collect()
+}
+
+/// One NIC from `virsh domiflist`.
+#[derive(Debug, Clone)]
+pub struct HostInterface {
+    /// Host-side device such as `vnet0`; `None` while the VM is off.
+    pub device: Option<String>,
+    /// "network", "bridge", "direct", ...
+    pub kind: String,
+    /// Libvirt network or bridge name.
+    pub source: String,
+    pub model: String,
+    /// Lower-case `aa:bb:cc:dd:ee:ff`.
+    pub mac: String,
+}
+
+/// Parse `virsh domiflist` output.
+pub fn parse_domiflist(s: &str) -> Vec<HostInterface> {
+    s.lines()
+        .skip(2)
+        .filter_map(|l| {
+            let cols: Vec<&str> = l.split_whitespace().collect();
+            let [device, kind, source, model, mac] = cols[..] else {
+                return None;
+            };
+            Some(HostInterface {
+                device: (device != "-").then(|| device.to_string()),
+                kind: kind.to_string(),
+                source: source.to_string(),
+                model: model.to_string(),
+                mac: mac.to_lowercase(),
+            })
+        })
+        .collect()
+}
+
+/// `(mac, ip/prefix)` pairs from `virsh domifaddr` output. Continuation
+/// lines (name `-`) belong to the MAC above them.
+pub fn parse_domifaddr_macs(s: &str) -> Vec<(String, String)> {
+    let mut mac = String::new();
+    s.lines()
+        .skip(2)
+        .filter_map(|l| {
+            let cols: Vec<&str> = l.split_whitespace().collect();
+            let [_, m, _, addr] = cols[..] else {
+                return None;
+            };
+            if m != "-" {
+                mac = m.to_lowercase();
+            }
+            Some((mac.clone(), addr.to_string()))
+        })
+        .collect()
+}
+
+/// `(rx_bytes, tx_bytes)` from `virsh domifstat` output.
+pub fn parse_domifstat(s: &str) -> (Option<u64>, Option<u64>) {
+    let value = |key: &str| {
+        s.lines().find_map(|l| {
+            let mut cols = l.split_whitespace().skip(1);
+            (cols.next()? == key).then(|| cols.next()?.parse().ok())?
+        })
+    };
+    (value("rx_bytes"), value("tx_bytes"))
+}
+
+/// Read the volume identifier from an ISO 9660 primary volume descriptor
+/// (sector 16). Returns `None` for files that are not ISO 9660 images.
+pub fn iso_volume_label(path: &Path) -> Option<String> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut f = std::fs::File::open(path).ok()?;
+    let mut pvd = [0u8; 72];
+    f.seek(SeekFrom::Start(16 * 2048)).ok()?;
+    f.read_exact(&mut pvd).ok()?;
+    if pvd[0] != 1 || &pvd[1..6] != b"CD001" {
+        return None;
+    }
+    let label = String::from_utf8_lossy(&pvd[40..72]).trim().to_string();
+    (!label.is_empty()).then_some(label)
+}
+
+/// SHA-256 of a file's content, streamed so large images are not loaded whole.
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+    let mut f = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut f, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
 /// Parse CPU time strings commonly seen in `virsh dominfo`:
 /// - "613h 33m 33s"
 /// - "154359.4s"
@@ -101,21 +348,20 @@ pub fn parse_cpu_time_to_seconds(s: &str) -> Option<u64> {
         let mut total: u64 = 0;
         for token in s.split_whitespace() {
             let token = token.trim();
-            if token.ends_with('h') {
-                if let Ok(v) = token[..token.len() - 1].parse::<u64>() {
+            if let Some(num) = token.strip_suffix('h') {
+                if let Ok(v) = num.parse::<u64>() {
                     total = total.saturating_add(v.saturating_mul(3600));
                 } else {
                     return None;
                 }
-            } else if token.ends_with('m') {
-                if let Ok(v) = token[..token.len() - 1].parse::<u64>() {
+            } else if let Some(num) = token.strip_suffix('m') {
+                if let Ok(v) = num.parse::<u64>() {
                     total = total.saturating_add(v.saturating_mul(60));
                 } else {
                     return None;
                 }
-            } else if token.ends_with('s') {
+            } else if let Some(num) = token.strip_suffix('s') {
                 // allow fractional seconds like "154359.4s"
-                let num = &token[..token.len() - 1];
                 if let Ok(f) = num.parse::<f64>() {
                     total = total.saturating_add(f as u64);
                 } else {
@@ -134,7 +380,7 @@ pub fn parse_cpu_time_to_seconds(s: &str) -> Option<u64> {
     }
 
     // Single token cases: "154359.4s", "154359s", or plain number
-    let token = if s.ends_with('s') { &s[..s.len() - 1] } else { s };
+    let token = s.strip_suffix('s').unwrap_or(s);
     if let Ok(f) = token.parse::<f64>() {
         return Some(f as u64);
     }
@@ -155,7 +401,7 @@ pub fn format_memory_kib(kib: Option<u64>) -> String {
             const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
             let mut unit = 0usize;
             while bytes >= 1024 && unit < UNITS.len() - 1 {
-                bytes = bytes / 1024;
+                bytes /= 1024;
                 unit += 1;
             }
             if unit >= 2 {
@@ -197,3 +443,223 @@ pub fn format_seconds_dhms(mut secs: u64) -> String {
     }
     parts.join(" ")
 }
+
+/// Metadata for a single libvirt snapshot, parsed from `snapshot-dumpxml`.
+#[derive(Debug, Clone)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub state: Option<String>,
+    pub parent: Option<String>,
+    pub creation_time: Option<u64>, // unix seconds
+}
+
+/// Parse the `<domainsnapshot>` document returned by `virsh snapshot-dumpxml`.
+/// Only top-level elements are inspected so the embedded `<domain>` copy
+/// (which has its own `<name>`) is ignored.
+pub fn parse_snapshot_xml(xml: &str) -> Option<SnapshotInfo> {
+    let doc = roxmltree::Document::parse(xml).ok()?;
+    let root = doc.root_element();
+    let child_text = |tag: &str| {
+        root.children()
+            .find(|n| n.has_tag_name(tag))
+            .and_then(|n| n.text())
+            .map(|t| t.trim().to_string())
+    };
+
+    let name = child_text("name")?;
+    let parent = root
+        .children()
+        .find(|n| n.has_tag_name("parent"))
+        .and_then(|p| p.children().find(|n| n.has_tag_name("name")))
+        .and_then(|n| n.text())
+        .map(|t| t.trim().to_string());
+
+    Some(SnapshotInfo {
+        name,
+        description: child_text("description").filter(|d| !d.is_empty()),
+        state: child_text("state"),
+        parent,
+        creation_time: child_text("creationTime").and_then(|t| t.parse().ok()),
+    })
+}
+
+/// Format unix seconds as RFC 3339 UTC, e.g. "2024-05-01T12:00:00Z".
+pub fn format_rfc3339(secs: u64) -> String {
+    format!("{}Z", format_unix_time(secs).replace(' ', "T"))
+}
+
+/// Format unix seconds as "YYYY-MM-DD HH:MM:SS" (UTC).
+pub fn format_unix_time(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil-from-days (Howard Hinnant's algorithm), valid for the unix era.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        let cases = [
+            ("web-*", "web-01", true),
+            ("web-*", "web-", true),
+            ("web-*", "db-01", false),
+            ("db?", "db1", true),
+            ("db?", "db12", false),
+            ("*", "", true),
+            ("", "", true),
+            ("", "a", false),
+            ("*-prod-*", "eu-prod-7", true),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyy", false),
+            ("hv1/*", "hv1/web", true),
+            ("hv1/*", "hv2/web", false),
+        ];
+        for (pattern, text, want) in cases {
+            assert_eq!(glob_match(pattern, text), want, "{} ~ {}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn par_map_keeps_order() {
+        let items: Vec<u64> = (0..20).collect();
+        let running = std::sync::atomic::AtomicUsize::new(0);
+        let peak = std::sync::atomic::AtomicUsize::new(0);
+        let squares = par_map(&items, |n| {
+            use std::sync::atomic::Ordering::SeqCst;
+            peak.fetch_max(running.fetch_add(1, SeqCst) + 1, SeqCst);
+            // Later items finish first.
+            std::thread::sleep(std::time::Duration::from_millis(20 - n));
+            running.fetch_sub(1, SeqCst);
+            n * n
+        });
+        assert_eq!(squares, items.iter().map(|n| n * n).collect::<Vec<_>>());
+        assert!(peak.into_inner() <= PARALLEL);
+        assert!(par_map(&[] as &[u64], |n| *n).is_empty());
+    }
+
+    #[test]
+    fn domblklist() {
+        let out = " Type   Device   Target   Source\n\
+                   ------------------------------------------------\n \
+                   file   disk     vda      /var/lib/libvirt/images/a b.qcow2\n \
+                   file   cdrom    sda      -\n \
+                   block  disk     vdb      /dev/sdb\n\n";
+        let got: Vec<_> = parse_domblklist(out)
+            .into_iter()
+            .map(|d| (d.kind, d.device, d.target, d.source))
+            .collect();
+        let s = |v: &str| v.to_string();
+        assert_eq!(
+            got,
+            vec![
+                (s("file"), s("disk"), s("vda"), Some(s("/var/lib/libvirt/images/a b.qcow2"))),
+                (s("file"), s("cdrom"), s("sda"), None),
+                (s("block"), s("disk"), s("vdb"), Some(s("/dev/sdb"))),
+            ]
+        );
+        assert!(parse_domblklist("").is_empty());
+    }
+
+    #[test]
+    fn domiflist() {
+        let out = " Interface   Type      Source    Model    MAC\n\
+                   -------------------------------------------------------------\n \
+                   vnet0       network   default   virtio   52:54:00:AA:BB:CC\n \
+                   -           bridge    br0       e1000    52:54:00:11:22:33\n \
+                   garbage line\n";
+        let got: Vec<_> = parse_domiflist(out)
+            .into_iter()
+            .map(|i| (i.device, i.kind, i.source, i.model, i.mac))
+            .collect();
+        let s = |v: &str| v.to_string();
+        assert_eq!(
+            got,
+            vec![
+                (Some(s("vnet0")), s("network"), s("default"), s("virtio"), s("52:54:00:aa:bb:cc")),
+                (None, s("bridge"), s("br0"), s("e1000"), s("52:54:00:11:22:33")),
+            ]
+        );
+    }
+
+    #[test]
+    fn domifaddr() {
+        let out = " Name       MAC address          Protocol     Address
+-------------------------------------------------------------------------------
+ lo         00:00:00:00:00:00    ipv4         127.0.0.1/8
+ eth0       52:54:00:aa:bb:cc    ipv4         10.0.0.5/24
+ -          -                    ipv6         fe80::1/64
+ eth1       52:54:00:aa:bb:dd    ipv4         10.0.1.7/24
+ eth2       52:54:00:aa:bb:ee    ipv4         10.0.0.5/24
+ -          -                    ipv4         10.0.1.7/24
+";
+        assert_eq!(parse_domifaddr(out), ["10.0.0.5", "fe80::1", "10.0.1.7"]);
+        assert!(parse_domifaddr("").is_empty());
+    }
+
+    #[test]
+    fn domifaddr_macs() {
+        let out = " Name       MAC address          Protocol     Address\n\
+                   -------------------------------------------------------\n \
+                   lo         00:00:00:00:00:00    ipv4         127.0.0.1/8\n \
+                   eth0       52:54:00:AA:BB:CC    ipv4         192.168.122.10/24\n \
+                   -          -                    ipv6         fe80::1/64\n \
+                   eth1       52:54:00:11:22:33    ipv4         10.0.0.2/8\n";
+        let s = |a: &str, b: &str| (a.to_string(), b.to_string());
+        assert_eq!(
+            parse_domifaddr_macs(out),
+            vec![
+                s("00:00:00:00:00:00", "127.0.0.1/8"),
+                s("52:54:00:aa:bb:cc", "192.168.122.10/24"),
+                s("52:54:00:aa:bb:cc", "fe80::1/64"),
+                s("52:54:00:11:22:33", "10.0.0.2/8"),
+            ]
+        );
+    }
+
+    #[test]
+    fn dommemstat() {
+        let stats = parse_dommemstat("actual 8388608\nunused 5000000\nusable 5200000\nlast_update 1760000000\nbad\nrss x\n");
+        assert_eq!(stats.len(), 4);
+        assert_eq!(stats.get("actual"), Some(&8388608));
+        assert_eq!(stats.get("usable"), Some(&5200000));
+        assert_eq!(stats.get("rss"), None);
+        assert!(parse_dommemstat("").is_empty());
+    }
+
+    #[test]
+    fn unix_time() {
+        let cases = [
+            (0, "1970-01-01 00:00:00"),
+            (951_782_400, "2000-02-29 00:00:00"),
+            (1_760_000_000, "2025-10-09 08:53:20"),
+            (4_102_444_799, "2099-12-31 23:59:59"),
+        ];
+        for (secs, want) in cases {
+            assert_eq!(format_unix_time(secs), want);
+        }
+        assert_eq!(format_rfc3339(1_760_000_000), "2025-10-09T08:53:20Z");
+    }
+}
@@ -1,149 +1,704 @@
 // src/virsh.rs
-use std::process::Command;
-use std::io;
+
+//! Thin wrappers over `virsh` subcommands, one process per call.
+//!
+//! VM names may be host-qualified (`host/vm`) when `[hosts]` are
+//! configured; every call resolves the connection URI itself. Failures come
+//! back as [`Error`], classified from virsh's stderr. Calls that change
+//! state honour [`dryrun`]. The `ga_*` functions talk to the guest agent
+//! through `virsh qemu-agent-command`.
+//!
+//! Hosts skipped by [`list_vms`] are reported to the sink set with
+//! [`set_warning_sink`]; without one they are dropped.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
 use serde_json::Value;
 
-/// Simple wrapper to call `virsh qemu-agent-command` and return parsed JSON.
-pub fn virsh_qemu_agent(vm: &str, payload: &str, timeout_secs: u64) -> io::Result<Value> {
-    let out = Command::new("virsh")
-        .args(["qemu-agent-command", "--timeout", &timeout_secs.to_string(), vm, payload])
-        .output()?;
-    if !out.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("virsh qemu-agent-command failed: {}", String::from_utf8_lossy(&out.stderr)),
-        ));
-    }
-    let s = String::from_utf8_lossy(&out.stdout);
-    let json: Value = serde_json::from_str(&s)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("json parse: {}", e)))?;
-    Ok(json)
-}
-
-/// Return VM names from `virsh list --all --name`.
-/// Trims empty lines and returns Vec<String>.
-pub fn list_vms() -> io::Result<Vec<String>> {
-    let out = Command::new("virsh")
-        .args(["list", "--all", "--name"])
-        .output()?;
+use crate::config;
+use crate::dryrun;
+use crate::error::{Error, Result};
+
+static WARNING_SINK: RwLock<Option<fn(&str)>> = RwLock::new(None);
+
+/// Send warnings about skipped hosts to `sink`.
+pub fn set_warning_sink(sink: fn(&str)) {
+    *WARNING_SINK.write().unwrap() = Some(sink);
+}
+
+fn warn(message: &str) {
+    if let Some(sink) = *WARNING_SINK.read().unwrap() {
+        sink(message);
+    }
+}
+
+/// Run `virsh <args>` against `uri` (or virsh's default connection) and
+/// return stdout. Failures are classified from stderr; `what` names the
+/// subcommand and `vm` the targeted domain, if any.
+fn run_virsh(uri: Option<&str>, args: &[&str], what: &str, vm: Option<&str>) -> Result<String> {
+    let mut cmd = Command::new("virsh");
+    if let Some(uri) = uri {
+        cmd.args(["-c", uri]);
+    }
+    let out = cmd.args(args).output()?;
     if !out.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("virsh list failed: {}", String::from_utf8_lossy(&out.stderr)),
-        ));
+        return Err(Error::from_virsh(what, &String::from_utf8_lossy(&out.stderr), uri, vm));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+/// Like `run_virsh`, for commands that change state: in dry-run mode the
+/// command line is printed instead and the result is empty.
+fn run_virsh_mut(uri: Option<&str>, args: &[&str], what: &str, vm: Option<&str>) -> Result<String> {
+    if dryrun::enabled() {
+        let mut full = vec!["virsh"];
+        if let Some(uri) = uri {
+            full.extend(["-c", uri]);
+        }
+        full.extend_from_slice(args);
+        dryrun::note(&dryrun::shell_line(&full));
+        return Ok(String::new());
+    }
+    run_virsh(uri, args, what, vm)
+}
+
+/// Split a possibly host-qualified VM name (`host/vm`) into the connection
+/// URI to use and the bare domain name. Unqualified names use the default
+/// connection.
+pub fn resolve_target(vm: &str) -> Result<(Option<&'static str>, &str)> {
+    let cfg = config::get();
+    match vm.split_once('/') {
+        Some((host, name)) => match cfg.host_uri(host) {
+            Some(uri) => Ok((Some(uri), name)),
+            None => Err(Error::UnknownHost(host.to_string())),
+        },
+        None => Ok((cfg.uri.as_deref(), vm)),
+    }
+}
+
+/// `qemu:///system` is local; a URI naming a server, as in
+/// `qemu+ssh://root@hv1:22/system`, is not unless the server is localhost.
+pub fn uri_is_local(uri: &str) -> bool {
+    let Some((_, rest)) = uri.split_once("://") else {
+        return true;
+    };
+    let authority = rest.split('/').next().unwrap_or_default();
+    let server = authority.rsplit('@').next().unwrap_or_default();
+    let server = match server.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => server.split(':').next().unwrap_or_default(),
+    };
+    matches!(server, "" | "localhost" | "127.0.0.1" | "::1")
+}
+
+/// True when `vm` runs on this machine, so the disk paths libvirt reports
+/// for it are paths in the local filesystem.
+pub fn is_local(vm: &str) -> Result<bool> {
+    Ok(resolve_target(vm)?.0.is_none_or(uri_is_local))
+}
+
+/// Run `virsh <cmd> --domain <vm> <args>` on the host that owns `vm`.
+fn run_domain(vm: &str, cmd: &str, args: &[&str]) -> Result<String> {
+    let (uri, name) = resolve_target(vm)?;
+    let mut full = vec![cmd, "--domain", name];
+    full.extend_from_slice(args);
+    run_virsh(uri, &full, cmd, Some(vm))
+}
+
+/// `run_domain` for commands that change state; see `run_virsh_mut`.
+fn run_domain_mut(vm: &str, cmd: &str, args: &[&str]) -> Result<String> {
+    let (uri, name) = resolve_target(vm)?;
+    let mut full = vec![cmd, "--domain", name];
+    full.extend_from_slice(args);
+    run_virsh_mut(uri, &full, cmd, Some(vm))
+}
+
+/// In dry-run mode, print the `qemu-agent-command` that would send
+/// `payload` to `vm` and return true; the caller then skips the call.
+pub fn agent_dry_run(vm: &str, payload: &str, timeout_secs: u64) -> Result<bool> {
+    if !dryrun::enabled() {
+        return Ok(false);
+    }
+    let timeout = timeout_secs.to_string();
+    run_domain_mut(vm, "qemu-agent-command", &["--timeout", &timeout, payload])?;
+    Ok(true)
+}
+
+/// Simple wrapper to call `virsh qemu-agent-command` and return parsed JSON.
+pub fn virsh_qemu_agent(vm: &str, payload: &str, timeout_secs: u64) -> Result<Value> {
+    let s = run_domain(vm, "qemu-agent-command", &["--timeout", &timeout_secs.to_string(), payload])
+        .map_err(|e| name_agent_command(e, payload))?;
+    parse_agent_reply(&s)
+}
+
+/// `virsh_qemu_agent` for payloads carrying a secret. The command goes to
+/// virsh's stdin, which it reads in batch mode, rather than onto its
+/// command line, where any local user could read it from `ps` or
+/// `/proc/<pid>/cmdline` while the call runs.
+pub fn virsh_qemu_agent_secret(vm: &str, payload: &str, timeout_secs: u64) -> Result<Value> {
+    let (uri, name) = resolve_target(vm)?;
+    let line = format!(
+        "qemu-agent-command --domain {} --timeout {} {}\n",
+        dryrun::shell_quote(name),
+        timeout_secs,
+        dryrun::shell_quote(payload)
+    );
+    let mut cmd = Command::new("virsh");
+    cmd.arg("-q");
+    if let Some(uri) = uri {
+        cmd.args(["-c", uri]);
+    }
+    let mut child = cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    // Dropping stdin after the write ends the batch.
+    child.stdin.take().expect("stdin is piped").write_all(line.as_bytes())?;
+    let out = child.wait_with_output()?;
+    // virsh can exit 0 after a failed command in batch mode; stderr tells.
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    if !out.status.success() || stderr.contains("error:") {
+        let e = Error::from_virsh("qemu-agent-command", &stderr, uri, Some(vm));
+        return Err(name_agent_command(e, payload));
     }
-    let s = String::from_utf8_lossy(&out.stdout);
+    parse_agent_reply(&String::from_utf8_lossy(&out.stdout))
+}
+
+/// Name the blocked agent command rather than "qemu-agent-command".
+fn name_agent_command(mut e: Error, payload: &str) -> Error {
+    if let Error::AgentCommandDisabled { command, .. } = &mut e
+        && let Some(exec) = serde_json::from_str::<Value>(payload)
+            .ok()
+            .and_then(|p| p.get("execute").and_then(|v| v.as_str()).map(str::to_string))
+    {
+        *command = exec;
+    }
+    e
+}
+
+fn parse_agent_reply(s: &str) -> Result<Value> {
+    serde_json::from_str(s).map_err(|e| Error::Protocol(format!("json parse: {}", e)))
+}
+
+/// The connection URI actually used for `vm`: the configured one, or what
+/// virsh picks by default (`virsh uri`).
+pub fn connection_uri(vm: &str) -> Result<String> {
+    match resolve_target(vm)? {
+        (Some(uri), _) => Ok(uri.to_string()),
+        (None, _) => Ok(run_virsh(None, &["uri"], "uri", None)?.trim().to_string()),
+    }
+}
+
+/// Connection URI for a `[hosts]` entry, or the default connection for `None`.
+fn host_uri(host: Option<&str>) -> Result<Option<&'static str>> {
+    match host {
+        Some(h) => Ok(Some(config::get().host_uri(h).ok_or_else(|| Error::UnknownHost(h.to_string()))?)),
+        None => Ok(config::get().uri.as_deref()),
+    }
+}
+
+/// Return VM names from `virsh list --all --name` on one host.
+/// With a host, names are qualified as `host/vm`; without, the default
+/// connection is used and names are bare. `filter` adds `virsh list` flags
+/// such as "--state-running".
+pub fn list_vms_on(host: Option<&str>, filter: &[&str]) -> Result<Vec<String>> {
+    let uri = host_uri(host)?;
+    let mut args = vec!["list", "--all", "--name"];
+    args.extend_from_slice(filter);
+    let s = run_virsh(uri, &args, "list", None)?;
     let vms: Vec<String> = s
         .lines()
         .map(|l| l.trim())
         .filter(|l| !l.is_empty())
-        .map(|l| l.to_string())
+        .map(|l| match host {
+            Some(h) => format!("{}/{}", h, l),
+            None => l.to_string(),
+        })
         .collect();
     Ok(vms)
 }
 
-/// Return the raw `virsh dominfo <vm>` output as a String.
-pub fn dominfo_raw(vm: &str) -> io::Result<String> {
-    let out = Command::new("virsh")
-        .args(["dominfo", vm])
-        .output()?;
-    if !out.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("virsh dominfo failed: {}", String::from_utf8_lossy(&out.stderr)),
-        ));
+/// List VMs on every configured host concurrently. One entry per host
+/// (`None` is the default connection when no `[hosts]` are configured), so
+/// an unreachable host is reported without hiding the others.
+pub fn inventory(filter: &[&str]) -> Vec<(Option<String>, Result<Vec<String>>)> {
+    let hosts = config::get().host_names();
+    std::thread::scope(|s| {
+        let handles: Vec<_> = hosts
+            .iter()
+            .map(|h| s.spawn(move || list_vms_on(h.as_deref(), filter)))
+            .collect();
+        hosts
+            .iter()
+            .cloned()
+            .zip(handles.into_iter().map(|h| h.join().unwrap()))
+            .collect()
+    })
+}
+
+/// Return VM names across all hosts, `host/vm` qualified when `[hosts]` are
+/// configured. With a single connection its error is returned; with several,
+/// unreachable hosts are skipped and reported to the warning sink.
+pub fn list_vms() -> Result<Vec<String>> {
+    list_vms_filtered(&[])
+}
+
+/// Like `list_vms`, restricted by `virsh list` flags such as "--state-running".
+pub fn list_vms_filtered(filter: &[&str]) -> Result<Vec<String>> {
+    let results = inventory(filter);
+    let single = results.len() == 1;
+    let mut vms = Vec::new();
+    for (host, result) in results {
+        match result {
+            Ok(list) => vms.extend(list),
+            Err(e) if single => return Err(e),
+            Err(e) => warn(&format!("host '{}' unreachable: {}", host.unwrap_or_default(), e)),
+        }
     }
-    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+    Ok(vms)
 }
 
-/// Read a file from a VM using guest-file-open, guest-file-read, guest-file-close.
-pub fn ga_read_file(vm: &str, path: &str) -> io::Result<Vec<u8>> {
-    use base64::Engine;
-    
-    // 1. Open the file
-    let open_payload = serde_json::json!({
+/// Return the raw `virsh domblklist --details <vm>` output.
+pub fn domblklist_raw(vm: &str) -> Result<String> {
+    run_domain(vm, "domblklist", &["--details"])
+}
+
+/// Return the domain XML; `inactive` gives the persistent definition
+/// instead of the running one.
+pub fn dumpxml(vm: &str, inactive: bool) -> Result<String> {
+    run_domain(vm, "dumpxml", if inactive { &["--inactive"] } else { &[] })
+}
+
+/// `--live` / `--config` flags for device changes.
+fn scope_flags(live: bool, config: bool) -> Vec<&'static str> {
+    let mut flags = Vec::new();
+    if live {
+        flags.push("--live");
+    }
+    if config {
+        flags.push("--config");
+    }
+    flags
+}
+
+/// Attach the device described by `xml` to the live and/or persistent config.
+pub fn attach_device(vm: &str, xml: &str, live: bool, config: bool) -> Result<()> {
+    device_change(vm, "attach-device", xml, live, config)
+}
+
+/// Detach the device matching `xml` from the live and/or persistent config.
+pub fn detach_device(vm: &str, xml: &str, live: bool, config: bool) -> Result<()> {
+    device_change(vm, "detach-device", xml, live, config)
+}
+
+fn device_change(vm: &str, cmd: &str, xml: &str, live: bool, config: bool) -> Result<()> {
+    with_xml_file(cmd, xml, |path| {
+        let mut args = vec!["--file", path];
+        args.extend(scope_flags(live, config));
+        run_domain_mut(vm, cmd, &args).map(|_| ())
+    })
+}
+
+/// Temporary XML files written by this process, so parallel calls with the
+/// same tag get distinct names.
+static XML_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// virsh only takes XML from a file: write `xml` to a temporary file, run
+/// `f` with its path and remove the file again. The file is created
+/// exclusively and readable only by us, so a file or symlink planted at
+/// the predictable path makes the call fail instead of being written
+/// through.
+fn with_xml_file<T>(tag: &str, xml: &str, f: impl FnOnce(&str) -> Result<T>) -> Result<T> {
+    let seq = XML_FILES.fetch_add(1, Ordering::Relaxed);
+    let file = std::env::temp_dir().join(format!("dismount_iso_qemu-{}-{}-{}.xml", std::process::id(), seq, tag));
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    if let Err(e) = options.open(&file).and_then(|mut out| out.write_all(xml.as_bytes())) {
+        // Never remove a file we did not create.
+        if e.kind() != std::io::ErrorKind::AlreadyExists {
+            let _ = std::fs::remove_file(&file);
+        }
+        return Err(std::io::Error::new(e.kind(), format!("{}: {}", file.display(), e)).into());
+    }
+    let result = f(&file.to_string_lossy());
+    let _ = std::fs::remove_file(&file);
+    if dryrun::enabled() {
+        // The file is gone by the time anyone reads the plan.
+        dryrun::note(&format!("contents of {}:\n{}", file.display(), xml.trim_end()));
+    }
+    result
+}
+
+/// Replace the persistent definition of `vm` with `xml`, validated by
+/// libvirt against its schema. A running VM picks it up on next boot.
+pub fn define(vm: &str, xml: &str) -> Result<()> {
+    let (uri, _) = resolve_target(vm)?;
+    with_xml_file("define", xml, |path| {
+        run_virsh_mut(uri, &["define", "--file", path, "--validate"], "define", Some(vm)).map(|_| ())
+    })
+}
+
+/// Return the raw `virsh domifaddr <vm>` output. The guest agent is asked
+/// first since it sees every interface; DHCP leases are the fallback.
+pub fn domifaddr_raw(vm: &str) -> Result<String> {
+    run_domain(vm, "domifaddr", &["--source", "agent"]).or_else(|_| run_domain(vm, "domifaddr", &[]))
+}
+
+/// `virsh domifaddr <vm>` from DHCP leases only, for when the agent is
+/// known not to answer.
+pub fn domifaddr_lease_raw(vm: &str) -> Result<String> {
+    run_domain(vm, "domifaddr", &["--source", "lease"])
+}
+
+/// Return the raw `virsh domiflist <vm>` output.
+pub fn domiflist_raw(vm: &str) -> Result<String> {
+    run_domain(vm, "domiflist", &[])
+}
+
+/// Return the raw `virsh domifstat <vm> <device>` output.
+pub fn domifstat_raw(vm: &str, device: &str) -> Result<String> {
+    run_domain(vm, "domifstat", &["--interface", device])
+}
+
+/// Hostname of the hypervisor behind one host's connection.
+pub fn hostname_on(host: Option<&str>) -> Result<String> {
+    Ok(run_virsh(host_uri(host)?, &["hostname"], "hostname", None)?.trim().to_string())
+}
+
+/// Names of the active storage pools on one host.
+pub fn pool_list_on(host: Option<&str>) -> Result<Vec<String>> {
+    let s = run_virsh(host_uri(host)?, &["pool-list", "--name"], "pool-list", None)?;
+    Ok(s.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect())
+}
+
+/// Volume paths in a storage pool, from the Path column of `virsh vol-list`.
+pub fn vol_paths_on(host: Option<&str>, pool: &str) -> Result<Vec<String>> {
+    let s = run_virsh(host_uri(host)?, &["vol-list", "--pool", pool], "vol-list", None)?;
+    Ok(s.lines()
+        .skip(2)
+        .filter_map(|l| l.find(" /").map(|i| l[i + 1..].trim().to_string()))
+        .collect())
+}
+
+/// Capacity in bytes of a storage volume, by path, from `virsh vol-info --bytes`.
+pub fn vol_size_on(host: Option<&str>, path: &str) -> Result<u64> {
+    let s = run_virsh(host_uri(host)?, &["vol-info", "--bytes", path], "vol-info", None)?;
+    s.lines()
+        .find_map(|l| l.trim().strip_prefix("Capacity:"))
+        .and_then(|v| v.split_whitespace().next())
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| Error::Protocol(format!("no capacity in vol-info for {}", path)))
+}
+
+/// Return the raw `virsh dominfo <vm>` output as a String.
+pub fn dominfo_raw(vm: &str) -> Result<String> {
+    run_domain(vm, "dominfo", &[])
+}
+
+/// Return the raw `virsh dommemstat <vm>` output.
+pub fn dommemstat_raw(vm: &str) -> Result<String> {
+    run_domain(vm, "dommemstat", &[])
+}
+
+/// Return the domain state from `virsh domstate <vm>`, e.g. "running",
+/// "shut off" or "paused".
+pub fn domstate(vm: &str) -> Result<String> {
+    Ok(run_domain(vm, "domstate", &[])?.trim().to_string())
+}
+
+/// Start a defined, shut-off VM.
+pub fn start(vm: &str) -> Result<()> {
+    run_domain_mut(vm, "start", &[])?;
+    Ok(())
+}
+
+/// Request a guest shutdown. `mode` is passed to `--mode` ("agent", "acpi", ...).
+pub fn shutdown(vm: &str, mode: &str) -> Result<()> {
+    run_domain_mut(vm, "shutdown", &["--mode", mode])?;
+    Ok(())
+}
+
+/// Request a guest reboot. `mode` is passed to `--mode` ("agent", "acpi", ...).
+pub fn reboot(vm: &str, mode: &str) -> Result<()> {
+    run_domain_mut(vm, "reboot", &["--mode", mode])?;
+    Ok(())
+}
+
+/// Immediately power off a VM (`virsh destroy`); the guest gets no warning.
+pub fn destroy(vm: &str) -> Result<()> {
+    run_domain_mut(vm, "destroy", &[])?;
+    Ok(())
+}
+
+/// Pause a running VM's vCPUs.
+pub fn suspend(vm: &str) -> Result<()> {
+    run_domain_mut(vm, "suspend", &[])?;
+    Ok(())
+}
+
+/// Resume a paused VM.
+pub fn resume(vm: &str) -> Result<()> {
+    run_domain_mut(vm, "resume", &[])?;
+    Ok(())
+}
+
+/// Read a whole file from a VM. Interrupted reads resume where they
+/// stopped; see `transfer::download`.
+pub fn ga_read_file(vm: &str, path: &str) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    crate::transfer::download(vm, path, &mut content, 0, &mut |_| {})?;
+    Ok(content)
+}
+
+/// Write a whole file to a VM, replacing it. Interrupted writes resume
+/// where they stopped; see `transfer::upload`.
+pub fn ga_write_file(vm: &str, path: &str, content: &[u8]) -> Result<()> {
+    crate::transfer::upload(vm, path, &mut std::io::Cursor::new(content), &mut |_| {})?;
+    Ok(())
+}
+
+/// Open a guest file with guest-file-open and return its handle. `mode` is
+/// an fopen() mode such as "r", "w" or "r+".
+pub fn ga_file_open(vm: &str, path: &str, mode: &str) -> Result<i64> {
+    let payload = serde_json::json!({
         "execute": "guest-file-open",
-        "arguments": {"path": path, "mode": "r"}
+        "arguments": {"path": path, "mode": mode}
     });
-    let open_result = virsh_qemu_agent(vm, &open_payload.to_string(), 10)?;
-    let handle = open_result
+    virsh_qemu_agent(vm, &payload.to_string(), config::get().timeouts.agent_file)?
         .get("return")
         .and_then(|v| v.as_i64())
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Failed to get file handle"))?;
+        .ok_or_else(|| Error::Protocol("Failed to get file handle".to_string()))
+}
 
-    // 2. Read the file in chunks
-    let mut content = Vec::new();
-    loop {
-        let read_payload = serde_json::json!({
-            "execute": "guest-file-read",
-            "arguments": {"handle": handle, "count": 4096}
-        });
-        let read_result = virsh_qemu_agent(vm, &read_payload.to_string(), 10)?;
-        
-        let ret = read_result
-            .get("return")
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "No return in guest-file-read"))?;
-        
-        let buf_b64 = ret
-            .get("buf-b64")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "No buf-b64 in response"))?;
-        
-        let chunk = base64::engine::general_purpose::STANDARD
-            .decode(buf_b64)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("base64 decode: {}", e)))?;
-        
-        let eof = ret.get("eof").and_then(|v| v.as_bool()).unwrap_or(false);
-        
-        content.extend_from_slice(&chunk);
-        
-        if eof {
-            break;
-        }
-    }
+/// Read up to `count` bytes with guest-file-read; the flag is set at end of file.
+pub fn ga_file_read(vm: &str, handle: i64, count: usize) -> Result<(Vec<u8>, bool)> {
+    use base64::Engine;
+    let payload = serde_json::json!({
+        "execute": "guest-file-read",
+        "arguments": {"handle": handle, "count": count}
+    });
+    let json = virsh_qemu_agent(vm, &payload.to_string(), config::get().timeouts.agent_file)?;
+    let ret = json
+        .get("return")
+        .ok_or_else(|| Error::Protocol("No return in guest-file-read".to_string()))?;
+    let buf_b64 = ret
+        .get("buf-b64")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| Error::Protocol("No buf-b64 in response".to_string()))?;
+    let chunk = base64::engine::general_purpose::STANDARD
+        .decode(buf_b64)
+        .map_err(|e| Error::Protocol(format!("base64 decode: {}", e)))?;
+    let eof = ret.get("eof").and_then(|v| v.as_bool()).unwrap_or(false);
+    Ok((chunk, eof))
+}
+
+/// Write `data` with guest-file-write and return how many bytes the agent
+/// reports written.
+pub fn ga_file_write(vm: &str, handle: i64, data: &[u8]) -> Result<usize> {
+    use base64::Engine;
+    let payload = serde_json::json!({
+        "execute": "guest-file-write",
+        "arguments": {"handle": handle, "buf-b64": base64::engine::general_purpose::STANDARD.encode(data)}
+    });
+    let json = virsh_qemu_agent(vm, &payload.to_string(), config::get().timeouts.agent_file)?;
+    json.get("return")
+        .and_then(|r| r.get("count"))
+        .and_then(|v| v.as_u64())
+        .map(|n| n as usize)
+        .ok_or_else(|| Error::Protocol("No count in guest-file-write".to_string()))
+}
+
+/// Move the file position with guest-file-seek and return the new position.
+/// `whence` is "set", "cur" or "end".
+pub fn ga_file_seek(vm: &str, handle: i64, offset: i64, whence: &str) -> Result<u64> {
+    let payload = serde_json::json!({
+        "execute": "guest-file-seek",
+        "arguments": {"handle": handle, "offset": offset, "whence": whence}
+    });
+    let json = virsh_qemu_agent(vm, &payload.to_string(), config::get().timeouts.agent_file)?;
+    json.get("return")
+        .and_then(|r| r.get("position"))
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| Error::Protocol("No position in guest-file-seek".to_string()))
+}
 
-    // 3. Close the file
-    let close_payload = serde_json::json!({
+/// Close a handle with guest-file-close, flushing writes in the guest.
+pub fn ga_file_close(vm: &str, handle: i64) -> Result<()> {
+    let payload = serde_json::json!({
         "execute": "guest-file-close",
         "arguments": {"handle": handle}
     });
-    let _ = virsh_qemu_agent(vm, &close_payload.to_string(), 10)?;
+    virsh_qemu_agent(vm, &payload.to_string(), config::get().timeouts.agent_file)?;
+    Ok(())
+}
 
-    Ok(content)
+/// Captured result of a program run in the guest with guest-exec.
+#[derive(Debug)]
+pub struct ExecOutput {
+    pub exit_code: i64,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Run `path args...` in the guest via guest-exec and poll guest-exec-status
+/// until it exits or `timeouts.exec` runs out. A non-zero exit code is not
+/// an error here; callers decide what it means. In dry-run mode the
+/// guest-exec call is printed and reported as a silent success.
+pub fn ga_exec(vm: &str, path: &str, args: &[&str]) -> Result<ExecOutput> {
+    let payload = exec_payload(path, args);
+    if agent_dry_run(vm, &payload, config::get().timeouts.agent_file)? {
+        return Ok(ExecOutput { exit_code: 0, stdout: String::new(), stderr: String::new() });
+    }
+    exec(vm, path, &payload)
+}
+
+/// `ga_exec` for commands that only read, which run even in dry-run mode.
+pub fn ga_exec_query(vm: &str, path: &str, args: &[&str]) -> Result<ExecOutput> {
+    exec(vm, path, &exec_payload(path, args))
 }
 
-/// Write a file to a VM using guest-file-open, guest-file-write, guest-file-close.
-pub fn ga_write_file(vm: &str, path: &str, content: &[u8]) -> io::Result<()> {
+fn exec_payload(path: &str, args: &[&str]) -> String {
+    serde_json::json!({
+        "execute": "guest-exec",
+        "arguments": {"path": path, "arg": args, "capture-output": true}
+    })
+    .to_string()
+}
+
+fn exec(vm: &str, path: &str, exec_payload: &str) -> Result<ExecOutput> {
     use base64::Engine;
-    
-    // 1. Open the file for writing
-    let open_payload = serde_json::json!({
-        "execute": "guest-file-open",
-        "arguments": {"path": path, "mode": "w"}
-    });
-    let open_result = virsh_qemu_agent(vm, &open_payload.to_string(), 10)?;
-    let handle = open_result
+    let cfg = config::get();
+    let timeout = cfg.timeouts.agent_file;
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(cfg.timeouts.exec);
+
+    let exec_result = virsh_qemu_agent(vm, exec_payload, timeout)?;
+    let pid = exec_result
         .get("return")
+        .and_then(|r| r.get("pid"))
         .and_then(|v| v.as_i64())
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Failed to get file handle"))?;
+        .ok_or_else(|| Error::Protocol("No pid in guest-exec reply".to_string()))?;
 
-    // 2. Write the file in chunks
-    let chunk_size = 4096;
-    for chunk in content.chunks(chunk_size) {
-        let buf_b64 = base64::engine::general_purpose::STANDARD.encode(chunk);
-        let write_payload = serde_json::json!({
-            "execute": "guest-file-write",
-            "arguments": {"handle": handle, "buf-b64": buf_b64}
-        });
-        virsh_qemu_agent(vm, &write_payload.to_string(), 10)?;
+    let status_payload = serde_json::json!({
+        "execute": "guest-exec-status",
+        "arguments": {"pid": pid}
+    });
+    loop {
+        let status = virsh_qemu_agent(vm, &status_payload.to_string(), timeout)?;
+        let ret = status
+            .get("return")
+            .ok_or_else(|| Error::Protocol("No return in guest-exec-status".to_string()))?;
+        if ret.get("exited").and_then(|v| v.as_bool()).unwrap_or(false) {
+            let decode = |key: &str| -> Result<String> {
+                let Some(b64) = ret.get(key).and_then(|v| v.as_str()) else {
+                    return Ok(String::new());
+                };
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(b64)
+                    .map_err(|e| Error::Protocol(format!("base64 decode: {}", e)))?;
+                Ok(String::from_utf8_lossy(&bytes).to_string())
+            };
+            return Ok(ExecOutput {
+                exit_code: ret.get("exitcode").and_then(|v| v.as_i64()).unwrap_or(-1),
+                stdout: decode("out-data")?,
+                stderr: decode("err-data")?,
+            });
+        }
+        if std::time::Instant::now() >= deadline {
+            return Err(Error::Timeout(format!("'{}' in '{}' still running after {}s", path, vm, cfg.timeouts.exec)));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(250));
     }
+}
 
-    // 3. Close the file
-    let close_payload = serde_json::json!({
-        "execute": "guest-file-close",
-        "arguments": {"handle": handle}
-    });
-    let _ = virsh_qemu_agent(vm, &close_payload.to_string(), 10)?;
+/// Return snapshot names for a VM from `virsh snapshot-list <vm> --name`.
+pub fn snapshot_list(vm: &str) -> Result<Vec<String>> {
+    let s = run_domain(vm, "snapshot-list", &["--name"])?;
+    Ok(s
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .map(|l| l.to_string())
+        .collect())
+}
+
+/// Return the raw `virsh snapshot-dumpxml <vm> <snapshot>` output.
+pub fn snapshot_dumpxml(vm: &str, snapshot: &str) -> Result<String> {
+    run_domain(vm, "snapshot-dumpxml", &["--snapshotname", snapshot])
+}
+
+/// Create a snapshot with `virsh snapshot-create-as`.
+/// `disk_only` produces an external disk-only snapshot; otherwise a full
+/// snapshot (disks plus memory state for running VMs) is taken.
+pub fn snapshot_create(vm: &str, name: &str, description: &str, disk_only: bool) -> Result<()> {
+    let mut args = vec!["--name", name];
+    if !description.is_empty() {
+        args.extend(["--description", description]);
+    }
+    if disk_only {
+        args.extend(["--disk-only", "--atomic"]);
+    }
+    run_domain_mut(vm, "snapshot-create-as", &args)?;
+    Ok(())
+}
+
+/// Redirect writes to the listed disks into new qcow2 overlays, given as
+/// `(target, overlay path)`, with an external disk-only snapshot that
+/// leaves no snapshot metadata behind. Every writable disk must be listed,
+/// or libvirt picks overlay names for the rest. The original images stay unchanged until `blockcommit_pivot`.
+pub fn snapshot_overlays(vm: &str, overlays: &[(String, String)]) -> Result<()> {
+    let specs: Vec<String> = overlays
+        .iter()
+        .map(|(target, file)| format!("{},snapshot=external,file={}", target, file))
+        .collect();
+    let mut args = vec!["--disk-only", "--atomic", "--no-metadata"];
+    for spec in &specs {
+        args.extend(["--diskspec", spec.as_str()]);
+    }
+    run_domain_mut(vm, "snapshot-create-as", &args)?;
+    Ok(())
+}
+
+/// Merge the active overlay of `target` back into its backing image and
+/// switch the VM back to that image, waiting until done.
+pub fn blockcommit_pivot(vm: &str, target: &str) -> Result<()> {
+    run_domain_mut(vm, "blockcommit", &["--path", target, "--active", "--pivot", "--wait"])?;
+    Ok(())
+}
+
+/// Revert a VM to the given snapshot.
+pub fn snapshot_revert(vm: &str, snapshot: &str) -> Result<()> {
+    run_domain_mut(vm, "snapshot-revert", &["--snapshotname", snapshot])?;
+    Ok(())
+}
 
+/// Delete the given snapshot (children are re-parented by libvirt).
+pub fn snapshot_delete(vm: &str, snapshot: &str) -> Result<()> {
+    run_domain_mut(vm, "snapshot-delete", &["--snapshotname", snapshot])?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_uris() {
+        let cases = [
+            ("qemu:///system", true),
+            ("qemu:///session", true),
+            ("qemu+unix:///system", true),
+            ("qemu://localhost/system", true),
+            ("qemu+ssh://root@127.0.0.1/system", true),
+            ("qemu+tcp://[::1]:16509/system", true),
+            ("qemu+ssh://hv1/system", false),
+            ("qemu+ssh://admin@hv1.example.com:2222/system?keyfile=/k", false),
+            ("qemu+tls://[2001:db8::1]/system", false),
+            ("test:///default", true),
+        ];
+        for (uri, want) in cases {
+            assert_eq!(uri_is_local(uri), want, "{}", uri);
+        }
+    }
+}
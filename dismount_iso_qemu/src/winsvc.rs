@@ -0,0 +1,238 @@
+// src/winsvc.rs
+
+//! Windows service control inside guests through guest-exec.
+//!
+//! Services are listed with PowerShell (`Get-CimInstance Win32_Service`),
+//! which also reports the start type, falling back to `sc.exe queryex` when
+//! PowerShell cannot be run. Starting, stopping and state polling use
+//! `sc.exe`, with the service name passed as its own argument.
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::dryrun;
+use crate::error::{Error, Result};
+use crate::virsh;
+
+/// sc.exe: "An instance of the service is already running."
+const ERROR_SERVICE_ALREADY_RUNNING: i64 = 1056;
+/// sc.exe: "The service has not been started."
+const ERROR_SERVICE_NOT_ACTIVE: i64 = 1062;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct Service {
+    pub name: String,
+    pub display_name: String,
+    /// Lowercase state such as "running", "stopped" or "start pending".
+    pub state: String,
+    /// "auto", "manual", "disabled", ... or "-" when unknown.
+    pub start_type: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Start,
+    Stop,
+    Restart,
+}
+
+impl Action {
+    /// "start", "stop" or "restart", as used in audit actions.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Start => "start",
+            Action::Stop => "stop",
+            Action::Restart => "restart",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CimService {
+    name: String,
+    display_name: Option<String>,
+    state: Option<String>,
+    start_mode: Option<String>,
+}
+
+/// List every service in a Windows guest, sorted by name.
+pub fn list(vm: &str) -> Result<Vec<Service>> {
+    let mut services = match list_powershell(vm) {
+        Ok(s) => s,
+        Err(Error::Virsh { .. } | Error::GuestCommand { .. }) => list_sc(vm)?,
+        Err(e) => return Err(e),
+    };
+    services.sort_by_key(|s| s.name.to_lowercase());
+    Ok(services)
+}
+
+fn list_powershell(vm: &str) -> Result<Vec<Service>> {
+    let script = "Get-CimInstance Win32_Service | Select-Object Name,DisplayName,State,StartMode | ConvertTo-Json -Compress";
+    let out = run_checked(vm, "powershell.exe", &["-NoProfile", "-NonInteractive", "-Command", script])?;
+    let json: Value = serde_json::from_str(out.trim())
+        .map_err(|e| Error::Protocol(format!("service list json: {}", e)))?;
+    // ConvertTo-Json emits a bare object for a single service.
+    let items = match json {
+        Value::Array(items) => items,
+        other => vec![other],
+    };
+    items
+        .into_iter()
+        .map(|v| {
+            let s: CimService = serde_json::from_value(v)
+                .map_err(|e| Error::Protocol(format!("service list json: {}", e)))?;
+            Ok(Service {
+                display_name: s.display_name.unwrap_or_default(),
+                state: s.state.map(|st| st.to_lowercase()).unwrap_or_else(|| "-".to_string()),
+                start_type: s.start_mode.map(|m| m.to_lowercase()).unwrap_or_else(|| "-".to_string()),
+                name: s.name,
+            })
+        })
+        .collect()
+}
+
+fn list_sc(vm: &str) -> Result<Vec<Service>> {
+    let out = run_checked(vm, "sc.exe", &["queryex", "type=", "service", "state=", "all"])?;
+    let mut services: Vec<Service> = Vec::new();
+    for line in out.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix("SERVICE_NAME:") {
+            services.push(Service {
+                name: name.trim().to_string(),
+                display_name: String::new(),
+                state: "-".to_string(),
+                start_type: "-".to_string(),
+            });
+        } else if let Some(last) = services.last_mut() {
+            if let Some(display) = line.strip_prefix("DISPLAY_NAME:") {
+                last.display_name = display.trim().to_string();
+            } else if line.starts_with("STATE") {
+                last.state = parse_sc_state(line).unwrap_or_else(|| "-".to_string());
+            }
+        }
+    }
+    Ok(services)
+}
+
+/// Parse `STATE              : 4  RUNNING` into "running".
+fn parse_sc_state(line: &str) -> Option<String> {
+    let (_, value) = line.split_once(':')?;
+    let word = value.split_whitespace().nth(1)?;
+    Some(word.replace('_', " ").to_lowercase())
+}
+
+/// Current state of one service.
+pub fn state(vm: &str, name: &str) -> Result<String> {
+    let out = run_checked(vm, "sc.exe", &["query", name])?;
+    out.lines()
+        .map(str::trim)
+        .find(|l| l.starts_with("STATE"))
+        .and_then(parse_sc_state)
+        .ok_or_else(|| Error::Protocol(format!("no STATE in 'sc query {}' output", name)))
+}
+
+/// Ask the service control manager to start a service. Already running is fine.
+pub fn start(vm: &str, name: &str) -> Result<()> {
+    send(vm, "start", name, ERROR_SERVICE_ALREADY_RUNNING)
+}
+
+/// Ask the service control manager to stop a service. Already stopped is fine.
+pub fn stop(vm: &str, name: &str) -> Result<()> {
+    send(vm, "stop", name, ERROR_SERVICE_NOT_ACTIVE)
+}
+
+fn send(vm: &str, verb: &str, name: &str, benign: i64) -> Result<()> {
+    let out = virsh::ga_exec(vm, "sc.exe", &[verb, name])?;
+    if out.exit_code == 0 || out.exit_code == benign {
+        Ok(())
+    } else {
+        Err(command_error(vm, &format!("sc {} {}", verb, name), &out))
+    }
+}
+
+/// Start, stop or restart a service and wait until it settles. A restart
+/// stops the service and waits for it to stop before starting it again.
+/// Each wait is bounded by `wait`.
+pub fn control(vm: &str, name: &str, action: Action, wait: Duration) -> Result<()> {
+    if action != Action::Start {
+        stop(vm, name)?;
+        wait_for_state(vm, name, "stopped", wait)?;
+    }
+    if action != Action::Stop {
+        start(vm, name)?;
+        wait_for_state(vm, name, "running", wait)?;
+    }
+    Ok(())
+}
+
+/// Poll until the service reports `target` ("running" or "stopped"),
+/// giving up after `wait`. In dry-run mode nothing was sent, so there is
+/// nothing to wait for.
+pub fn wait_for_state(vm: &str, name: &str, target: &str, wait: Duration) -> Result<()> {
+    if dryrun::enabled() {
+        return Ok(());
+    }
+    let started = Instant::now();
+    loop {
+        let current = state(vm, name)?;
+        if current == target {
+            return Ok(());
+        }
+        if started.elapsed() >= wait {
+            return Err(Error::Timeout(format!(
+                "service '{}' in '{}' is '{}' after {}s, expected '{}'",
+                name,
+                vm,
+                current,
+                wait.as_secs(),
+                target
+            )));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Run a query program and return its stdout, turning a non-zero exit into an error.
+fn run_checked(vm: &str, path: &str, args: &[&str]) -> Result<String> {
+    let out = virsh::ga_exec_query(vm, path, args)?;
+    if out.exit_code != 0 {
+        return Err(command_error(vm, path, &out));
+    }
+    Ok(out.stdout)
+}
+
+fn command_error(vm: &str, command: &str, out: &virsh::ExecOutput) -> Error {
+    // sc.exe reports failures on stdout; keep the last meaningful line.
+    let detail = [out.stderr.as_str(), out.stdout.as_str()]
+        .iter()
+        .flat_map(|s| s.lines())
+        .map(str::trim)
+        .rfind(|l| !l.is_empty())
+        .unwrap_or("")
+        .to_string();
+    Error::GuestCommand { vm: vm.to_string(), command: command.to_string(), code: out.exit_code, detail }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sc_state() {
+        let cases = [
+            ("STATE              : 4  RUNNING", Some("running")),
+            ("STATE              : 1  STOPPED ", Some("stopped")),
+            ("STATE              : 2  START_PENDING", Some("start pending")),
+            ("STATE              : 4", None),
+            ("STATE", None),
+        ];
+        for (line, want) in cases {
+            assert_eq!(parse_sc_state(line).as_deref(), want, "{}", line);
+        }
+    }
+}